@@ -1,69 +1,564 @@
-// TODO: Fix send_to_server
-use chat_shared::{Config, Message, User, message::MessageKind};
-use std::{io, sync::Arc, thread::sleep, time::Duration};
+use chat_shared::{
+    Client, Command, Config, FrameError, IgnoreMode, Message, ParseError, User,
+    frame::{self, FrameReader},
+    message::{Channel, Destination, MessageKind},
+    tls,
+};
+use chrono::{
+    Local,
+    format::{Item, StrftimeItems},
+};
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{
-    io::ErrorKind,
-    sync::mpsc::{Receiver, Sender},
+    io::AsyncWriteExt,
+    net::TcpStream,
+    sync::mpsc::{Receiver, Sender, error::TrySendError},
+    sync::{Mutex, Notify},
+    time::timeout,
 };
+use tokio_rustls::{TlsConnector, rustls::pki_types::ServerName};
+
+mod style;
+
+// How many received messages we remember so replies can quote them
+const RECENT_MESSAGES: usize = 100;
+
+// How times are shown when the config doesn't say
+const DEFAULT_TIME_FORMAT: &str = "%H:%M:%S";
+
+// How long to wait on a slow network before giving up on a message
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Where the connection to the server stands, which decides what becomes
+// of what the user types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    // Making the first connection. Anything typed waits until it is up
+    Connecting,
+    // Anything typed goes straight out
+    Connected,
+    // The connection was lost or dropped and we are making a new one.
+    // Anything typed is queued and sent once we are back
+    Reconnecting,
+    // We have stopped trying, so nothing typed can be sent
+    Disconnected,
+}
+
+// Client side settings that the user can change while chatting. The
+// nickname and channels are kept here so they outlive a connection
+pub struct Settings {
+    pub show_timestamps: Mutex<bool>,
+    pub ignored: Mutex<HashSet<String>>,
+    pub nick_name: Mutex<Option<String>>,
+    pub channels: Mutex<Vec<String>>,
+    // The author id the server gave this connection, once it has said
+    own_id: Mutex<Option<String>>,
+    // The token for the server session of the current connection
+    session_token: Mutex<Option<String>>,
+    echo_own_messages: bool,
+    ignore_list_path: Option<PathBuf>,
+    ignore_mode: IgnoreMode,
+    time_format: String,
+    recent: Mutex<VecDeque<(u64, String)>>,
+    reconnect: Notify,
+    // Set once we have sent :quit, so the connection closing isn't
+    // mistaken for losing the server
+    quitting: Mutex<bool>,
+    // What commands start with. The server has to be using the same one
+    prefix: char,
+    // Shown when we are ready for input, unless nobody is there to see it
+    prompt: Option<String>,
+    state: Mutex<ConnectionState>,
+}
+
+impl Settings {
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config, io::stdin().is_terminal())
+    }
+
+    // As from_config, but told whether someone is typing at a terminal,
+    // which is the only time the prompt is worth showing
+    pub fn new(config: &Config, interactive: bool) -> Self {
+        // A missing ignore list just means nobody has been ignored yet
+        let ignored = config
+            .ignore_list_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+
+        // chrono panics when asked to print with a bad format, so check it now
+        let time_format = match &config.time_format {
+            Some(format) if StrftimeItems::new(format).any(|item| item == Item::Error) => {
+                eprintln!("Ignoring invalid time_format {format:?}");
+                DEFAULT_TIME_FORMAT.to_string()
+            }
+            Some(format) => format.to_string(),
+            None => DEFAULT_TIME_FORMAT.to_string(),
+        };
+
+        Self {
+            show_timestamps: Mutex::new(config.show_timestamps),
+            ignored: Mutex::new(ignored),
+            nick_name: Mutex::new(None),
+            channels: Mutex::new(Vec::new()),
+            own_id: Mutex::new(None),
+            session_token: Mutex::new(None),
+            echo_own_messages: config.echo_own_messages,
+            ignore_list_path: config.ignore_list_path.clone(),
+            ignore_mode: config.ignore_mode,
+            time_format,
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_MESSAGES)),
+            reconnect: Notify::new(),
+            quitting: Mutex::new(false),
+            prefix: config.prefix,
+            prompt: (!config.prompt.is_empty() && interactive).then(|| config.prompt.clone()),
+            state: Mutex::new(ConnectionState::Connecting),
+        }
+    }
+
+    pub async fn state(&self) -> ConnectionState {
+        *self.state.lock().await
+    }
+
+    pub async fn set_state(&self, state: ConnectionState) {
+        *self.state.lock().await = state;
+    }
+
+    // Let the user know we are ready for them to type
+    pub fn show_prompt(&self) {
+        self.show_prompt_to(&mut io::stdout());
+    }
+
+    // As show_prompt, but written to out
+    pub fn show_prompt_to<W: Write>(&self, out: &mut W) {
+        if let Some(prompt) = &self.prompt {
+            let _ = write!(out, "{prompt}");
+            let _ = out.flush();
+        }
+    }
+
+    // Ask the connection to be dropped and made again
+    pub fn request_reconnect(&self) {
+        self.reconnect.notify_one();
+    }
+
+    // Keep track of what we have told the server about ourselves, so it
+    // can be told again after reconnecting. A nickname, a channel or an
+    // ignore the server enforces can be refused, so those are only kept
+    // once the server acks them
+    async fn track(&self, command: &Command) {
+        match command {
+            Command::Name(None) => *self.nick_name.lock().await = None,
+            Command::Leave(channel) => self.channels.lock().await.retain(|c| c != channel),
+            Command::Quit => *self.quitting.lock().await = true,
+            _ => (),
+        }
+    }
+
+    // Chat goes to the channel joined most recently, or to everyone when
+    // we aren't in one
+    async fn destination(&self) -> Destination {
+        match self.channels.lock().await.last() {
+            Some(channel) => Destination::Channel(Channel::new(channel.to_string())),
+            None => Destination::Global,
+        }
+    }
+
+    // Remember how a message was shown so a later reply can quote it
+    async fn remember(&self, id: u64, line: String) {
+        let mut recent = self.recent.lock().await;
+        if recent.len() == RECENT_MESSAGES {
+            recent.pop_front();
+        }
+        recent.push_back((id, line));
+    }
+
+    // Find how a message we've seen was shown, if we still remember it
+    pub async fn recall(&self, id: u64) -> Option<String> {
+        let recent = self.recent.lock().await;
+        recent
+            .iter()
+            .find(|(seen, _)| *seen == id)
+            .map(|(_, line)| line.clone())
+    }
+
+    // Write the ignore list out, one name per line, if a path is configured
+    async fn save_ignored(&self) {
+        if let Some(path) = &self.ignore_list_path {
+            let ignored = self.ignored.lock().await;
+            let contents: Vec<&str> = ignored.iter().map(String::as_str).collect();
+            if let Err(e) = fs::write(path, contents.join("\n")) {
+                eprintln!("Could not save the ignore list: {e}");
+            }
+        }
+    }
+}
+
+// Handle commands that only change how the client behaves. Returns true
+// when the command was handled here and should not be sent to the server
+pub async fn handle_local_command(command: &Command, settings: &Settings) -> bool {
+    match command {
+        Command::Timestamps(on) => {
+            *settings.show_timestamps.lock().await = *on;
+            true
+        }
+        // When the server enforces ignores it answers these itself, and
+        // the ignore list only changes once it acks them
+        Command::Ignore(_) | Command::Unignore(_) if settings.ignore_mode == IgnoreMode::Server => {
+            false
+        }
+        Command::Ignore(None) => {
+            let ignored = settings.ignored.lock().await;
+            let names: Vec<&str> = ignored.iter().map(String::as_str).collect();
+            println!("-->Ignoring: {}", names.join(", "));
+            true
+        }
+        Command::Ignore(Some(name)) => {
+            settings.ignored.lock().await.insert(name.to_string());
+            settings.save_ignored().await;
+            println!("-->Ignoring {name}");
+            true
+        }
+        Command::Unignore(name) => {
+            settings.ignored.lock().await.remove(name);
+            settings.save_ignored().await;
+            println!("-->No longer ignoring {name}");
+            true
+        }
+        Command::Reconnect => {
+            settings.request_reconnect();
+            true
+        }
+        Command::Prefix => {
+            println!("-->Commands start with {}", settings.prefix);
+            true
+        }
+        Command::Commands => {
+            println!("-->{}", Command::local_help(settings.prefix));
+            true
+        }
+        _ => false,
+    }
+}
 
-// Helper function to translate the buffer to utf8 and print to the console
-pub async fn get_and_print_message(buffer: Vec<u8>, user: &Arc<User>) {
-    // Translate buffer to a vec
-    let message: Vec<u8> = buffer.into_iter().filter(|n| *n != 0).collect();
-    // Translate the vec to a utf8 string
-    let message = String::from_utf8(message).expect("Invalid utf8 message");
-    // If the message is not empty and is not sent by us, print it
-    let display_name = user.get_display_name().await;
-    if !message.is_empty() && !message.starts_with(format!("{}: ", display_name).as_str()) {
-        println!("-->{}", message);
+// Whether we sent the message. Compare author ids when the server has told
+// us ours, and fall back to our display name for servers that don't
+async fn is_own_message(
+    author_id: Option<&str>,
+    author: &str,
+    user: &User,
+    settings: &Settings,
+) -> bool {
+    if let (Some(author_id), Some(own_id)) = (author_id, settings.own_id.lock().await.as_ref()) {
+        return author_id == own_id;
+    }
+    match settings.nick_name.lock().await.as_ref() {
+        Some(name) => author == name,
+        None => author == user.client.address,
+    }
+}
+
+// Turn a message from the server into the line shown to the user.
+// Returns None when there is nothing to show
+pub async fn format_message(message: Message, user: &User, settings: &Settings) -> Option<String> {
+    let content = message.as_string();
+    let line = match message.kind {
+        // Acks only confirm things, there is nothing to show. They do
+        // tell us which author id is ours, and the one welcoming us to a
+        // new connection carries its session token. The server also acks
+        // the nickname it gave us and each channel we are in. One addressed
+        // to another user confirms we are ignoring them, or no longer are
+        MessageKind::Ack if let Destination::Direct(_) = message.channel => {
+            if let Some(name) = message.author {
+                let mut ignored = settings.ignored.lock().await;
+                match content.as_str() {
+                    "ignore" => ignored.insert(name),
+                    _ => ignored.remove(&name),
+                };
+            }
+            settings.save_ignored().await;
+            return None;
+        }
+        MessageKind::Ack => {
+            if let Some(id) = message.author_id {
+                *settings.own_id.lock().await = Some(id);
+            }
+            if message.id.is_none() && !content.is_empty() {
+                *settings.session_token.lock().await = Some(content);
+            }
+            if let Some(name) = message.author {
+                *settings.nick_name.lock().await = Some(name);
+            }
+            if let Destination::Channel(channel) = message.channel {
+                let mut channels = settings.channels.lock().await;
+                let name = channel.display_name().to_string();
+                if !channels.contains(&name) {
+                    channels.push(name);
+                }
+            }
+            return None;
+        }
+        // Keepalives are answered before we get here, and kinds from a
+        // newer server mean nothing to us
+        MessageKind::Ping | MessageKind::Pong | MessageKind::Unknown => return None,
+        MessageKind::ServerBroadcast => style::system(&content),
+        _ => {
+            let author = message.author.unwrap_or_default();
+            let private = matches!(message.channel, Destination::Direct(_));
+            let name = style::author(&author);
+            let said = match message.kind {
+                MessageKind::Action => format!("* {} {}", name, content),
+                _ => format!("{}: {}", name, content),
+            };
+            let line = match message.id {
+                Some(id) => format!("#{} {}", id, said),
+                None => said,
+            };
+            let line = match &message.channel {
+                Destination::Global => line,
+                Destination::Channel(channel) => format!("[{}] {}", channel.display_name(), line),
+                Destination::Direct(_) => format!("[private] {}", line),
+            };
+            if let Some(id) = message.id {
+                settings.remember(id, line.clone()).await;
+            }
+
+            // If the message is empty, sent by us or sent by someone we
+            // are ignoring, don't show it. Our own messages are only shown
+            // when asked for, except private ones, which are always echoed
+            // back so we can see they were delivered
+            let own = is_own_message(message.author_id.as_deref(), &author, user, settings).await;
+            if content.is_empty()
+                || (own && !private && !settings.echo_own_messages)
+                || settings.ignored.lock().await.contains(&author)
+            {
+                return None;
+            }
+
+            // Show a reply indented under a quote of the message it replies to
+            match message.in_reply_to {
+                Some(parent) => {
+                    let quoted = settings
+                        .recall(parent)
+                        .await
+                        .unwrap_or_else(|| format!("#{}", parent));
+                    format!("> {}\n-->  {}", quoted, line)
+                }
+                None => line,
+            }
+        }
+    };
+
+    match *settings.show_timestamps.lock().await {
+        true => {
+            let time = message.timestamp.with_timezone(&Local);
+            let time = format!("[{}]", time.format(&settings.time_format));
+            Some(format!("-->{} {}", style::time(&time), line))
+        }
+        false => Some(format!("-->{}", line)),
     }
 }
 
+// Helper function to parse the message in a frame and print it to the console
+pub async fn get_and_print_message(
+    config: &Config,
+    payload: Vec<u8>,
+    user: &Arc<User>,
+    settings: &Settings,
+) {
+    // Printing the line moves us past the prompt, so put it back
+    if let Some(line) = receive_message(config, payload, user, settings).await {
+        println!("{}", line);
+        settings.show_prompt();
+    }
+}
+
+// Parse the message in a frame and deal with it, returning the line to
+// show for it, if any. Pings are answered here and never shown
+pub async fn receive_message(
+    config: &Config,
+    payload: Vec<u8>,
+    user: &Arc<User>,
+    settings: &Settings,
+) -> Option<String> {
+    let message = match frame::decode(&payload) {
+        Ok(message) => message,
+        Err(e) => {
+            eprintln!("Could not read message from server: {e}");
+            return None;
+        }
+    };
+
+    // Answer the server's keepalive straight away, on the connection it
+    // came in on. Going through the send queue would hold it up behind
+    // whatever the user has typed, or until after a reconnect
+    if message.kind == MessageKind::Ping {
+        let pong = Message::from_string(Arc::clone(&user.client), String::new(), MessageKind::Pong);
+        if let Err(e) = write_message(config, user, &pong).await {
+            eprintln!("Could not answer the server's ping: {e}");
+        }
+        return None;
+    }
+
+    format_message(message, user, settings).await
+}
+
+// Hand a message over to be sent, telling the user when the network is too
+// slow to keep up. Returns false once the connection is gone for good
+pub async fn queue_message(tx: &Sender<Message>, message: Message, settings: &Settings) -> bool {
+    queue_message_to(&mut io::stdout(), tx, message, settings).await
+}
+
+// As queue_message, but with what the user is told written to out
+pub async fn queue_message_to<W: Write>(
+    out: &mut W,
+    tx: &Sender<Message>,
+    message: Message,
+    settings: &Settings,
+) -> bool {
+    match settings.state().await {
+        ConnectionState::Disconnected => {
+            let _ = writeln!(out, "-->Disconnected, message not sent");
+            return false;
+        }
+        // There's no point waiting on a queue nobody is reading from
+        ConnectionState::Reconnecting => {
+            match tx.try_send(message) {
+                Ok(()) => {
+                    let _ = writeln!(out, "-->Disconnected, message queued until we are back");
+                }
+                Err(TrySendError::Full(_)) => {
+                    let _ = writeln!(
+                        out,
+                        "-->Disconnected and the queue is full, message not sent"
+                    );
+                }
+                Err(TrySendError::Closed(_)) => return false,
+            }
+            return true;
+        }
+        ConnectionState::Connecting | ConnectionState::Connected => (),
+    }
+
+    let message = match tx.try_send(message) {
+        Ok(()) => return true,
+        Err(TrySendError::Closed(_)) => return false,
+        Err(TrySendError::Full(message)) => message,
+    };
+
+    // Said straight away, as the wait below may be a long one
+    let _ = writeln!(out, "-->sending... (network slow)");
+    let _ = out.flush();
+    match timeout(SEND_TIMEOUT, tx.send(message)).await {
+        Ok(Ok(())) => true,
+        Ok(Err(_)) => false,
+        Err(_) => {
+            let _ = writeln!(out, "-->failed to send");
+            true
+        }
+    }
+}
+
+// Turn a line the user typed into what is sent to the server. Lines
+// starting with the configured prefix are commands, anything else is chat.
+// Returns None when there is nothing to send, as the command was handled
+// here or couldn't be parsed
+pub async fn message_from_input(
+    input: &str,
+    client: &Arc<Client>,
+    settings: &Settings,
+) -> Option<Message> {
+    let (message_kind, text) = if input.starts_with(settings.prefix) {
+        // Catch mistakes here rather than waiting on the server to refuse them
+        let command = match Command::parse(input, settings.prefix) {
+            Ok(command) => command,
+            Err(e @ ParseError::UnknownCommand(_)) => {
+                println!("-->{e}, type {}help to see the commands", settings.prefix);
+                return None;
+            }
+            Err(e) => {
+                println!("-->{e}");
+                return None;
+            }
+        };
+        if handle_local_command(&command, settings).await {
+            return None;
+        }
+
+        match command {
+            // Actions go where messages go, they are only shown differently
+            Command::Me(action) => (MessageKind::Action, action),
+            command => {
+                settings.track(&command).await;
+                (MessageKind::Command, input.to_string())
+            }
+        }
+    } else {
+        (MessageKind::Message, input.to_string())
+    };
+
+    let mut message = Message::from_string(Arc::clone(client), text, message_kind);
+    if message_kind != MessageKind::Command {
+        message.channel = settings.destination().await;
+    }
+    Some(message)
+}
+
 // This function handles getting information from
 // stdin and sending it to the server
-pub async fn read_and_send(tx: Sender<Message>, user: Arc<User>) {
-    // Create a buffer to control our loop and to collect
-    // the message to send
-    let mut buff = String::new();
-
+pub async fn read_and_send(tx: Sender<Message>, client: Arc<Client>, settings: Arc<Settings>) {
     // Loop until we choose to quit
-    while buff.trim() != ":quit" {
-        buff = String::new();
-        io::stdin()
+    while !*settings.quitting.lock().await {
+        settings.show_prompt();
+        let mut buff = String::new();
+        let read = io::stdin()
             .read_line(&mut buff)
             .expect("reading from stdin failed");
 
-        let buff = buff.trim().to_string();
-        let message_kind: MessageKind;
-        if buff.starts_with(':') {
-            message_kind = MessageKind::Command;
-            if buff.contains(":name ") {
-                let mut nickname = user.nick_name.lock().await;
-                *nickname = Some(buff.split_whitespace().nth(1).unwrap().to_string());
-            }
-        } else {
-            message_kind = MessageKind::Message;
+        // Nothing more will ever be typed, so stop as if we had quit
+        if read == 0 {
+            break;
         }
 
-        // Send to our receiver thread
-        let message = Message::from_string(user.client.clone(), buff, message_kind);
+        let Some(message) = message_from_input(buff.trim(), &client, &settings).await else {
+            continue;
+        };
 
-        tx.send(message).await.expect("Couldn't send the message");
+        // The connection is gone for good, so there's nobody to send to
+        if !queue_message(&tx, message, &settings).await {
+            break;
+        }
     }
-
-    sleep(Duration::new(0, 100));
+    // Returning drops tx, which tells send_to_server to finish up once
+    // whatever is still queued, our :quit included, has been sent
 }
 
-pub async fn get_message_from_server(config_handle: Arc<Config>, user: Arc<User>) {
-    let socket = user.socket.as_ref().unwrap();
-    while socket.readable().await.is_ok() {
-        let mut buffer = vec![0; config_handle.msg_size as usize];
-        match socket.try_read(&mut buffer) {
-            Ok(0) => continue,
-            Ok(_) => get_and_print_message(buffer, &user).await,
-            Err(ref err) if err.kind() == ErrorKind::WouldBlock => continue,
-            Err(_) => {
-                eprintln!("Connection with the server was severed");
+pub async fn get_message_from_server(
+    config_handle: Arc<Config>,
+    user: Arc<User>,
+    settings: Arc<Settings>,
+) {
+    let Some(reader) = user.reader.lock().await.take() else {
+        return;
+    };
+    let mut reader = FrameReader::new(reader, config_handle.max_frame_size);
+    loop {
+        match reader.next_frame_bytes().await {
+            Ok(payload) => get_and_print_message(&config_handle, payload, &user, &settings).await,
+            Err(FrameError::Closed) => {
+                eprintln!("Connection with the server was closed");
+                break;
+            }
+            Err(e) => {
+                eprintln!("Connection with the server was severed: {e}");
                 break;
             }
         }
@@ -71,17 +566,219 @@ pub async fn get_message_from_server(config_handle: Arc<Config>, user: Arc<User>
 }
 
 // check the receiver and if we have data, try to write it to the
-// stream
-// TODO: Send the message struct instead of the content array
-pub async fn send_to_server(config: Arc<Config>, mut rx: Receiver<Message>, user: Arc<User>) {
-    while let Some(message) = rx.recv().await {
-        if let Ok(buff) = ron::to_string(&message) {
-            let mut buff = buff.into_bytes();
-            buff.resize(config.msg_size as usize, 0);
-            let socket = user.socket.as_ref().unwrap();
-
-            socket.writable().await.expect("Could not check writable");
-            socket.try_write(&buff).expect("writing to socket failed");
+// stream. Returns once the receiver is closed and everything in it has
+// been sent and the connection shut, or with the error if writing to
+// the server fails
+pub async fn send_to_server(
+    config: Arc<Config>,
+    rx: &mut Receiver<Message>,
+    user: Arc<User>,
+) -> Result<(), FrameError> {
+    while let Some(mut message) = rx.recv().await {
+        // Messages may have been queued before this connection existed
+        message.address = user.client.address.to_string();
+        match write_message(&config, &user, &message).await {
+            Ok(()) => (),
+            // Nothing was written, so the connection is still good
+            Err(e @ FrameError::TooLarge { .. }) => println!("-->Not sent: {e}"),
+            Err(e) => {
+                eprintln!("Could not send to the server: {e}");
+                return Err(e);
+            }
+        }
+    }
+    // Nothing more is coming, so let the server see the end of our side
+    // rather than having the connection cut from under it
+    if let Some(writer) = user.writer.lock().await.as_mut() {
+        writer.shutdown().await?;
+    }
+    Ok(())
+}
+
+// Serialize a message and write it to the server
+async fn write_message(config: &Config, user: &User, message: &Message) -> Result<(), FrameError> {
+    // Only copy the message when there is a checksum to add to it
+    let sealed = config.frame_checksums.then(|| {
+        let mut sealed = message.clone();
+        sealed.seal();
+        sealed
+    });
+    let message = sealed.as_ref().unwrap_or(message);
+    let mut writer = user.writer.lock().await;
+    match writer.as_mut() {
+        Some(writer) => frame::write_frame(writer, message, config.max_frame_size).await,
+        None => Err(FrameError::Closed),
+    }
+}
+
+// Tell a fresh connection which session we are resuming, our nickname,
+// channels and, when the server enforces them, who we are ignoring
+async fn restore_session(config: &Config, user: &User, settings: &Settings) {
+    let prefix = settings.prefix;
+    let mut commands = Vec::new();
+    // The server hasn't told this connection its own token yet, so this is
+    // still the one from the connection that dropped
+    if let Some(token) = settings.session_token.lock().await.as_ref() {
+        commands.push(format!("{prefix}resume {token}"));
+    }
+    if let Some(name) = settings.nick_name.lock().await.as_ref() {
+        commands.push(format!("{prefix}name {name}"));
+    }
+    for channel in settings.channels.lock().await.iter() {
+        commands.push(format!("{prefix}join {channel}"));
+    }
+    if settings.ignore_mode == IgnoreMode::Server {
+        for name in settings.ignored.lock().await.iter() {
+            commands.push(format!("{prefix}ignore {name}"));
+        }
+    }
+
+    for command in commands {
+        let message = Message::from_string(Arc::clone(&user.client), command, MessageKind::Command);
+        if let Err(e) = write_message(config, user, &message).await {
+            eprintln!("Could not restore the session: {e}");
+            return;
+        }
+    }
+}
+
+// Open a connection to the server, running the TLS handshake over it
+// when TLS is set up
+async fn connect(
+    address: &str,
+    tls: Option<&(TlsConnector, ServerName<'static>)>,
+) -> io::Result<User> {
+    let socket = TcpStream::connect(address).await?;
+    let Some((connector, server_name)) = tls else {
+        return Ok(User::from(socket, None));
+    };
+    let local = socket.local_addr()?.to_string();
+    let stream = connector.connect(server_name.clone(), socket).await?;
+    Ok(User::over(Box::new(stream), local))
+}
+
+// How a connection to the server came to an end
+enum Ended {
+    // The user asked for a fresh connection with :reconnect
+    Reconnect,
+    // The server went away or the connection failed
+    Lost,
+    // The user quit, or there is nothing more to send
+    Done,
+}
+
+// Connect to the server and serve the connection until the user quits. A
+// :reconnect drops the connection and makes a new one, restoring the
+// session on it. So does losing the server, after waiting a while, and
+// waiting longer after every try that fails. Anything typed in the
+// meantime waits in rx and is sent once we are back
+pub async fn run_client(
+    config: Arc<Config>,
+    address: String,
+    rx: Receiver<Message>,
+    settings: Arc<Settings>,
+) {
+    // Set up TLS once, so a mistake in it is reported before connecting
+    let tls = match &config.tls {
+        Some(tls_config) => {
+            // Check the certificate against the host name we looked up,
+            // if there was one, rather than the address it resolved to
+            let host = config.host_name.as_deref().unwrap_or_else(|| {
+                address
+                    .rsplit_once(':')
+                    .map_or(address.as_str(), |(host, _)| host)
+                    .trim_matches(['[', ']'])
+            });
+            let secured = tls::connector(tls_config)
+                .and_then(|connector| Ok((connector, tls::server_name(tls_config, host)?)));
+            match secured {
+                Ok(secured) => Some(secured),
+                Err(e) => {
+                    eprintln!("{e}");
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let target = address.clone();
+    let connector = move || {
+        let address = target.clone();
+        let tls = tls.clone();
+        async move { connect(&address, tls.as_ref()).await }
+    };
+    run_client_with(config, address, connector, rx, settings).await;
+}
+
+// As run_client, but each connection is made by calling connect rather
+// than dialling the address, which is only used to tell the user where
+// they are connected to. This lets a client run over any transport, such
+// as an in-memory one
+pub async fn run_client_with<C, F>(
+    config: Arc<Config>,
+    address: String,
+    mut connect: C,
+    mut rx: Receiver<Message>,
+    settings: Arc<Settings>,
+) where
+    C: FnMut() -> F,
+    F: Future<Output = io::Result<User>>,
+{
+    let base = Duration::from_millis(config.reconnect_base_ms);
+    let max = Duration::from_millis(config.reconnect_max_ms);
+    // A server that was never there is most likely the wrong address, so
+    // only keep trying once we have been connected
+    let mut connected = false;
+    let mut delay = None;
+
+    loop {
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+        let user = match connect().await {
+            Ok(user) => Arc::new(user),
+            Err(e) if !connected => {
+                eprintln!("Could not connect to {address}: {e}");
+                settings.set_state(ConnectionState::Disconnected).await;
+                return;
+            }
+            Err(e) => {
+                let next = delay.map_or(base, |delay: Duration| (delay * 2).min(max));
+                println!("-->Could not reconnect: {e}, trying again in {next:?}");
+                delay = Some(next);
+                continue;
+            }
+        };
+        if std::mem::replace(&mut connected, true) {
+            println!("-->Connected to {address}");
+        }
+        delay = None;
+        restore_session(&config, &user, &settings).await;
+        settings.set_state(ConnectionState::Connected).await;
+
+        let ended = tokio::select! {
+            sent = send_to_server(Arc::clone(&config), &mut rx, Arc::clone(&user)) => match sent {
+                Ok(()) => Ended::Done,
+                Err(_) => Ended::Lost,
+            },
+            _ = get_message_from_server(Arc::clone(&config), Arc::clone(&user), Arc::clone(&settings)) => Ended::Lost,
+            _ = settings.reconnect.notified() => Ended::Reconnect,
+        };
+        match ended {
+            Ended::Reconnect => {
+                settings.set_state(ConnectionState::Reconnecting).await;
+                println!("-->Reconnecting to {address}");
+            }
+            Ended::Lost if !*settings.quitting.lock().await => {
+                settings.set_state(ConnectionState::Reconnecting).await;
+                println!("-->Lost the server, queuing messages and reconnecting in {base:?}");
+                delay = Some(base);
+            }
+            Ended::Lost | Ended::Done => {
+                settings.set_state(ConnectionState::Disconnected).await;
+                return;
+            }
         }
     }
 }
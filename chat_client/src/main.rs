@@ -1,16 +1,37 @@
 use chat_client::*;
-use chat_shared::{Config, Message, User};
-use std::{env::args, process, sync::Arc};
-use tokio::{net::TcpStream, spawn, sync::mpsc};
+use chat_shared::{
+    Client, Config, Message,
+    cli::{self, CliArgs, Subcommand},
+};
+use std::{env::args, future::pending, process, sync::Arc};
+use tokio::{spawn, sync::mpsc};
 
 #[tokio::main]
 async fn main() {
-    // Get the config file path from the command line arguments.
-    // Then match the result and either load the config from the file the path supplied or request default.
-    let config = match chat_shared::get_config_path(args()) {
-        Some(config) => Config::from_path(Some(config.as_ref())),
-        None => Config::from_path(None),
-    };
+    // Read the command line once, up front
+    let cli = CliArgs::parse(args()).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(1);
+    });
+
+    match cli.subcommand {
+        Some(Subcommand::Help) => {
+            println!("{}", cli::usage("chat_client"));
+            return;
+        }
+        Some(Subcommand::Version) => {
+            println!("chat_client {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Some(Subcommand::Selftest) => {
+            eprintln!("selftest is only available on chat_server");
+            process::exit(1);
+        }
+        None => (),
+    }
+
+    // Load the config from the path supplied on the command line or request default.
+    let config = Config::from_path(cli.config_path.as_deref());
 
     // If the config is not valid, print the error and exit.
     let config = match config {
@@ -21,38 +42,43 @@ async fn main() {
         }
     };
 
-    // If the config is valid, get the ip address and port from the config.
-    // If the ip address is not valid, print the error and exit.
-    let host_ip = config.get_ip().unwrap_or_else(|_| {
-        eprintln!("Invalid IP address");
-        process::exit(1);
-    });
+    // If the config is valid, work out the address to connect to from it,
+    // looking up the host name if one is set. If that fails, say why and exit.
+    let address = config
+        .server_address()
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            process::exit(1);
+        })
+        .to_string();
 
-    // Create the address string to connect to.
-    let address = format!("{}:{}", host_ip, config.host_port).replace('"', "");
+    // Create a shared config and settings object to pass to our threads
+    let settings = Arc::new(Settings::from_config(&config));
 
-    // Create a shared config and user object to pass to our threads
+    // A nickname from the command line is sent as soon as we connect
+    *settings.nick_name.lock().await = cli.nick;
     let config = Arc::new(config);
-    // Open our stream or die trying
-    let client = TcpStream::connect(address)
-        .await
-        .expect("Stream failed to connect");
-
-    // Create a shared user object to pass to our threads
-    let user = Arc::new(User::from(client, None));
 
-    // Open our thread communication channels
+    // Messages typed before a connection is up wait in the channel. The
+    // address of the client is filled in per connection when sending
+    let client = Arc::new(Client::new(String::new()));
     let (tx, rx) = mpsc::channel::<Message>(32);
 
-    // spawn off our routine that sends messages to the server
-    spawn(send_to_server(Arc::clone(&config), rx, Arc::clone(&user)));
-    // spawn off our routine that gets messages from the server
-    spawn(get_message_from_server(
-        Arc::clone(&config),
-        Arc::clone(&user),
-    ));
+    // spawn off our routine that connects and stays connected until we
+    // quit or the server goes away. Either way we are done once it ends,
+    // even if stdin is still waiting on a line
+    let connection_settings = Arc::clone(&settings);
+    spawn(async move {
+        run_client(config, address, rx, connection_settings).await;
+        process::exit(0);
+    });
 
     println!("Welcome to chat!!!!");
     // Start our routine that gets a message from stdin and sends to the send_to_server thread
-    read_and_send(tx.clone(), Arc::clone(&user)).await;
+    read_and_send(tx, client, settings).await;
+
+    // Leave it to the connection to pass on our :quit and end the process
+    // once it has
+    pending::<()>().await;
 }
@@ -0,0 +1,41 @@
+// Optional colour for what the client prints, behind the colored feature.
+// Without the feature, or when stdout isn't a terminal, text is left as is
+#[cfg(feature = "colored")]
+use std::{
+    io::{IsTerminal, stdout},
+    sync::OnceLock,
+};
+
+// Bold cyan for whoever sent a message
+const AUTHOR: &str = "\x1b[1;36m";
+// Faint for the time a message was sent
+const TIME: &str = "\x1b[2m";
+// Yellow for notices from the server
+const SYSTEM: &str = "\x1b[33m";
+
+pub fn author(text: &str) -> String {
+    paint(AUTHOR, text)
+}
+
+pub fn time(text: &str) -> String {
+    paint(TIME, text)
+}
+
+pub fn system(text: &str) -> String {
+    paint(SYSTEM, text)
+}
+
+#[cfg(feature = "colored")]
+fn paint(code: &str, text: &str) -> String {
+    // Whether stdout is a terminal won't change while we run
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    match *ENABLED.get_or_init(|| stdout().is_terminal()) {
+        true => format!("{code}{text}\x1b[0m"),
+        false => text.to_string(),
+    }
+}
+
+#[cfg(not(feature = "colored"))]
+fn paint(_code: &str, text: &str) -> String {
+    text.to_string()
+}
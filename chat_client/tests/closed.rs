@@ -0,0 +1,88 @@
+use chat_client::{Settings, get_message_from_server, send_to_server};
+use chat_shared::{Client, Config, FrameError, Message, User, message::MessageKind};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncWriteExt, duplex},
+    sync::mpsc,
+    time::timeout,
+};
+
+#[tokio::test]
+async fn writing_to_a_closed_connection_is_an_error() {
+    let config = Arc::new(Config::default());
+    let (ours, server) = duplex(4096);
+    let user = Arc::new(User::over(Box::new(ours), String::from("test")));
+    // The server going away is what a reconnect has to recover from
+    drop(server);
+
+    let (tx, mut rx) = mpsc::channel(8);
+    let client = Arc::new(Client::new(String::new()));
+    let message = Message::from_string(client, String::from("hello"), MessageKind::Message);
+    tx.send(message).await.expect("could not queue the message");
+
+    // An error comes back for run_client to act on, rather than a panic
+    // taking the whole task down
+    let sent = timeout(
+        Duration::from_secs(5),
+        send_to_server(config, &mut rx, user),
+    )
+    .await
+    .expect("send_to_server did not finish");
+    assert!(matches!(sent, Err(FrameError::Io(_))));
+}
+
+#[tokio::test]
+async fn writing_without_a_connection_is_an_error() {
+    let config = Arc::new(Config::default());
+    let (ours, _server) = duplex(4096);
+    let user = Arc::new(User::over(Box::new(ours), String::from("test")));
+    // Whatever the writer was, it is gone
+    user.writer.lock().await.take();
+
+    let (tx, mut rx) = mpsc::channel(8);
+    let client = Arc::new(Client::new(String::new()));
+    let message = Message::from_string(client, String::from("hello"), MessageKind::Message);
+    tx.send(message).await.expect("could not queue the message");
+
+    let sent = send_to_server(config, &mut rx, user).await;
+    assert!(matches!(sent, Err(FrameError::Closed)));
+}
+
+// Reading stops as soon as the server goes, rather than spinning on the
+// empty reads a closed connection gives
+#[tokio::test]
+async fn reading_stops_when_the_server_hangs_up() {
+    let config = Arc::new(Config::default());
+    let settings = Arc::new(Settings::from_config(&config));
+    let (ours, server) = duplex(4096);
+    let user = Arc::new(User::over(Box::new(ours), String::from("test")));
+    drop(server);
+
+    timeout(
+        Duration::from_secs(5),
+        get_message_from_server(config, user, settings),
+    )
+    .await
+    .expect("the closed connection went unnoticed");
+}
+
+#[tokio::test]
+async fn reading_stops_when_the_server_hangs_up_mid_frame() {
+    let config = Arc::new(Config::default());
+    let settings = Arc::new(Settings::from_config(&config));
+    let (ours, mut server) = duplex(4096);
+    let user = Arc::new(User::over(Box::new(ours), String::from("test")));
+    // A length promising more than ever arrives
+    server
+        .write_all(&[0, 0, 0, 16, b'{'])
+        .await
+        .expect("could not write the partial frame");
+    drop(server);
+
+    timeout(
+        Duration::from_secs(5),
+        get_message_from_server(config, user, settings),
+    )
+    .await
+    .expect("the closed connection went unnoticed");
+}
@@ -0,0 +1,31 @@
+// Helpers shared by the integration tests. Each test file only uses some
+// of them, so the rest would otherwise be reported as dead code
+#![allow(dead_code)]
+
+use chat_client::{ConnectionState, Settings};
+use chat_shared::{Client, Message, message::MessageKind};
+use std::{sync::Arc, time::Duration};
+use tokio::time::{Instant, sleep};
+
+// How long a test waits on the client before failing, rather than hanging
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Something to say, from nobody in particular
+pub fn hello() -> Message {
+    let author = Arc::new(Client::new(String::new()));
+    Message::from_string(author, String::from("hello"), MessageKind::Message)
+}
+
+// Wait until the client is in the given state. It moves between states on
+// its own time, so a test checks the state through this rather than
+// straight away
+pub async fn wait_for_state(settings: &Settings, state: ConnectionState) {
+    let deadline = Instant::now() + TIMEOUT;
+    while settings.state().await != state {
+        assert!(
+            Instant::now() < deadline,
+            "the client never got to {state:?}"
+        );
+        sleep(Duration::from_millis(10)).await;
+    }
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use chat_client::{ConnectionState, Settings, queue_message, run_client};
+use chat_shared::Config;
+use common::{TIMEOUT, hello, wait_for_state};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    net::TcpListener,
+    sync::mpsc,
+    time::{Instant, timeout},
+};
+
+#[tokio::test]
+async fn sending_depends_on_the_connection_state() {
+    let settings = Settings::from_config(&Config::default());
+    let (tx, mut rx) = mpsc::channel(1);
+    assert_eq!(settings.state().await, ConnectionState::Connecting);
+
+    // Before the first connection and while connected, it goes out
+    for state in [ConnectionState::Connecting, ConnectionState::Connected] {
+        settings.set_state(state).await;
+        assert!(queue_message(&tx, hello(), &settings).await);
+        assert!(rx.try_recv().is_ok(), "nothing was sent while {state:?}");
+    }
+
+    // While reconnecting it is queued, and a full queue drops it at once
+    // rather than waiting on it
+    settings.set_state(ConnectionState::Reconnecting).await;
+    assert!(queue_message(&tx, hello(), &settings).await);
+    let started = Instant::now();
+    assert!(queue_message(&tx, hello(), &settings).await);
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert!(rx.try_recv().is_ok());
+    assert!(rx.try_recv().is_err(), "a full queue took another message");
+
+    // Once disconnected nothing is sent, and typing stops
+    settings.set_state(ConnectionState::Disconnected).await;
+    assert!(!queue_message(&tx, hello(), &settings).await);
+    assert!(
+        rx.try_recv().is_err(),
+        "a message was sent while disconnected"
+    );
+}
+
+#[tokio::test]
+async fn reconnecting_by_request_makes_a_fresh_connection() {
+    let config = Arc::new(Config::default());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let settings = Arc::new(Settings::from_config(&config));
+    let (tx, rx) = mpsc::channel(8);
+    let client = tokio::spawn(run_client(
+        Arc::clone(&config),
+        listener.local_addr().unwrap().to_string(),
+        rx,
+        Arc::clone(&settings),
+    ));
+
+    let (_first, _) = timeout(TIMEOUT, listener.accept()).await.unwrap().unwrap();
+    wait_for_state(&settings, ConnectionState::Connected).await;
+
+    // Asking for a fresh connection drops this one and makes another, with
+    // no wait in between
+    settings.request_reconnect();
+    let (_second, _) = timeout(TIMEOUT, listener.accept()).await.unwrap().unwrap();
+    wait_for_state(&settings, ConnectionState::Connected).await;
+
+    // Nothing more to send ends the client for good
+    drop(tx);
+    timeout(TIMEOUT, client).await.unwrap().unwrap();
+    assert_eq!(settings.state().await, ConnectionState::Disconnected);
+}
+
+#[tokio::test]
+async fn a_server_that_was_never_there_is_not_retried() {
+    let config = Arc::new(Config::default());
+    // Nothing is listening once the listener is gone
+    let address = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let settings = Arc::new(Settings::from_config(&config));
+    let (_tx, rx) = mpsc::channel(8);
+
+    timeout(
+        TIMEOUT,
+        run_client(config, address.to_string(), rx, Arc::clone(&settings)),
+    )
+    .await
+    .expect("the client kept trying");
+    assert_eq!(settings.state().await, ConnectionState::Disconnected);
+}
@@ -0,0 +1,241 @@
+use chat_client::{Settings, format_message, handle_local_command};
+use chat_shared::{
+    Client, Command, Config, IgnoreMode, Message, User,
+    message::{Channel, Destination, MessageKind},
+};
+use chrono::{Local, TimeZone, Utc};
+use std::{env, fs, process, sync::Arc};
+use tokio::io::duplex;
+
+// A user for a connection nothing is read from or written to, as
+// formatting a message never touches it
+fn user() -> User {
+    let (ours, _server) = duplex(64);
+    User::over(Box::new(ours), String::from("test"))
+}
+
+// A message from someone else, sent at a known time
+fn from(author: &str, text: &str, kind: MessageKind) -> Message {
+    let mut message = Message::from_string(Arc::new(Client::new(String::new())), text.into(), kind);
+    message.author = Some(author.to_string());
+    message.author_id = Some(format!("{author}-id"));
+    message.timestamp = Utc.with_ymd_and_hms(2024, 5, 1, 12, 34, 56).unwrap();
+    message
+}
+
+#[tokio::test]
+async fn timestamps_can_be_turned_on_and_off() {
+    let settings = Settings::from_config(&Config::default());
+    let user = user();
+    let said = || from("bob", "hi", MessageKind::Message);
+
+    let line = format_message(said(), &user, &settings).await;
+    assert_eq!(line.as_deref(), Some("-->bob: hi"));
+
+    // Times are shown as they were where we are
+    assert!(handle_local_command(&Command::Timestamps(true), &settings).await);
+    let time = said().timestamp.with_timezone(&Local).format("%H:%M:%S");
+    let line = format_message(said(), &user, &settings).await;
+    assert_eq!(line, Some(format!("-->[{time}] bob: hi")));
+
+    assert!(handle_local_command(&Command::Timestamps(false), &settings).await);
+    let line = format_message(said(), &user, &settings).await;
+    assert_eq!(line.as_deref(), Some("-->bob: hi"));
+}
+
+#[tokio::test]
+async fn timestamps_follow_the_configured_format() {
+    let settings = Settings::from_config(&Config {
+        show_timestamps: true,
+        time_format: Some(String::from("%Y-%m-%d")),
+        ..Config::default()
+    });
+    let message = from("bob", "hi", MessageKind::Message);
+    let date = message.timestamp.with_timezone(&Local).format("%Y-%m-%d");
+
+    let line = format_message(message, &user(), &settings).await;
+    assert_eq!(line, Some(format!("-->[{date}] bob: hi")));
+}
+
+#[tokio::test]
+async fn actions_are_shown_as_what_the_sender_did() {
+    let settings = Settings::from_config(&Config::default());
+    let user = user();
+    // :me is how a user says what they are doing
+    assert_eq!(
+        Command::parse(":me waves", ':'),
+        Ok(Command::Me(String::from("waves")))
+    );
+
+    let line = format_message(
+        from("alice", "waves", MessageKind::Action),
+        &user,
+        &settings,
+    )
+    .await;
+    assert_eq!(line.as_deref(), Some("-->* alice waves"));
+
+    // In a channel it is shown like any other message there
+    let mut action = from("alice", "waves", MessageKind::Action);
+    action.channel = Destination::Channel(Channel::new(String::from("games")));
+    let line = format_message(action, &user, &settings).await;
+    assert_eq!(line.as_deref(), Some("-->[games] * alice waves"));
+}
+
+#[tokio::test]
+async fn ignored_users_are_not_shown() {
+    let settings = Settings::from_config(&Config::default());
+    let user = user();
+    let said = |author| from(author, "hi", MessageKind::Message);
+
+    // Ignoring is done here, so the server never hears of it
+    let ignore = Command::Ignore(Some(String::from("bob")));
+    assert!(handle_local_command(&ignore, &settings).await);
+    assert_eq!(format_message(said("bob"), &user, &settings).await, None);
+    let line = format_message(said("carol"), &user, &settings).await;
+    assert_eq!(line.as_deref(), Some("-->carol: hi"));
+
+    let unignore = Command::Unignore(String::from("bob"));
+    assert!(handle_local_command(&unignore, &settings).await);
+    let line = format_message(said("bob"), &user, &settings).await;
+    assert_eq!(line.as_deref(), Some("-->bob: hi"));
+}
+
+#[tokio::test]
+async fn server_ignores_wait_for_the_server() {
+    let settings = Settings::from_config(&Config {
+        ignore_mode: IgnoreMode::Server,
+        ..Config::default()
+    });
+    let user = user();
+    let said = || from("bob", "hi", MessageKind::Message);
+
+    // Both go to the server, and nothing changes until it agrees
+    assert!(!handle_local_command(&Command::Ignore(None), &settings).await);
+    let ignore = Command::Ignore(Some(String::from("bob")));
+    assert!(!handle_local_command(&ignore, &settings).await);
+    assert!(settings.ignored.lock().await.is_empty());
+
+    let acked = |command: &str| {
+        let mut ack = Message::from_server(command.to_string(), MessageKind::Ack);
+        ack.channel = Destination::Direct(Client::new(String::new()));
+        ack.author = Some(String::from("bob"));
+        ack
+    };
+    assert_eq!(
+        format_message(acked("ignore"), &user, &settings).await,
+        None
+    );
+    assert_eq!(format_message(said(), &user, &settings).await, None);
+
+    let unignore = Command::Unignore(String::from("bob"));
+    assert!(!handle_local_command(&unignore, &settings).await);
+    assert_eq!(
+        format_message(acked("unignore"), &user, &settings).await,
+        None
+    );
+    let line = format_message(said(), &user, &settings).await;
+    assert_eq!(line.as_deref(), Some("-->bob: hi"));
+}
+
+#[tokio::test]
+async fn the_ignore_list_is_kept_between_runs() {
+    let path = env::temp_dir().join(format!("chat_ignored_{}.txt", process::id()));
+    let config = Config {
+        ignore_list_path: Some(path.clone()),
+        ..Config::default()
+    };
+    let ignore = Command::Ignore(Some(String::from("bob")));
+    assert!(handle_local_command(&ignore, &Settings::from_config(&config)).await);
+
+    let settings = Settings::from_config(&config);
+    let said = from("bob", "hi", MessageKind::Message);
+    assert_eq!(format_message(said, &user(), &settings).await, None);
+    let _ = fs::remove_file(path);
+}
+
+// Tell the settings which author id is ours, as the server's welcome does
+async fn welcomed_as(id: &str, user: &User, settings: &Settings) {
+    let mut welcome = Message::from_server(String::from("token"), MessageKind::Ack);
+    welcome.author_id = Some(id.to_string());
+    assert_eq!(format_message(welcome, user, settings).await, None);
+}
+
+#[tokio::test]
+async fn own_messages_are_only_echoed_when_asked_for() {
+    let user = user();
+    let quiet = Settings::from_config(&Config::default());
+    let echoing = Settings::from_config(&Config {
+        echo_own_messages: true,
+        ..Config::default()
+    });
+    for settings in [&quiet, &echoing] {
+        welcomed_as("alice-id", &user, settings).await;
+    }
+    let said = || from("alice", "hi", MessageKind::Message);
+
+    assert_eq!(format_message(said(), &user, &quiet).await, None);
+    let line = format_message(said(), &user, &echoing).await;
+    assert_eq!(line.as_deref(), Some("-->alice: hi"));
+
+    // Someone else's messages are shown either way
+    let other = from("bob", "hi", MessageKind::Message);
+    let line = format_message(other, &user, &quiet).await;
+    assert_eq!(line.as_deref(), Some("-->bob: hi"));
+
+    // Private messages always come back, so we can see they got through
+    let mut private = said();
+    private.channel = Destination::Direct(Client::new(String::new()));
+    let line = format_message(private, &user, &quiet).await;
+    assert_eq!(line.as_deref(), Some("-->[private] alice: hi"));
+}
+
+fn prompt_shown(settings: &Settings) -> String {
+    let mut out = Vec::new();
+    settings.show_prompt_to(&mut out);
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn the_prompt_is_only_shown_to_someone_typing() {
+    let config = Config::default();
+    assert_eq!(prompt_shown(&Settings::new(&config, true)), "> ");
+    // Input piped in from a script has nobody to prompt
+    assert_eq!(prompt_shown(&Settings::new(&config, false)), "");
+
+    let custom = Config {
+        prompt: String::from("chat> "),
+        ..Config::default()
+    };
+    assert_eq!(prompt_shown(&Settings::new(&custom, true)), "chat> ");
+    let off = Config {
+        prompt: String::new(),
+        ..Config::default()
+    };
+    assert_eq!(prompt_shown(&Settings::new(&off, true)), "");
+}
+
+// Colour is only ever added by the colored feature, so without it every
+// line is the plain text it always was
+#[cfg(not(feature = "colored"))]
+#[tokio::test]
+async fn without_colour_nothing_is_escaped() {
+    let settings = Settings::from_config(&Config {
+        show_timestamps: true,
+        ..Config::default()
+    });
+    let user = user();
+    let notice = Message::from_server(String::from("bob joined"), MessageKind::ServerBroadcast);
+    let messages = [
+        from("bob", "hi", MessageKind::Message),
+        from("bob", "waves", MessageKind::Action),
+        notice,
+    ];
+
+    for message in messages {
+        let line = format_message(message, &user, &settings)
+            .await
+            .expect("the message was not shown");
+        assert!(!line.contains('\x1b'), "{line:?} has an escape sequence");
+    }
+}
@@ -0,0 +1,25 @@
+use chat_client::{Settings, message_from_input};
+use chat_shared::{Client, Config, message::MessageKind};
+use std::sync::Arc;
+
+#[tokio::test]
+async fn only_the_configured_prefix_starts_a_command() {
+    let settings = Settings::from_config(&Config {
+        prefix: '/',
+        ..Config::default()
+    });
+    let client = Arc::new(Client::new(String::new()));
+
+    let quit = message_from_input("/quit", &client, &settings)
+        .await
+        .expect("/quit was not sent");
+    assert_eq!(quit.kind, MessageKind::Command);
+    assert_eq!(quit.as_string(), "/quit");
+
+    // The default prefix means nothing now, so it is just something said
+    let said = message_from_input(":quit", &client, &settings)
+        .await
+        .expect(":quit was not sent");
+    assert_eq!(said.kind, MessageKind::Message);
+    assert_eq!(said.as_string(), ":quit");
+}
@@ -0,0 +1,33 @@
+use chat_client::{Settings, receive_message};
+use chat_shared::{Config, Message, User, frame, message::MessageKind};
+use std::{sync::Arc, time::Duration};
+use tokio::{io::duplex, time::timeout};
+
+#[tokio::test]
+async fn ping_is_answered_and_not_shown() {
+    let config = Config::default();
+    let settings = Settings::from_config(&config);
+    // One end stands in for our connection, the other for the server
+    let (ours, mut server) = duplex(4096);
+    let user = Arc::new(User::over(Box::new(ours), String::from("test")));
+
+    let ping = Message::from_server(String::new(), MessageKind::Ping);
+    let payload = frame::encode(&ping, config.max_frame_size).expect("ping did not encode");
+    let line = receive_message(
+        &config,
+        payload[frame::HEADER_LEN..].to_vec(),
+        &user,
+        &settings,
+    )
+    .await;
+    assert_eq!(line, None, "the ping was shown to the user");
+
+    let reply = timeout(
+        Duration::from_secs(5),
+        frame::read_frame(&mut server, config.max_frame_size),
+    )
+    .await
+    .expect("no reply to the ping")
+    .expect("the reply did not decode");
+    assert_eq!(reply.kind, MessageKind::Pong);
+}
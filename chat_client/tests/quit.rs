@@ -0,0 +1,38 @@
+use chat_client::send_to_server;
+use chat_shared::{Client, Config, FrameError, Message, User, frame, message::MessageKind};
+use std::{sync::Arc, time::Duration};
+use tokio::{io::duplex, sync::mpsc, time::timeout};
+
+#[tokio::test]
+async fn quit_is_sent_before_the_connection_ends() {
+    let config = Arc::new(Config::default());
+    // One end stands in for our connection, the other for the server
+    let (ours, mut server) = duplex(4096);
+    let user = Arc::new(User::over(Box::new(ours), String::from("test")));
+
+    // As read_and_send does when :quit is typed, queue it and stop sending
+    let (tx, mut rx) = mpsc::channel(8);
+    let client = Arc::new(Client::new(String::new()));
+    let quit = Message::from_string(client, String::from(":quit"), MessageKind::Command);
+    tx.send(quit).await.expect("could not queue :quit");
+    drop(tx);
+
+    // The client exits once this returns, so :quit must be out by then
+    timeout(
+        Duration::from_secs(5),
+        send_to_server(Arc::clone(&config), &mut rx, user),
+    )
+    .await
+    .expect("send_to_server did not finish")
+    .expect("send_to_server failed");
+
+    let received = frame::read_frame(&mut server, config.max_frame_size)
+        .await
+        .expect(":quit did not reach the server");
+    assert_eq!(received.kind, MessageKind::Command);
+    assert_eq!(received.as_string(), ":quit");
+
+    // And the server is left knowing nothing more will come
+    let after = frame::read_frame(&mut server, config.max_frame_size).await;
+    assert!(matches!(after, Err(FrameError::Closed)));
+}
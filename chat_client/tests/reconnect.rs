@@ -0,0 +1,90 @@
+mod common;
+
+use chat_client::{ConnectionState, Settings, run_client};
+use chat_shared::{Client, Config, Message, frame, message::MessageKind};
+use common::{TIMEOUT, wait_for_state};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time::{Instant, sleep, timeout},
+};
+
+async fn accept(listener: &TcpListener) -> TcpStream {
+    let (socket, _) = timeout(TIMEOUT, listener.accept())
+        .await
+        .expect("the client never connected")
+        .expect("could not accept");
+    socket
+}
+
+// The next chat message the client sends, skipping anything else
+async fn next_said(socket: &mut TcpStream, config: &Config) -> String {
+    loop {
+        let message = timeout(TIMEOUT, frame::read_frame(socket, config.max_frame_size))
+            .await
+            .expect("the client sent nothing")
+            .expect("the connection failed");
+        if message.kind == MessageKind::Message {
+            return message.as_string();
+        }
+    }
+}
+
+// Losing the server sends the client into backing off and trying again,
+// and once the server is back what was typed in the meantime goes out
+#[tokio::test]
+async fn client_backs_off_and_resumes_once_the_server_returns() {
+    let config = Arc::new(Config {
+        reconnect_base_ms: 50,
+        reconnect_max_ms: 200,
+        ..Config::default()
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let settings = Arc::new(Settings::from_config(&config));
+    let (tx, rx) = mpsc::channel(8);
+    let client = tokio::spawn(run_client(
+        Arc::clone(&config),
+        address.to_string(),
+        rx,
+        Arc::clone(&settings),
+    ));
+    let author = Arc::new(Client::new(String::new()));
+    let say = |text: &str| {
+        Message::from_string(Arc::clone(&author), text.to_string(), MessageKind::Message)
+    };
+
+    let mut socket = accept(&listener).await;
+    wait_for_state(&settings, ConnectionState::Connected).await;
+    tx.send(say("before")).await.unwrap();
+    assert_eq!(next_said(&mut socket, &config).await, "before");
+
+    // The server goes away entirely, so every try in the meantime fails
+    drop(socket);
+    drop(listener);
+    wait_for_state(&settings, ConnectionState::Reconnecting).await;
+    tx.send(say("while away")).await.unwrap();
+    sleep(Duration::from_millis(400)).await;
+    assert_eq!(settings.state().await, ConnectionState::Reconnecting);
+
+    // Back on the same address, the client finds it again on its own
+    let listener = TcpListener::bind(address).await.unwrap();
+    let started = Instant::now();
+    let mut socket = accept(&listener).await;
+    // However many tries failed, none waits much longer than the cap. Left
+    // to double, the wait would be up to 800ms by now
+    assert!(started.elapsed() < Duration::from_millis(600));
+    assert_eq!(next_said(&mut socket, &config).await, "while away");
+    wait_for_state(&settings, ConnectionState::Connected).await;
+    tx.send(say("after")).await.unwrap();
+    assert_eq!(next_said(&mut socket, &config).await, "after");
+
+    // Nothing more to send ends the client
+    drop(tx);
+    timeout(TIMEOUT, client)
+        .await
+        .expect("the client did not finish")
+        .expect("the client task failed");
+    assert_eq!(settings.state().await, ConnectionState::Disconnected);
+}
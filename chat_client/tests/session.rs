@@ -0,0 +1,51 @@
+use chat_client::{Settings, receive_message};
+use chat_shared::{
+    Config, Message, User, frame,
+    message::{Channel, Destination, MessageKind},
+};
+use std::sync::Arc;
+use tokio::io::duplex;
+
+// Hand a message to the client as if it had just arrived from the server
+async fn arrive(config: &Config, user: &Arc<User>, settings: &Settings, message: Message) {
+    let payload = frame::encode(&message, config.max_frame_size).expect("message did not encode");
+    let _ = receive_message(
+        config,
+        payload[frame::HEADER_LEN..].to_vec(),
+        user,
+        settings,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn only_what_the_server_acks_is_restored() {
+    let config = Config::default();
+    let settings = Settings::from_config(&config);
+    let (ours, _server) = duplex(4096);
+    let user = Arc::new(User::over(Box::new(ours), String::from("test")));
+
+    // Refusals are only shown to the user, and change nothing
+    let refused =
+        Message::from_server(String::from("nickname taken"), MessageKind::ServerBroadcast);
+    arrive(&config, &user, &settings, refused).await;
+    let refused = Message::from_server(
+        String::from("No channel named rust"),
+        MessageKind::ServerBroadcast,
+    );
+    arrive(&config, &user, &settings, refused).await;
+    assert_eq!(*settings.nick_name.lock().await, None);
+    assert!(settings.channels.lock().await.is_empty());
+
+    let mut named = Message::from_server(String::new(), MessageKind::Ack);
+    named.author = Some(String::from("alice"));
+    arrive(&config, &user, &settings, named).await;
+    let mut joined = Message::from_server(String::new(), MessageKind::Ack);
+    joined.channel = Destination::Channel(Channel::new(String::from("rust")));
+    arrive(&config, &user, &settings, joined.clone()).await;
+    // Joining again is acked again, but the channel is only kept once
+    arrive(&config, &user, &settings, joined).await;
+
+    assert_eq!(settings.nick_name.lock().await.as_deref(), Some("alice"));
+    assert_eq!(*settings.channels.lock().await, ["rust"]);
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use chat_client::{Settings, queue_message_to, send_to_server};
+use chat_shared::{Config, User};
+use common::hello;
+use std::{sync::Arc, time::Duration};
+use tokio::{io::duplex, sync::mpsc, time::sleep};
+
+// The server stops reading, so writes stall, the queue fills and the user
+// is told, first that sending is slow and at last that it failed
+#[tokio::test(start_paused = true)]
+async fn a_stalled_writer_is_reported_to_the_user() {
+    let config = Arc::new(Config::default());
+    let settings = Settings::from_config(&config);
+    let (ours, _server) = duplex(64);
+    let user = Arc::new(User::over(Box::new(ours), String::from("test")));
+    let (tx, mut rx) = mpsc::channel(1);
+    tokio::spawn(async move { send_to_server(config, &mut rx, user).await });
+
+    // Sends can be slow yet still get through while the connection's
+    // buffer fills, so keep going until one gives up
+    for _ in 0..100 {
+        let mut out = Vec::new();
+        assert!(queue_message_to(&mut out, &tx, hello(), &settings).await);
+        let out = String::from_utf8(out).unwrap();
+        if out.contains("failed") {
+            assert_eq!(out, "-->sending... (network slow)\n-->failed to send\n");
+            return;
+        }
+    }
+    panic!("sending never failed");
+}
+
+// A queue that drains in time only earns the first notice
+#[tokio::test(start_paused = true)]
+async fn a_slow_writer_that_catches_up_is_not_a_failure() {
+    let settings = Settings::from_config(&Config::default());
+    let (tx, mut rx) = mpsc::channel(1);
+    tx.send(hello()).await.unwrap();
+    tokio::spawn(async move {
+        sleep(Duration::from_secs(1)).await;
+        while rx.recv().await.is_some() {}
+    });
+
+    let mut out = Vec::new();
+    assert!(queue_message_to(&mut out, &tx, hello(), &settings).await);
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "-->sending... (network slow)\n"
+    );
+}
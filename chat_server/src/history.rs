@@ -0,0 +1,76 @@
+use chat_shared::{Message, message::Destination};
+use std::{
+    collections::VecDeque,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+};
+use tokio::sync::mpsc::{Sender, channel};
+use tracing::error;
+
+// A bounded record of the most recently relayed messages. Once full,
+// the oldest message is dropped to make room for the newest
+pub struct History {
+    messages: VecDeque<Message>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            messages: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, message: Message) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.messages.len() == self.capacity {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message);
+    }
+
+    // The last count messages that were sent to everyone, oldest first
+    pub fn recent_global(&self, count: usize) -> Vec<Message> {
+        let mut recent: Vec<Message> = self
+            .messages
+            .iter()
+            .rev()
+            .filter(|message| matches!(message.channel, Destination::Global))
+            .take(count)
+            .cloned()
+            .collect();
+        recent.reverse();
+        recent
+    }
+
+    // Look up a message that is still in the history by its id
+    pub fn get(&self, id: u64) -> Option<&Message> {
+        self.messages.iter().find(|message| message.id == Some(id))
+    }
+}
+
+// Append every message handed to the returned sender to the file at path,
+// one line of RON each, creating the file if needed. The file is written
+// from a blocking thread so disk IO never holds up relaying messages. A
+// failed write is logged and the log carries on with the next message
+pub fn spawn_log(path: &Path) -> io::Result<Sender<Message>> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let path = path.display().to_string();
+    let (tx, mut rx) = channel::<Message>(256);
+
+    tokio::task::spawn_blocking(move || {
+        while let Some(message) = rx.blocking_recv() {
+            let result = ron::to_string(&message)
+                .map_err(io::Error::other)
+                .and_then(|line| writeln!(file, "{line}"));
+            if let Err(e) = result {
+                error!(%path, error = %e, "Could not write to the history log");
+            }
+        }
+    });
+    Ok(tx)
+}
@@ -1,163 +1,1563 @@
 use chat_shared::{
-    Config, User,
-    message::{Message, MessageKind},
+    BoxedConnection, Command, Config, DisconnectReason, FrameError, IgnoreMode, LogFormat,
+    ParseError, Role, TlsError, User, format_duration,
+    frame::{self, FrameReader},
+    message::{Destination, Message, MessageKind},
+    storage::{FileStorage, MemberRecord, MemoryStorage, Storage},
+    tls,
+    token_bucket::TokenBucket,
+    user::Client,
+};
+use chrono::{DateTime, Utc};
+use history::History;
+use ron::ser::PrettyConfig;
+use roster::{JoinOutcome, Roster};
+use socket2::{Domain, Socket, Type};
+use stats::{HighWater, QueueAlert, QueueDepth, SizeHistogram};
+use std::{
+    collections::HashMap,
+    future::poll_fn,
+    io,
+    net::SocketAddr,
+    pin::pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::Poll,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use std::sync::Arc;
 use tokio::{
-    io::ErrorKind,
+    io::{AsyncWriteExt, ErrorKind},
+    net::{TcpListener, TcpStream},
     sync::Mutex,
-    sync::mpsc::{Receiver, Sender},
+    sync::mpsc::{Receiver, Sender, channel},
+    task::JoinSet,
+    time::{interval, sleep, timeout},
 };
+use tokio_rustls::TlsAcceptor;
+use tracing::{Level, debug, error, info, warn};
+
+pub mod history;
+pub mod roster;
+pub mod selftest;
+pub mod stats;
+
+// How long a client gets to finish the TLS handshake before it is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How many frames the writer queue and each client's outbox hold. Once an
+// outbox is full, messages for that client are dropped
+const QUEUE_CAPACITY: usize = 32;
+
+// The state shared by every task on the server
+pub struct ServerState {
+    pub config: Config,
+    pub roster: Mutex<Roster>,
+    pub history: Mutex<History>,
+    pub storage: Box<dyn Storage>,
+    pub history_log: Option<Sender<Message>>,
+    // Users who have dropped off but may still resume their session, keyed
+    // by session token
+    pub departures: Mutex<HashMap<String, Departure>>,
+    // Runs the TLS handshake on accepted connections. If None, clients
+    // talk to the server in plain text
+    pub tls: Option<TlsAcceptor>,
+    // How large the relayed chat messages have been, for :stats
+    pub message_sizes: SizeHistogram,
+    // How far behind the writer queue has been, and the deepest any one
+    // client's outbox has got, for :stats
+    pub writer_depth: QueueDepth,
+    pub outbox_depth: QueueDepth,
+    next_message_id: AtomicU64,
+}
+
+// What is kept of a session for the grace period after its user drops off,
+// so it can carry on if they reconnect and resume it
+pub struct Departure {
+    pub name: String,
+    pub connected_at: DateTime<Utc>,
+    // Whether anyone was told they arrived, and so should hear they left
+    pub announced: bool,
+}
+
+impl ServerState {
+    pub fn new(config: Config) -> Self {
+        Self {
+            history: Mutex::new(History::new(config.history_size)),
+            storage: Box::new(MemoryStorage::new()),
+            history_log: None,
+            departures: Mutex::new(HashMap::new()),
+            tls: None,
+            message_sizes: SizeHistogram::new(),
+            writer_depth: QueueDepth::new(),
+            outbox_depth: QueueDepth::new(),
+            roster: Mutex::new(Roster::new(config.case_insensitive_channels)),
+            config,
+            next_message_id: AtomicU64::new(1),
+        }
+    }
+
+    // Swap in a different storage backend
+    pub fn with_storage(mut self, storage: Box<dyn Storage>) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    // Keep messages and member records in the configured event log rather
+    // than in memory, first replaying it into the history when asked to.
+    // Does nothing if no event log is configured
+    pub async fn open_event_log(&mut self) -> io::Result<()> {
+        let Some(path) = self.config.event_log_path.clone() else {
+            return Ok(());
+        };
+        self.storage = Box::new(FileStorage::open(&path, self.config.event_log_max_bytes)?);
+
+        if self.config.replay_event_log {
+            let messages = self
+                .storage
+                .recent_messages(self.config.history_size)
+                .await?;
+            info!(count = messages.len(), "Replayed event log");
+
+            // Carry on numbering after the replayed messages so :reply
+            // can't mix up an old message with a new one
+            if let Some(last) = messages.iter().filter_map(|message| message.id).max() {
+                *self.next_message_id.get_mut() = last + 1;
+            }
+            let history = self.history.get_mut();
+            for message in messages {
+                history.push(message);
+            }
+        }
+        Ok(())
+    }
 
-// define a type to make this easier to work with
-type Clients = Arc<Mutex<Vec<Arc<User>>>>;
+    // Start writing messages sent to everyone to the configured history
+    // file. Without one, or if it can't be opened, no transcript is kept
+    pub fn open_history_log(&mut self) {
+        let Some(path) = &self.config.history_path else {
+            return;
+        };
+        match history::spawn_log(path) {
+            Ok(log) => self.history_log = Some(log),
+            Err(e) => error!(path = %path.display(), error = %e, "Could not open the history log"),
+        }
+    }
 
-// Get's a message from the buffer
-pub fn get_message_from_buffer(buffer: &[u8]) -> Result<Message, String> {
-    let result: Result<Message, String>;
-    if let Ok(message) = String::from_utf8(buffer.iter().filter(|n| **n != 0).copied().collect()) {
-        result = match ron::from_str::<Message>(&message) {
-            Ok(r) => Ok(r),
-            Err(e) => Err(e.to_string()),
+    // Serve clients over TLS with the configured certificate. Does nothing
+    // if TLS isn't configured
+    pub fn load_tls(&mut self) -> Result<(), TlsError> {
+        if let Some(config) = &self.config.tls {
+            self.tls = Some(tls::acceptor(config)?);
         }
-    } else {
-        result = Err("Could not get message from buffer".to_string());
+        Ok(())
+    }
+
+    // Hand out the id for a newly accepted message. Ids are unique and only
+    // grow for the lifetime of the server. They start over from 1 when the
+    // server restarts, unless the event log is replayed
+    pub fn assign_message_id(&self) -> u64 {
+        self.next_message_id.fetch_add(1, Ordering::Relaxed)
     }
-    result
+}
+
+// Set up the global logger in the requested format. Each -v on the
+// command line brings in a more detailed level
+pub fn init_logging(format: LogFormat, verbosity: u8) {
+    let level = match verbosity {
+        0 => Level::INFO,
+        1 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let logger = tracing_subscriber::fmt().with_max_level(level);
+    match format {
+        LogFormat::Plain => logger.init(),
+        LogFormat::Json => logger.json().init(),
+    }
+}
+
+// Bind a listener on the address. An IPv6 listener only takes IPv6
+// clients, so one on the same port for IPv4 can sit alongside it
+pub fn bind_listener(address: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+    if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+// Wait for a client on whichever of the listeners gets one first
+async fn accept_any(listeners: &[TcpListener]) -> io::Result<(TcpStream, SocketAddr)> {
+    poll_fn(|cx| {
+        for listener in listeners {
+            if let Poll::Ready(accepted) = listener.poll_accept(cx) {
+                return Poll::Ready(accepted);
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+// Accept clients on the listeners and spawn the tasks that serve them.
+// Clients from every listener end up in the same roster, so one on IPv4
+// can chat with one on IPv6. Runs until a listener fails or shutdown
+// completes, then disconnects everyone and waits for their tasks to finish
+pub async fn run_server(
+    state: Arc<ServerState>,
+    listeners: Vec<TcpListener>,
+    shutdown: impl Future<Output = ()>,
+) {
+    // Nobody can hand over a connection, so only the listeners bring clients
+    let (_, connections) = channel(1);
+    run_server_with(state, listeners, connections, shutdown).await;
+}
+
+// As run_server, but also serving connections made some other way, such
+// as over an in-memory pipe, and handed over along with the address to
+// treat them as coming from. These skip TLS, as they never leave the process
+pub async fn run_server_with(
+    state: Arc<ServerState>,
+    listeners: Vec<TcpListener>,
+    mut connections: Receiver<(BoxedConnection, SocketAddr)>,
+    shutdown: impl Future<Output = ()>,
+) {
+    // set up the sender and receiver for our threads. Whole messages go
+    // through it, author and destination included, so the writer can
+    // decide per recipient who gets what. Clients format them for display
+    let (tx, rx) = channel::<Message>(QUEUE_CAPACITY);
+    let mut tasks = JoinSet::new();
+
+    // spawn off our writer
+    tasks.spawn(handle_writes(Arc::clone(&state), rx));
+
+    // Pinging goes on forever, so it is stopped rather than waited for
+    let heartbeat = state
+        .config
+        .heartbeat_secs
+        .map(|secs| tokio::spawn(heartbeat(Arc::clone(&state), Duration::from_secs(secs))));
+
+    // TLS handshakes run on their own, so a client that is slow to finish
+    // one doesn't hold up accepting everyone else
+    let mut handshakes = JoinSet::new();
+
+    // Loop until a listener fails or we are asked to stop
+    let mut shutdown = pin!(shutdown);
+    loop {
+        let (socket, addr, certified_name) = tokio::select! {
+            accepted = accept_any(&listeners) => match accepted {
+                Ok((socket, addr)) => match &state.tls {
+                    Some(acceptor) => {
+                        handshakes.spawn(accept_tls(acceptor.clone(), socket, addr));
+                        continue;
+                    }
+                    None => (Box::new(socket) as BoxedConnection, addr, None),
+                },
+                Err(_) => break,
+            },
+            Some(Ok(Some(secured))) = handshakes.join_next() => secured,
+            Some((socket, addr)) = connections.recv() => (socket, addr, None),
+            _ = &mut shutdown => break,
+        };
+        // Only this loop adds users, so the count can't grow between
+        // checking it here and adding the user below
+        if let Some(max) = state.config.max_clients
+            && state.roster.lock().await.len() >= max
+        {
+            info!(address = %addr, "Refusing client, server is full");
+            tokio::spawn(refuse_client(Arc::clone(&state), socket));
+            continue;
+        }
+
+        // log that a client connected
+        let is_admin = state.config.admin_ips.contains(&addr.ip());
+        let addr = addr.to_string();
+        info!(address = %addr, "Client connected");
+
+        // put our socket in an Arc so it can be shared
+        // and push it to the client's list
+        let user = Arc::new(User::over(socket, addr));
+        *user.display_fallback.lock().await = state.config.display_fallback;
+        if is_admin {
+            *user.role.lock().await = Role::Admin;
+        }
+
+        // give the client its own writer so it can be paced on its own,
+        // with room on top for the history they are caught up on
+        let capacity = QUEUE_CAPACITY + state.config.history_replay;
+        let (out_tx, out_rx) = channel::<Vec<u8>>(capacity);
+        *user.outbox.lock().await = Some(out_tx);
+        tasks.spawn(handle_user_writes(
+            Arc::clone(&state),
+            Arc::clone(&user),
+            out_rx,
+        ));
+
+        // Tell the client which author id is theirs, so it can recognise
+        // its own messages whatever name it goes by, and the session token
+        // that lets it resume after reconnecting
+        let mut welcome = Message::from_server(user.session_token.clone(), MessageKind::Ack);
+        welcome.author_id = Some(user.client.id.clone());
+        if let Err(e) = send_to_user(&state.config, &user, welcome).await {
+            warn!(address = %user.client.address, error = %e, "Could not welcome client");
+        }
+
+        // The welcome is queued before the user is on the roster, and their
+        // outbox is first in, first out, so the welcome is always the first
+        // thing they get. The history replay comes next, then everything
+        // relayed once they are on the roster, in order
+        join_roster(&state, &user).await;
+
+        if let Some(name) = certified_name {
+            take_certified_name(&state, &user, name).await;
+        }
+
+        // spawn off our client thread
+        tasks.spawn(handle_client(
+            Arc::clone(&state),
+            Arc::clone(&user),
+            tx.clone(),
+        ));
+    }
+
+    info!("Shutting down");
+    handshakes.shutdown().await;
+    if let Some(heartbeat) = heartbeat {
+        heartbeat.abort();
+    }
+
+    // Once every client is gone, so is every sender, and the writer ends
+    // too. Don't let a client that has stopped reading keep us up forever,
+    // even while it is being told we are going
+    drop(tx);
+    let users = state.roster.lock().await.users();
+    let grace = Duration::from_secs(state.config.shutdown_timeout_secs);
+    let drained = timeout(grace, async {
+        disconnect_everyone(&state).await;
+        while tasks.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        // A user's writer lets go of their connection once everything
+        // queued for them is written, so whoever still has one is stuck
+        for user in users {
+            let stuck = user
+                .writer
+                .try_lock()
+                .map_or(true, |writer| writer.is_some());
+            if stuck {
+                warn!(address = %user.client.address, "Client did not drain in time");
+            }
+        }
+        warn!(
+            remaining = tasks.len(),
+            "Gave up waiting on clients to disconnect"
+        );
+        tasks.shutdown().await;
+    }
+}
+
+// Ping every user each period, disconnecting anyone who has let too many
+// pings in a row go unanswered. Hearing anything at all from a user
+// counts as an answer
+pub async fn heartbeat(state: Arc<ServerState>, period: Duration) {
+    let mut ticks = interval(period);
+    // The first tick is immediate, and nobody has had a chance to answer yet
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        let users = state.roster.lock().await.users();
+        for user in users {
+            let missed = {
+                let mut missed_pings = user.missed_pings.lock().await;
+                *missed_pings += 1;
+                *missed_pings
+            };
+            if missed > state.config.heartbeat_miss_limit {
+                info!(address = %user.client.address, "Disconnecting unresponsive client");
+                user.deactivate(DisconnectReason::Unresponsive).await;
+                continue;
+            }
+
+            let ping = Message::from_server(String::new(), MessageKind::Ping);
+            if let Err(e) = send_to_user(&state.config, &user, ping).await {
+                warn!(address = %user.client.address, error = %e, "Could not ping client");
+            }
+        }
+    }
+}
+
+// Run the TLS handshake on a newly accepted socket, giving up on clients
+// that fail it or take too long about it. Along with the stream comes the
+// name on the client's certificate, if the server asked for one
+async fn accept_tls(
+    acceptor: TlsAcceptor,
+    socket: TcpStream,
+    addr: SocketAddr,
+) -> Option<(BoxedConnection, SocketAddr, Option<String>)> {
+    match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+        Ok(Ok(stream)) => {
+            let certified_name = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(tls::certificate_name);
+            Some((Box::new(stream), addr, certified_name))
+        }
+        Ok(Err(e)) => {
+            warn!(address = %addr, error = %e, "TLS handshake failed");
+            None
+        }
+        Err(_) => {
+            warn!(address = %addr, "TLS handshake timed out");
+            None
+        }
+    }
+}
+
+// Give a client the nickname its certificate is issued for, as though it
+// had asked for it. It can't pick another, so if the name is unusable or
+// already taken it goes without one
+async fn take_certified_name(state: &ServerState, user: &Arc<User>, name: String) {
+    *user.certified_name.lock().await = Some(name.clone());
+    if let Err(e) = User::validate_nickname(&name, &state.config) {
+        warn!(address = %user.client.address, %name, error = %e, "Certificate name is not a usable nickname");
+        return;
+    }
+    if !claim_nick_name(state, user, name.clone()).await {
+        warn!(address = %user.client.address, %name, "Certificate name is already taken");
+        let _ = notify_user(&state.config, user, String::from("nickname taken")).await;
+        return;
+    }
+    info!(address = %user.client.address, %name, "Client identified by certificate");
+    let mut ack = Message::from_server(String::new(), MessageKind::Ack);
+    ack.author = Some(name);
+    if let Err(e) = send_to_user(&state.config, user, ack).await {
+        warn!(address = %user.client.address, error = %e, "Could not tell client its name");
+    }
+    announce_arrival(state, user).await;
+}
+
+// Tell a client we can't take them and hang up, without ever adding
+// them to the roster
+pub async fn refuse_client(state: Arc<ServerState>, mut socket: BoxedConnection) {
+    let notice = Message::from_server(String::from("Server full"), MessageKind::ServerBroadcast);
+    if frame::write_frame(&mut socket, &notice, state.config.max_frame_size)
+        .await
+        .is_ok()
+    {
+        let _ = socket.shutdown().await;
+    }
+}
+
+// Tell everyone the server is going away and disconnect them. Each
+// client's tasks flush the notice, close the connection and end
+pub async fn disconnect_everyone(state: &Arc<ServerState>) {
+    let users = state.roster.lock().await.users();
+    // Everyone is told at once, so a client whose outbox is full only
+    // holds up their own notice
+    let mut notices = JoinSet::new();
+    for user in users {
+        let state = Arc::clone(state);
+        notices.spawn(async move {
+            let notice = String::from("Server shutting down");
+            let _ = notify_user(&state.config, &user, notice).await;
+            user.deactivate(DisconnectReason::ServerShutdown).await;
+        });
+    }
+    notices.join_all().await;
+}
+
+// Catch a newly connected user up on the conversation by sending them
+// the most recent messages said to everyone, then put them on the roster.
+// Message ids are handed out under the history lock, and it is held
+// throughout, so every message either is in the replay or is relayed to
+// them live, never both, and the replay comes first
+pub async fn join_roster(state: &ServerState, user: &Arc<User>) {
+    let history = state.history.lock().await;
+    for message in history.recent_global(state.config.history_replay) {
+        // Their outbox has room for the whole replay, and waiting on a
+        // full one here would hold up every message being relayed
+        if let Err(e) = try_send_to_user(&state.config, user, message).await {
+            warn!(address = %user.client.address, error = %e, "Could not replay history");
+            break;
+        }
+    }
+    let next_id = state.next_message_id.load(Ordering::Relaxed);
+    user.live_from.store(next_id, Ordering::Relaxed);
+    state.roster.lock().await.add(Arc::clone(user));
+}
+
+// Address a private message to a client. Routing only needs their id,
+// and the copy echoed to the sender mustn't tell them where it went
+fn direct_to(client: &Client) -> Destination {
+    Destination::Direct(Client {
+        id: client.id.clone(),
+        address: String::new(),
+    })
+}
+
+// Confirm an :ignore or :unignore, so the client only keeps what the
+// server accepted. The ack is addressed to the user it is about, carries
+// their name, and says which of the two it confirms
+async fn ack_ignore(
+    state: &ServerState,
+    user: &Arc<User>,
+    command: &Command,
+    target: &Arc<User>,
+) -> Result<(), String> {
+    let mut ack = Message::from_server(command.name().to_string(), MessageKind::Ack);
+    ack.channel = direct_to(&target.client);
+    ack.author = Some(target.get_display_name().await);
+    send_to_user(&state.config, user, ack).await
+}
+
+// Find a connected user by their current display name. Like claiming a
+// nickname, this skips anyone on their way out and ignores case, so it
+// finds whoever holds the name and never someone who just gave it up
+pub async fn find_user(state: &ServerState, name: &str) -> Option<Arc<User>> {
+    // Take a snapshot so the roster lock isn't held while we lock each nickname
+    let snapshot = state.roster.lock().await.users();
+    for client in snapshot {
+        if !*client.is_active.lock().await {
+            continue;
+        }
+        if same_name(&client.get_display_name().await, name) {
+            return Some(client);
+        }
+    }
+    None
+}
+
+// Whether two names are the same one. Case doesn't count, so nobody can
+// pass for someone else by capitalising their name differently
+fn same_name(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+// The display names of everyone still connected, with the asking user
+// marked so they can find themselves
+pub async fn connected_names(state: &ServerState, asking: &Arc<User>) -> Vec<String> {
+    // Take a snapshot so the roster lock isn't held while we lock each user
+    let snapshot = state.roster.lock().await.users();
+    let mut names = Vec::with_capacity(snapshot.len());
+    for user in snapshot {
+        if !*user.is_active.lock().await {
+            continue;
+        }
+        let name = user.get_display_name().await;
+        match Arc::ptr_eq(&user, asking) {
+            true => names.push(format!("{name} (you)")),
+            false => names.push(name),
+        }
+    }
+    names
+}
+
+// Write a message to a single user rather than everyone
+pub async fn send_to_user(
+    config: &Config,
+    user: &Arc<User>,
+    message: Message,
+) -> Result<(), String> {
+    let buff = encode_for(config, message)?;
+
+    // Clone the sender so the outbox lock isn't held while we wait on the queue
+    let outbox = user.outbox.lock().await.clone();
+    match outbox {
+        Some(outbox) => outbox
+            .send(buff)
+            .await
+            .map_err(|_| String::from("Failed to queue message")),
+        None => Err(String::from("User has no writer")),
+    }
+}
+
+// As send_to_user, but the message is dropped rather than waiting on a
+// full outbox, so a user who has stopped reading holds nobody else up
+pub async fn try_send_to_user(
+    config: &Config,
+    user: &Arc<User>,
+    message: Message,
+) -> Result<(), String> {
+    let buff = encode_for(config, message)?;
+    match user.outbox.lock().await.as_ref() {
+        Some(outbox) => outbox
+            .try_send(buff)
+            .map_err(|_| String::from("Outbox is full")),
+        None => Err(String::from("User has no writer")),
+    }
+}
+
+// Serialize a message for one user's outbox, sealing it if asked to. The
+// author's address is only for the server's own records, so it never goes
+// out to other clients
+fn encode_for(config: &Config, mut message: Message) -> Result<Vec<u8>, String> {
+    message.address.clear();
+    if config.frame_checksums {
+        message.seal();
+    }
+    frame::encode(&message, config.max_frame_size).map_err(|e| e.to_string())
+}
+
+// How deep the writer queue and the clients' outboxes are now and the
+// deepest they have been, for :stats
+async fn queue_report(state: &ServerState) -> String {
+    let users = state.roster.lock().await.users();
+    let mut deepest = 0;
+    for user in users {
+        if let Some(outbox) = user.outbox.lock().await.as_ref() {
+            deepest = deepest.max(outbox.max_capacity() - outbox.capacity());
+        }
+    }
+    format!(
+        "Queues (warning at {} of {QUEUE_CAPACITY}):\nwriter: {} now, {} at most\ndeepest outbox: {deepest} now, {} at most",
+        state.config.queue_high_water,
+        state.writer_depth.current(),
+        state.writer_depth.peak(),
+        state.outbox_depth.peak(),
+    )
+}
+
+// Who has been connected the longest, for :stats
+async fn session_report(state: &ServerState) -> String {
+    let now = Utc::now();
+    let users = state.roster.lock().await.users();
+    let mut longest: Option<(u64, &Arc<User>)> = None;
+    for user in &users {
+        let secs = user.session_secs(now).await;
+        if longest.is_none_or(|(most, _)| secs > most) {
+            longest = Some((secs, user));
+        }
+    }
+    match longest {
+        Some((secs, user)) => format!(
+            "Longest session: {}, connected for {}",
+            user.get_display_name().await,
+            format_duration(secs)
+        ),
+        None => String::from("Longest session: nobody is connected"),
+    }
+}
+
+// Send a plain text notice from the server to a single user
+pub async fn notify_user(config: &Config, user: &Arc<User>, text: String) -> Result<(), String> {
+    let message = Message::from_server(text, MessageKind::ServerBroadcast);
+    send_to_user(config, user, message).await
+}
+
+// Write the whole buffer to the user's socket
+pub async fn write_to_socket(user: &User, buff: &[u8]) -> io::Result<()> {
+    let mut writer = user.writer.lock().await;
+    let writer = writer
+        .as_mut()
+        .ok_or_else(|| io::Error::from(ErrorKind::NotConnected))?;
+
+    writer.write_all(buff).await?;
+    writer.flush().await
+}
+
+// Add the user to a channel. Whether a missing channel is created for
+// them depends on the config, though admins can always create one.
+// Channels created on demand go away once they are empty, but one an
+// admin had to create is kept
+pub async fn join_channel(state: &ServerState, user: &Arc<User>, channel: &str) -> JoinOutcome {
+    let is_admin = *user.role.lock().await == Role::Admin;
+    let may_create = state.config.auto_create_channels || is_admin;
+    let max_channels = state.config.max_channels_per_user;
+    let mut roster = state.roster.lock().await;
+    let outcome = roster.join(user, channel, may_create, max_channels);
+    if outcome == JoinOutcome::Created && !state.config.auto_create_channels {
+        roster.keep(channel);
+    }
+    outcome
+}
+
+// Remove the user from a channel, returning false if they weren't in it
+pub async fn leave_channel(state: &ServerState, user: &Arc<User>, channel: &str) -> bool {
+    state.roster.lock().await.leave(user, channel)
+}
+
+// How much longer the user has to wait before they can use the command
+// again, or None if they can use it now. Using it restarts the cooldown
+async fn cooldown_remaining(config: &Config, user: &User, command: &Command) -> Option<Duration> {
+    let cooldown = Duration::from_millis(*config.command_cooldowns_ms.get(command.name())?);
+    let mut last_commands = user.last_commands.lock().await;
+    let now = Instant::now();
+    if let Some(last) = last_commands.get(command.name()) {
+        let elapsed = now.duration_since(*last);
+        if elapsed < cooldown {
+            return Some(cooldown - elapsed);
+        }
+    }
+    last_commands.insert(command.name().to_string(), now);
+    None
+}
+
+// Record what an admin did, and to whom, for anyone reviewing the logs later.
+// These go to their own "audit" target so they can be filtered out of the rest
+async fn audit(admin: &User, command: &Command, target: Option<&str>) {
+    let name = admin.get_display_name().await;
+    info!(
+        target: "audit",
+        admin = %name,
+        address = %admin.client.address,
+        command = command.name(),
+        target = target.unwrap_or_default(),
+        "Admin action"
+    );
 }
 
 // Process a command string sent from the client
-// Currently only returns OK, but error handling should be added
-pub async fn process_command(command: Vec<u8>, user: &Arc<User>) -> Result<(), String> {
-    if let Ok(command) = String::from_utf8(command) {
-        let args: Vec<&str> = command.split_whitespace().collect();
-        if let Some(c) = args.first() {
-            match *c {
-                ":quit" => {
-                    let mut is_active = user.is_active.lock().await;
-                    *is_active = false;
+pub async fn process_command(
+    command: Vec<u8>,
+    user: &Arc<User>,
+    tx: &Sender<Message>,
+    state: &ServerState,
+) -> Result<(), String> {
+    // Commands come straight off the network, so tell the user rather than
+    // silently dropping anything that isn't valid UTF-8
+    let command = match String::from_utf8(command) {
+        Ok(command) => command,
+        Err(_) => {
+            let reply = String::from("Commands must be valid UTF-8");
+            return notify_user(&state.config, user, reply).await;
+        }
+    };
+
+    // The client checks commands before sending them, but the server has
+    // the final say, so tell the user exactly what was wrong
+    let command = match Command::parse(&command, state.config.prefix) {
+        Ok(command) => command,
+        Err(e @ ParseError::UnknownCommand(_)) => {
+            let reply = format!("{e}, type {}help to see the commands", state.config.prefix);
+            return notify_user(&state.config, user, reply).await;
+        }
+        Err(e) => return notify_user(&state.config, user, e.to_string()).await,
+    };
+
+    // Operators can turn commands off, but users must always be able to leave
+    if command != Command::Quit
+        && state
+            .config
+            .disabled_commands
+            .iter()
+            .any(|name| name == command.name())
+    {
+        return notify_user(&state.config, user, String::from("command disabled")).await;
+    }
+
+    // Admins may need to act quickly, so cooldowns don't hold them back
+    let is_admin = *user.role.lock().await == Role::Admin;
+    if !is_admin && let Some(wait) = cooldown_remaining(&state.config, user, &command).await {
+        let reply = format!(
+            "Please wait {:.1}s before using {}{} again",
+            wait.as_secs_f64(),
+            state.config.prefix,
+            command.name()
+        );
+        return notify_user(&state.config, user, reply).await;
+    }
+
+    match command {
+        Command::Quit => user.deactivate(DisconnectReason::Quit).await,
+        // A client known by its certificate keeps the name on it
+        Command::Name(name)
+            if user
+                .certified_name
+                .lock()
+                .await
+                .as_ref()
+                .is_some_and(|certified| name.as_ref() != Some(certified)) =>
+        {
+            let reply = String::from("Your nickname comes from your certificate");
+            notify_user(&state.config, user, reply).await?;
+        }
+        Command::Name(None) => *user.nick_name.lock().await = None,
+        Command::Name(Some(name)) if let Err(e) = User::validate_nickname(&name, &state.config) => {
+            notify_user(&state.config, user, e.to_string()).await?;
+        }
+        Command::Name(Some(name)) => match claim_nick_name(state, user, name.clone()).await {
+            true => {
+                let mut ack = Message::from_server(String::new(), MessageKind::Ack);
+                ack.author = Some(name);
+                send_to_user(&state.config, user, ack).await?;
+                announce_arrival(state, user).await;
+            }
+            false => {
+                let reply = String::from("nickname taken");
+                notify_user(&state.config, user, reply).await?;
+            }
+        },
+        Command::Resume(token) => {
+            // Whoever this session belonged to never really left, so don't
+            // tell anyone they did, or that they are back, and count the
+            // session from when it first began
+            let departure = state.departures.lock().await.remove(&token);
+            if let Some(departure) = departure {
+                *user.announced.lock().await = departure.announced;
+                *user.connected_at.lock().await = departure.connected_at;
+            } else if let Some(old) = session_holder(state, user, &token).await {
+                // After a network blip the old connection may not have been
+                // noticed to be gone yet. It is closed in favour of this one,
+                // which frees up its nickname for us
+                *user.announced.lock().await = *old.announced.lock().await;
+                *user.connected_at.lock().await = *old.connected_at.lock().await;
+                old.deactivate(DisconnectReason::Resumed).await;
+            }
+        }
+        Command::Info(name) => {
+            let reply = match find_user(state, &name).await {
+                // Only admins get to see the raw address of other users
+                Some(target) => {
+                    let is_admin = *user.role.lock().await == Role::Admin;
+                    target.info(is_admin).await.to_string()
+                }
+                None => match state.storage.get_member(&name).await {
+                    Ok(Some(record)) => format!(
+                        "{name} is not connected, last seen {}s ago",
+                        unix_secs().saturating_sub(record.last_seen_secs)
+                    ),
+                    _ => format!("No user named {name} is connected"),
+                },
+            };
+            notify_user(&state.config, user, reply).await?;
+        }
+        Command::List => {
+            let reply = format!(
+                "Connected users: {}",
+                connected_names(state, user).await.join(", ")
+            );
+            notify_user(&state.config, user, reply).await?;
+        }
+        Command::Whoami => {
+            // This reply is written after the counts are taken, so it isn't
+            // included in them
+            let reply = format!(
+                "You are {} ({}), connected for {}, {} frames received from you, {} sent to you",
+                user.get_display_name().await,
+                user.client.id,
+                format_duration(user.session_secs(Utc::now()).await),
+                user.frames_received.load(Ordering::Relaxed),
+                user.frames_sent.load(Ordering::Relaxed),
+            );
+            notify_user(&state.config, user, reply).await?;
+        }
+        Command::History(count) => {
+            // Never send more than the operator allows, however many were asked for
+            let limit = state.config.max_history_request;
+            let count = count.unwrap_or(limit);
+            if count > limit {
+                let reply = format!("Only the last {limit} messages can be shown");
+                notify_user(&state.config, user, reply).await?;
+            }
+            let recent = state.history.lock().await.recent_global(count.min(limit));
+            for message in recent {
+                send_to_user(&state.config, user, message).await?;
+            }
+        }
+        // These only change how the client behaves
+        Command::Timestamps(_) | Command::Reconnect | Command::Prefix | Command::Commands => (),
+        // Unless the server enforces ignores, the client handles them itself
+        Command::Ignore(_) | Command::Unignore(_)
+            if state.config.ignore_mode != IgnoreMode::Server => {}
+        // Ignores are kept by id, so only those still connected can be named
+        Command::Ignore(None) => {
+            let ignored = user.ignored.lock().await.clone();
+            let users = state.roster.lock().await.users();
+            let mut names = Vec::new();
+            for other in users {
+                if ignored.contains(&other.client.id) {
+                    names.push(other.get_display_name().await);
+                }
+            }
+            let reply = match names.is_empty() {
+                true => String::from("You aren't ignoring anyone"),
+                false => format!("Ignoring: {}", names.join(", ")),
+            };
+            notify_user(&state.config, user, reply).await?;
+        }
+        Command::Ignore(Some(ref name)) => {
+            let reply = match find_user(state, name).await {
+                Some(target) => {
+                    user.ignored.lock().await.insert(target.client.id.clone());
+                    ack_ignore(state, user, &command, &target).await?;
+                    format!("The server will no longer send you messages from {name}")
                 }
-                ":name" => {
-                    let mut nickname = user.nick_name.lock().await;
+                None => format!("No user named {name} is connected"),
+            };
+            notify_user(&state.config, user, reply).await?;
+        }
+        Command::Unignore(ref name) => {
+            let reply = match find_user(state, name).await {
+                Some(target) => {
+                    user.ignored.lock().await.remove(&target.client.id);
+                    ack_ignore(state, user, &command, &target).await?;
+                    format!("The server will send you messages from {name} again")
+                }
+                None => format!("No user named {name} is connected"),
+            };
+            notify_user(&state.config, user, reply).await?;
+        }
+        Command::Config => {
+            if *user.role.lock().await != Role::Admin {
+                let reply = String::from("Only admins can see the config");
+                return notify_user(&state.config, user, reply).await;
+            }
+            audit(user, &command, None).await;
+            let reply = ron::ser::to_string_pretty(&state.config.redacted(), PrettyConfig::new())
+                .map_err(|e| e.to_string())?;
+            notify_user(&state.config, user, reply).await?;
+        }
+        Command::Help => {
+            let reply = Command::help(state.config.prefix, &state.config.disabled_commands);
+            notify_user(&state.config, user, reply).await?;
+        }
+        Command::Stats => {
+            let reply = match *user.role.lock().await {
+                Role::Admin => {
+                    audit(user, &command, None).await;
+                    format!(
+                        "{}\n{}\n{}",
+                        state.message_sizes.report(),
+                        queue_report(state).await,
+                        session_report(state).await
+                    )
+                }
+                Role::Member => String::from("Only admins can see the stats"),
+            };
+            notify_user(&state.config, user, reply).await?;
+        }
+        Command::Kick {
+            ref nick,
+            ref reason,
+        } => {
+            if *user.role.lock().await != Role::Admin {
+                let reply = String::from("Only admins can kick users");
+                return notify_user(&state.config, user, reply).await;
+            }
+            audit(user, &command, Some(nick)).await;
 
-                    match args.len() {
-                        n if n <= 1 => *nickname = None,
-                        _ => *nickname = Some(args[1].to_string()),
-                    }
+            let reply = match find_user(state, nick).await {
+                Some(target) => {
+                    let admin = user.get_display_name().await;
+                    let notice = match reason {
+                        Some(reason) => format!("You were kicked by {admin}: {reason}"),
+                        None => format!("You were kicked by {admin}"),
+                    };
+
+                    // Queue the notice before deactivating so the target's
+                    // writer flushes it before the connection closes
+                    let _ = notify_user(&state.config, &target, notice).await;
+                    target.deactivate(DisconnectReason::Kicked).await;
+                    format!("Kicked {nick}")
                 }
-                // Should message user that the command was not recognized
-                _ => (),
+                None => format!("No user named {nick} is connected"),
+            };
+            notify_user(&state.config, user, reply).await?;
+        }
+        Command::Reply { id, text } => {
+            if state.history.lock().await.get(id).is_none() {
+                let reply = format!("No message with id {id}");
+                return notify_user(&state.config, user, reply).await;
+            }
+
+            let kind = MessageKind::Message;
+            relay_message(text, kind, Some(id), Destination::Global, user, tx, state).await?;
+        }
+        Command::Msg { nick, text } => match find_user(state, &nick).await {
+            Some(target) => {
+                let destination = direct_to(&target.client);
+                let kind = MessageKind::Message;
+                relay_message(text, kind, None, destination, user, tx, state).await?;
             }
+            None => {
+                let reply = format!("No user named {nick} is connected");
+                notify_user(&state.config, user, reply).await?;
+            }
+        },
+        // The client sends actions as messages of their own kind, but one
+        // typed as a command is just as good, and goes to everyone
+        Command::Me(action) => {
+            let kind = MessageKind::Action;
+            relay_message(action, kind, None, Destination::Global, user, tx, state).await?;
+        }
+        Command::Join(channel) => {
+            let outcome = join_channel(state, user, &channel).await;
+            let joined = state.roster.lock().await.channel(&channel);
+            if let Some(joined) = joined.filter(|_| outcome.is_member()) {
+                let mut ack = Message::from_server(String::new(), MessageKind::Ack);
+                ack.channel = Destination::Channel(joined);
+                send_to_user(&state.config, user, ack).await?;
+            }
+            let reply = match outcome {
+                JoinOutcome::Created => {
+                    // Let everyone know there is somewhere new to talk
+                    let name = user.get_display_name().await;
+                    announce(state, format!("{name} created {channel}")).await;
+                    format!("Created {channel}, which you own")
+                }
+                JoinOutcome::Joined => format!("Joined {channel}"),
+                JoinOutcome::AlreadyMember => format!("Already in {channel}"),
+                JoinOutcome::NoSuchChannel => format!("No channel named {channel}"),
+                JoinOutcome::TooManyChannels => {
+                    format!("You can't join {channel}, you are already in too many channels")
+                }
+            };
+            notify_user(&state.config, user, reply).await?;
+        }
+        Command::Leave(channel) => {
+            let reply = match leave_channel(state, user, &channel).await {
+                true => format!("Left {channel}"),
+                false => format!("Not in {channel}"),
+            };
+            notify_user(&state.config, user, reply).await?;
+        }
+        Command::Channels => {
+            let channels = state.roster.lock().await.channels();
+            let reply = match channels.is_empty() {
+                true => String::from("There are no channels"),
+                false => {
+                    let listed: Vec<String> = channels
+                        .into_iter()
+                        .map(|(name, members)| format!("{name} ({members})"))
+                        .collect();
+                    format!("Channels: {}", listed.join(", "))
+                }
+            };
+            notify_user(&state.config, user, reply).await?;
         }
     }
     Ok(())
 }
 
 // Handle the writing to the attached clients
-// Reads from the thread receiver and writes using the clients vec
-pub async fn handle_writes(config: Arc<Config>, mut rx: Receiver<String>, clients: Clients) {
+// Reads from the thread receiver and writes to whoever the roster says
+// the message is for
+pub async fn handle_writes(state: Arc<ServerState>, mut rx: Receiver<Message>) {
+    let mut high_water = HighWater::new(state.config.queue_high_water);
+
     // Exit if our receiver is closed
     while let Some(message) = rx.recv().await {
-        let guard = clients.lock().await;
-        for client in guard.iter() {
-            let mut buff = message.clone().into_bytes();
-            buff.resize(config.msg_size as usize, 0);
+        let depth = rx.len();
+        state.writer_depth.record(depth);
+        match high_water.check(depth) {
+            Some(QueueAlert::Backlogged) => {
+                warn!(
+                    depth,
+                    "Writer queue is backing up, the server can't keep up"
+                )
+            }
+            Some(QueueAlert::CaughtUp) => info!("Writer queue has caught up"),
+            None => (),
+        }
 
-            if let Some(socket) = client.socket.as_ref()
-                && socket.try_write(&buff).is_err()
+        // Keep a transcript of everything said to everyone. Drop the line
+        // rather than wait if the disk is falling behind
+        if matches!(message.channel, Destination::Global)
+            && let Some(log) = &state.history_log
+            && log.try_send(message.clone()).is_err()
+        {
+            warn!("Dropping message from the history log, it is falling behind");
+        }
+
+        let buff = match encode_for(&state.config, message.clone()) {
+            Ok(buff) => buff,
+            Err(e) => {
+                error!(error = %e, "Could not serialize message");
+                continue;
+            }
+        };
+
+        let targets = state
+            .roster
+            .lock()
+            .await
+            .broadcast_targets(&message.channel, message.author_id.as_deref())
+            .await;
+        // Nobody to send it to isn't the same as failing to send it. The
+        // message is already in the history either way
+        if targets.is_empty() {
+            debug!(id = ?message.id, "Relayed message to nobody, no one is connected to get it");
+        }
+        for client in targets {
+            // Anyone who joined after this message was accepted has it
+            // from the history replay already
+            if message
+                .id
+                .is_some_and(|id| id < client.live_from.load(Ordering::Relaxed))
             {
                 continue;
             }
+            let buff = buff.clone();
+
+            // Hand the message to the client's own writer so a slow client
+            // can't hold up everyone else
+            if let Some(outbox) = client.outbox.lock().await.as_ref()
+                && outbox.try_send(buff).is_err()
+            {
+                warn!(address = %client.client.address, "Dropping message, outbox is full");
+            }
         }
     }
 }
 
-// Read messages from our client, parse them and where appropriate
-// put send to the writer thread
-pub async fn handle_client(
-    config: Arc<Config>,
+// Handle the writing to a single client
+// Reads from the client's outbox and writes to its socket, pacing itself
+// to the configured bandwidth limit. Exits once the outbox is closed
+pub async fn handle_user_writes(
+    state: Arc<ServerState>,
     user: Arc<User>,
-    tx: Sender<String>,
-    clients: Clients,
+    mut rx: Receiver<Vec<u8>>,
 ) {
-    println!("Starting thread for {}", user.client.address);
-    let mut buffer = vec![0; config.msg_size as usize];
+    let mut bucket = state
+        .config
+        .bandwidth_limit_bps
+        .map(|bps| TokenBucket::new(bps, bps));
+    let mut high_water = HighWater::new(state.config.queue_high_water);
 
-    loop {
-        {
-            let is_active = user.is_active.lock().await;
-            if !*is_active {
-                break;
+    while let Some(buff) = rx.recv().await {
+        let depth = rx.len();
+        state.outbox_depth.record(depth);
+        match high_water.check(depth) {
+            Some(QueueAlert::Backlogged) => {
+                warn!(address = %user.client.address, depth, "Outbox is backing up, the client can't keep up")
+            }
+            Some(QueueAlert::CaughtUp) => {
+                info!(address = %user.client.address, "Outbox has caught up")
             }
+            None => (),
+        }
+
+        if let Some(bucket) = bucket.as_mut() {
+            bucket.pace(buff.len() as u64).await;
+        }
+
+        if let Err(e) = write_to_socket(&user, &buff).await {
+            warn!(address = %user.client.address, error = %e, "Write to client failed");
+            break;
+        }
+        user.frames_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Nothing more will be written, so let the client see the connection close
+    if let Some(mut writer) = user.writer.lock().await.take() {
+        let _ = writer.shutdown().await;
+    }
+}
+
+// Read messages from our client, parse them and where appropriate
+// put send to the writer thread
+pub async fn handle_client(state: Arc<ServerState>, user: Arc<User>, tx: Sender<Message>) {
+    info!(address = %user.client.address, "Starting thread");
+    let Some(reader) = user.reader.lock().await.take() else {
+        warn!(address = %user.client.address, "Someone else is already reading from this client");
+        return;
+    };
+    // Bytes are kept until a whole frame has arrived, however TCP splits
+    // them up, and nothing is lost if the read is cut short by a disconnect
+    let mut reader = FrameReader::new(reader, state.config.max_frame_size);
+    let mut bucket = state
+        .config
+        .bandwidth_limit_bps
+        .map(|bps| TokenBucket::new(bps, bps));
+
+    let reason = loop {
+        // Whoever ended the session said why
+        if !*user.is_active.lock().await {
+            let reason = *user.disconnect_reason.lock().await;
+            break reason.unwrap_or(DisconnectReason::Closed);
         }
 
-        let socket = user.socket.as_ref().unwrap();
-        socket.readable().await.expect("Failed to check socket");
-        let message = match user.socket.as_ref().unwrap().try_read(&mut buffer) {
-            Ok(_) => get_message_from_buffer(&buffer),
-            Err(ref err) if err.kind() == ErrorKind::WouldBlock => continue,
+        // Wait for a whole message, or for someone else (like an admin) to
+        // disconnect us
+        let payload = tokio::select! {
+            result = reader.next_frame_bytes() => result,
+            _ = user.disconnect.notified() => continue,
+        };
+        let payload = match payload {
+            Ok(payload) => payload,
+            Err(FrameError::Closed) => break DisconnectReason::Closed,
+            Err(e @ FrameError::TooLarge { .. }) => {
+                warn!(address = %user.client.address, error = %e, "Read from client failed");
+                break DisconnectReason::ProtocolError;
+            }
             Err(e) => {
-                eprintln!("{e}");
-                break;
+                warn!(address = %user.client.address, error = %e, "Read from client failed");
+                break DisconnectReason::NetworkError;
             }
         };
+        if let Some(bucket) = bucket.as_mut() {
+            bucket
+                .pace((frame::HEADER_LEN + payload.len()) as u64)
+                .await;
+        }
+        // They are clearly still there
+        user.frames_received.fetch_add(1, Ordering::Relaxed);
+        *user.missed_pings.lock().await = 0;
 
-        let message = match message {
+        let mut message = match frame::decode(&payload) {
             Ok(m) => m,
+            // Nothing was said, so there is nothing to answer
+            Err(FrameError::Empty) => continue,
+            // Only this message was damaged, the ones after it are fine
+            Err(e @ FrameError::Corrupt { .. }) => {
+                warn!(address = %user.client.address, error = %e, "Dropping corrupt message");
+                continue;
+            }
             Err(e) => {
-                eprintln!("{e}");
-                break;
+                warn!(address = %user.client.address, error = %e, "Could not parse message");
+                break DisconnectReason::ProtocolError;
             }
         };
 
+        // The client may claim to be anyone, but we know where it really is
+        message.address = user.client.address.clone();
+
         // if the contents of msg match the command string, run process_command
         let message_result = match message.kind {
-            MessageKind::Command => process_command(message.content, &user).await,
-            MessageKind::Message => send_message(message.content, &user, &tx).await,
-            MessageKind::ServerBroadcast => continue,
+            MessageKind::Command => process_command(message.content, &user, &tx, &state).await,
+            MessageKind::Message | MessageKind::Action => {
+                let kind = message.kind;
+                send_message(message.content, kind, message.channel, &user, &tx, &state).await
+            }
+            MessageKind::Pong => continue,
+            MessageKind::Unknown => {
+                warn!(address = %user.client.address, "Dropping message of a kind this server doesn't know");
+                continue;
+            }
+            // Clients don't get to speak for the server
+            MessageKind::ServerBroadcast | MessageKind::Ack | MessageKind::Ping => {
+                warn!(address = %user.client.address, kind = ?message.kind, "Dropping message of a kind only the server sends");
+                continue;
+            }
         };
 
         if let Err(e) = message_result {
-            eprintln!("{e}");
-            break;
+            warn!(address = %user.client.address, error = %e, "Could not handle message");
+            break DisconnectReason::NetworkError;
         }
-    }
+    };
 
     // if we get here, indicate we are closing the connection and remove
     // the client from the client's list
-    println!("closing connection with: {}", user.client.address);
-    remove_client(clients, user).await;
+    info!(address = %user.client.address, %reason, "Closing connection");
+    remove_client(&state, user, reason).await;
 }
 
 // function that removes the associated client from the client's list
-pub async fn remove_client(clients: Clients, user: Arc<User>) {
-    let mut remove_index: Option<usize> = None;
-    let mut guard = clients.lock().await;
-    for (index, client) in guard.iter().enumerate() {
-        if Arc::ptr_eq(client, &user) {
-            remove_index = Some(index);
-            break;
+// and from every channel they were a member of
+pub async fn remove_client(state: &Arc<ServerState>, user: Arc<User>, reason: DisconnectReason) {
+    // Closing the outbox lets the user's writer flush what is queued and exit
+    user.outbox.lock().await.take();
+
+    state.roster.lock().await.remove(&user);
+
+    announce_departure(state, &user, reason).await;
+
+    // Remember who they were, so :info can say when they were last around
+    if let Some(nick_name) = user.nick_name.lock().await.clone() {
+        let record = MemberRecord {
+            nick_name,
+            role: *user.role.lock().await,
+            last_seen_secs: unix_secs(),
+        };
+        if let Err(e) = state.storage.put_member(record).await {
+            error!(error = %e, "Could not store member record");
         }
     }
-    if let Some(index) = remove_index {
-        guard.remove(index);
+}
+
+// The user, other than the one asking, whose session the token belongs to
+async fn session_holder(state: &ServerState, asking: &Arc<User>, token: &str) -> Option<Arc<User>> {
+    let users = state.roster.lock().await.users();
+    users
+        .into_iter()
+        .find(|user| user.session_token == token && !Arc::ptr_eq(user, asking))
+}
+
+// Give the user the nickname unless another connected user already goes
+// by it, ignoring case. The roster stays locked from the check until the
+// name is set, so two users can't claim the same name at once
+async fn claim_nick_name(state: &ServerState, user: &Arc<User>, name: String) -> bool {
+    let roster = state.roster.lock().await;
+    for other in roster.users() {
+        if Arc::ptr_eq(&other, user) || !*other.is_active.lock().await {
+            continue;
+        }
+        let taken = other
+            .nick_name
+            .lock()
+            .await
+            .as_ref()
+            .is_some_and(|nick_name| same_name(nick_name, &name));
+        if taken {
+            return false;
+        }
+    }
+    *user.nick_name.lock().await = Some(name);
+    true
+}
+
+// Send a notice from the server to everyone connected. It is kept in the
+// history too, so anyone who joins later, even when nobody is around to
+// hear it now, can still catch up on it
+pub async fn announce(state: &ServerState, text: String) {
+    let notice = Message::from_server(text, MessageKind::ServerBroadcast);
+    // Anyone joining either gets it in their replay or is on the roster
+    // by the time we look, as join_roster holds the history lock too
+    let users = {
+        let mut history = state.history.lock().await;
+        history.push(notice.clone());
+        state.roster.lock().await.users()
+    };
+    for user in users {
+        offer_notice(&state.config, &user, notice.clone()).await;
+    }
+}
+
+// Send a notice from the server to everyone connected but the user it is about
+async fn announce_to_others(state: &ServerState, about: &Arc<User>, text: String) {
+    let notice = Message::from_server(text, MessageKind::ServerBroadcast);
+    let users = state.roster.lock().await.users();
+    for user in users.iter().filter(|user| !Arc::ptr_eq(user, about)) {
+        offer_notice(&state.config, user, notice.clone()).await;
+    }
+}
+
+// Queue a notice meant for everyone on one user's outbox. One who has
+// stopped reading misses it, as with any other relayed message, rather
+// than holding up the notice for everyone else, and whoever it is about
+async fn offer_notice(config: &Config, user: &Arc<User>, notice: Message) {
+    if let Err(e) = try_send_to_user(config, user, notice).await {
+        warn!(address = %user.client.address, error = %e, "Dropping notice");
+    }
+}
+
+// Tell everyone else the user has arrived, the first time they give a name.
+// The user already knows, so it isn't echoed back to them
+async fn announce_arrival(state: &ServerState, user: &Arc<User>) {
+    if std::mem::replace(&mut *user.announced.lock().await, true) || !state.config.presence_notices
+    {
+        return;
+    }
+    let notice = format!("{} joined", user.get_display_name().await);
+    announce_to_others(state, user, notice).await;
+}
+
+// Hold on to the user's session for the grace period, so they can
+// reconnect and resume it, then tell everyone they have left. A resumed
+// session is taken out of the departures, and then nothing is said. The
+// reason they left is only given when the operator asks for it
+async fn announce_departure(state: &Arc<ServerState>, user: &User, reason: DisconnectReason) {
+    // The session carries on over another connection
+    if reason == DisconnectReason::Resumed {
+        return;
+    }
+    let token = user.session_token.clone();
+    let departure = Departure {
+        name: user.get_display_name().await,
+        connected_at: *user.connected_at.lock().await,
+        announced: *user.announced.lock().await,
+    };
+    state
+        .departures
+        .lock()
+        .await
+        .insert(token.clone(), departure);
+
+    let grace = Duration::from_secs(state.config.reconnect_grace_secs);
+    let state = Arc::clone(state);
+    tokio::spawn(async move {
+        sleep(grace).await;
+        let departure = state.departures.lock().await.remove(&token);
+        let name = match departure {
+            Some(departure) if departure.announced && state.config.presence_notices => {
+                departure.name
+            }
+            _ => return,
+        };
+        let notice = match state.config.departure_reasons {
+            true => format!("{name} left ({reason})"),
+            false => format!("{name} left"),
+        };
+        announce(&state, notice).await;
+    });
+}
+
+// Seconds since the Unix epoch, for records that outlive the server
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default()
+}
+
+// Whether the user may send another chat message right now. Always true
+// unless the operator has set a rate limit, and always true for admins, who
+// may need to get a word in quickly
+async fn within_message_limit(config: &Config, user: &User) -> bool {
+    let Some(per_sec) = config.rate_limit_per_sec else {
+        return true;
+    };
+    if *user.role.lock().await == Role::Admin {
+        return true;
+    }
+    let mut limit = user.message_limit.lock().await;
+    limit
+        .get_or_insert_with(|| TokenBucket::new(config.rate_limit_burst, per_sec))
+        .try_take(1)
+}
+
+// Tell the user their message was dropped for being over the limit, but no
+// more than once a second so the warnings aren't a flood of their own
+async fn warn_over_limit(config: &Config, user: &Arc<User>) -> Result<(), String> {
+    {
+        let mut warned_at = user.limit_warned_at.lock().await;
+        if warned_at.is_some_and(|at| at.elapsed() < Duration::from_secs(1)) {
+            return Ok(());
+        }
+        *warned_at = Some(Instant::now());
     }
+    let reply = String::from("You are sending messages too fast, some were dropped");
+    notify_user(config, user, reply).await
 }
 
 // Sends messages on our sender to our writer thread
 pub async fn send_message(
     message: Vec<u8>,
+    kind: MessageKind,
+    destination: Destination,
     user: &Arc<User>,
-    tx: &Sender<String>,
+    tx: &Sender<Message>,
+    state: &ServerState,
 ) -> Result<(), String> {
-    if let Ok(message) = String::from_utf8(message) {
-        let message = format!("{}: {}", user.get_display_name().await, message);
-        let message = message.replace('"', "");
-
-        if tx.send(message).await.is_err() {
-            eprintln!("closing connection with: {}", user.get_display_name().await);
-            return Err(String::from("Failed to write message"));
+    // Don't relay a private message nobody will receive, and address it
+    // to the client as we know it rather than as the sender described it
+    let destination = match destination {
+        Destination::Direct(client) => match state.roster.lock().await.client(&client.id) {
+            Some(client) => direct_to(&client),
+            None => {
+                let reply = String::from("No such user is connected");
+                return notify_user(&state.config, user, reply).await;
+            }
+        },
+        // Only members may talk in a channel. It goes out under the name it
+        // was created with, whatever case the sender typed it in
+        Destination::Channel(channel) => {
+            let name = channel.display_name();
+            let found = {
+                let roster = state.roster.lock().await;
+                roster
+                    .in_channel(user, name)
+                    .then(|| roster.channel(name))
+                    .flatten()
+            };
+            match found {
+                Some(channel) => Destination::Channel(channel),
+                None => return notify_user(&state.config, user, format!("Not in {name}")).await,
+            }
         }
+        destination => destination,
+    };
+
+    // The text travels as bytes inside the frame, so quotes and anything
+    // else the user typed can be passed on untouched. Line endings are the
+    // exception, as other systems would show a stray \r
+    if let Ok(message) = String::from_utf8(message) {
+        let message = match state.config.normalize_newlines {
+            true => normalize_newlines(&message),
+            false => message,
+        };
+        relay_message(message, kind, None, destination, user, tx, state).await?;
     };
     Ok(())
 }
+
+// Turn Windows and old Mac line endings into plain newlines. The lines
+// themselves are kept, so a multiline message stays multiline
+fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+// Give the text an id, record it in the history unless it is private and
+// pass it on to the writer thread, then acknowledge it to the sender with
+// the id it was assigned
+pub async fn relay_message(
+    text: String,
+    kind: MessageKind,
+    in_reply_to: Option<u64>,
+    destination: Destination,
+    user: &Arc<User>,
+    tx: &Sender<Message>,
+    state: &ServerState,
+) -> Result<(), String> {
+    if !within_message_limit(&state.config, user).await {
+        return warn_over_limit(&state.config, user).await;
+    }
+    state.message_sizes.record(text.len());
+
+    // Point newcomers at something useful, but only the once
+    let first_message = {
+        let mut has_sent_message = user.has_sent_message.lock().await;
+        !std::mem::replace(&mut *has_sent_message, true)
+    };
+    if first_message && let Some(hint) = &state.config.first_message_hint {
+        notify_user(&state.config, user, hint.to_string()).await?;
+    }
+
+    let author = user.get_display_name().await;
+
+    // The history stays locked until the message is queued for the writer,
+    // so ids, the history and what the writer sees are all in one order,
+    // which join_roster relies on
+    let mut history = state.history.lock().await;
+    let id = state.assign_message_id();
+
+    // Chat content stays out of the logs unless the operator opts in
+    if state.config.log_chat_content {
+        info!(id, %author, content = %text, "Message");
+    }
+
+    let mut outgoing = Message::from_string(Arc::clone(&user.client), text, kind);
+    outgoing.id = Some(id);
+    outgoing.author = Some(author.clone());
+    outgoing.author_id = Some(user.client.id.clone());
+    outgoing.in_reply_to = in_reply_to;
+    outgoing.channel = destination;
+    outgoing.timestamp = Utc::now();
+
+    // A private message is only for the two of them, so it is kept out
+    // of the history and storage, where others could get at it
+    let private = matches!(outgoing.channel, Destination::Direct(_));
+    if !private {
+        history.push(outgoing.clone());
+    }
+    let sent = tx.send(outgoing.clone()).await;
+    drop(history);
+
+    // The disk is left until the history is free again, as nothing else
+    // has to wait on it
+    if !private && let Err(e) = state.storage.append_message(&outgoing).await {
+        error!(error = %e, "Could not store message");
+    }
+    if sent.is_err() {
+        warn!(name = %author, "Closing connection");
+        // Their own writer still works, so let them know to send it again
+        // once they have reconnected
+        let notice = String::from("Your message could not be delivered, please send it again");
+        let _ = notify_user(&state.config, user, notice).await;
+        return Err(String::from("Failed to write message"));
+    }
+
+    let mut ack = Message::from_server(String::new(), MessageKind::Ack);
+    ack.id = Some(id);
+    ack.author_id = Some(user.client.id.clone());
+    send_to_user(&state.config, user, ack).await
+}
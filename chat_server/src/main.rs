@@ -1,16 +1,45 @@
 use chat_server::*;
-use chat_shared::{Config, User};
-use std::{env::args, process, sync::Arc};
-use tokio::{net::TcpListener, sync::Mutex, sync::mpsc::channel};
+use chat_shared::{
+    Config,
+    cli::{self, CliArgs, Subcommand},
+};
+use std::{env::args, future::pending, process, sync::Arc};
+use tokio::{net::TcpListener, signal};
+use tracing::{error, info};
 
 #[tokio::main]
 async fn main() {
-    // Get the config file path from the command line arguments
-    // Then match the result and either load the config from the file the path supplied or request default.
-    let config = match chat_shared::get_config_path(args()) {
-        Some(config) => Config::from_path(Some(config.as_ref())),
-        None => Config::from_path(None),
-    };
+    // Read the command line once, up front
+    let cli = CliArgs::parse(args()).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(1);
+    });
+
+    match cli.subcommand {
+        Some(Subcommand::Help) => {
+            println!("{}", cli::usage("chat_server"));
+            return;
+        }
+        Some(Subcommand::Version) => {
+            println!("chat_server {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        // The selftest subcommand runs a round trip against an in-process server and exits
+        Some(Subcommand::Selftest) => {
+            match selftest::run().await {
+                Ok(()) => println!("PASS"),
+                Err(e) => {
+                    println!("FAIL: {e}");
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+        None => (),
+    }
+
+    // Load the config from the path supplied on the command line or request default.
+    let config = Config::from_path(cli.config_path.as_deref());
 
     // If the config is not valid, print the error and exit.
     let config = match config {
@@ -21,51 +50,50 @@ async fn main() {
         }
     };
 
-    // If the config is valid, get the ip address and port from the config.
-    // If the ip address is not valid, print the error and exit.
-    let host_ip = config.get_ip().unwrap_or_else(|_| {
-        eprintln!("Invalid IP address");
+    // If the config is valid, get the addresses to listen on from it. When
+    // both an IPv4 and an IPv6 address are set we listen on both
+    let addresses = config.listen_addresses().unwrap_or_else(|e| {
+        eprintln!("{e}");
         process::exit(1);
     });
 
-    // Create the address string to connect to.
-    let address = format!("{}:{}", host_ip, config.host_port).replace('"', "");
-
-    // Create a shared config object to pass to our threads
-    let config = Arc::new(config);
-    // Set up our listener or die trying
-    let server = TcpListener::bind(&address)
-        .await
-        .expect("Listener failed to bind");
+    // Set up our listeners or die trying
+    let listeners: Vec<TcpListener> = addresses
+        .iter()
+        .map(|&address| {
+            bind_listener(address).unwrap_or_else(|e| {
+                eprintln!("Listener failed to bind {address}: {e}");
+                process::exit(1);
+            })
+        })
+        .collect();
 
-    println!("Server is listening on {}!", address);
-
-    // Create our list of clients Needs to be Arc of Mutex of Arcs
-    // so that the sent trait is respected throughout
-    let clients = Arc::new(Mutex::new(Vec::new()));
-    // set up the sender and receiver for our threads
-    let (tx, rx) = channel::<String>(32);
-
-    // spawn off our writer
-    tokio::spawn(handle_writes(Arc::clone(&config), rx, Arc::clone(&clients)));
-
-    // Loop until our listener fails
-    while let Ok((socket, addr)) = server.accept().await {
-        // log that a client connected
-        let addr = addr.to_string();
-        println!("Client {addr} connected");
+    init_logging(config.log_format, cli.verbosity);
+    for address in &addresses {
+        info!(%address, tls = config.tls.is_some(), "Server is listening");
+    }
 
-        // put our socket in an Arc so it can be shared
-        // and push it to the client's list
-        let user = Arc::new(User::from(socket, Some(addr)));
-        clients.lock().await.push(Arc::clone(&user));
+    // Create the state shared by our threads. It holds the config along with
+    // the client and channel lists, so it needs to be an Arc
+    let mut state = ServerState::new(config);
+    if let Err(e) = state.open_event_log().await {
+        eprintln!("Could not open the event log: {e}");
+        process::exit(1);
+    }
+    state.open_history_log();
+    if let Err(e) = state.load_tls() {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+    let state = Arc::new(state);
+    run_server(state, listeners, shutdown_signal()).await;
+}
 
-        // spawn off our client thread
-        tokio::spawn(handle_client(
-            Arc::clone(&config),
-            Arc::clone(&user),
-            tx.clone(),
-            Arc::clone(&clients),
-        ));
+// Resolves once the server is asked to stop with Ctrl-C. If that can't be
+// listened for, the server just runs until it is killed
+async fn shutdown_signal() {
+    if let Err(e) = signal::ctrl_c().await {
+        error!(error = %e, "Could not listen for Ctrl-C");
+        pending::<()>().await;
     }
 }
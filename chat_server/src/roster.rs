@@ -0,0 +1,256 @@
+use chat_shared::{
+    Client, User,
+    message::{Channel, Destination},
+};
+use std::{collections::HashMap, sync::Arc};
+
+// Everyone connected to the server and the channels they have joined.
+// Channels are keyed by their id, which is the name lowercased when
+// fold_case is set, so differently cased names reach the same channel
+#[derive(Default)]
+pub struct Roster {
+    users: Vec<Arc<User>>,
+    channels: HashMap<String, ChannelMembers>,
+    fold_case: bool,
+}
+
+// Who is in a channel, the name it was created with, and the client id of
+// whoever created it. A kept channel stays around when everyone has left,
+// the rest are dropped
+struct ChannelMembers {
+    display_name: String,
+    owner: String,
+    members: Vec<Arc<User>>,
+    kept: bool,
+}
+
+// What happened when a user asked to join a channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinOutcome {
+    Created,
+    Joined,
+    AlreadyMember,
+    NoSuchChannel,
+    TooManyChannels,
+}
+
+impl JoinOutcome {
+    // Whether the user is in the channel afterwards
+    pub fn is_member(self) -> bool {
+        matches!(
+            self,
+            JoinOutcome::Created | JoinOutcome::Joined | JoinOutcome::AlreadyMember
+        )
+    }
+}
+
+impl Roster {
+    pub fn new(fold_case: bool) -> Self {
+        Self {
+            fold_case,
+            ..Self::default()
+        }
+    }
+
+    // The key a channel name is looked up by
+    fn channel_id(&self, channel: &str) -> String {
+        match self.fold_case {
+            true => channel.to_lowercase(),
+            false => channel.to_string(),
+        }
+    }
+
+    pub fn add(&mut self, user: Arc<User>) {
+        self.users.push(user);
+    }
+
+    // Take the user off the roster and out of every channel they were in
+    pub fn remove(&mut self, user: &Arc<User>) {
+        for channel in self.channels.values_mut() {
+            channel.members.retain(|member| !Arc::ptr_eq(member, user));
+        }
+        self.drop_empty_channels();
+        self.users.retain(|member| !Arc::ptr_eq(member, user));
+    }
+
+    // The connected client with the given id, if there is one
+    pub fn client(&self, client_id: &str) -> Option<Client> {
+        self.users
+            .iter()
+            .find(|user| user.client.id == client_id)
+            .map(|user| user.client.as_ref().clone())
+    }
+
+    // Whether the user has joined the channel
+    pub fn in_channel(&self, user: &Arc<User>, channel: &str) -> bool {
+        self.channels
+            .get(&self.channel_id(channel))
+            .is_some_and(|channel| {
+                channel
+                    .members
+                    .iter()
+                    .any(|member| Arc::ptr_eq(member, user))
+            })
+    }
+
+    // How many users are connected
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    // A copy of the connected users, so callers can await on each of them
+    // without holding up the roster
+    pub fn users(&self) -> Vec<Arc<User>> {
+        self.users.clone()
+    }
+
+    // Add the user to a channel. A channel that doesn't exist yet is only
+    // created when may_create is set, and its creator becomes its owner.
+    // A user already in max_channels channels can't join another one.
+    // The whole roster is behind one lock, so when several users join a
+    // new channel at once exactly one of them creates it
+    pub fn join(
+        &mut self,
+        user: &Arc<User>,
+        channel: &str,
+        may_create: bool,
+        max_channels: Option<usize>,
+    ) -> JoinOutcome {
+        if self.in_channel(user, channel) {
+            return JoinOutcome::AlreadyMember;
+        }
+        if max_channels.is_some_and(|max| self.channel_count(user) >= max) {
+            return JoinOutcome::TooManyChannels;
+        }
+
+        let id = self.channel_id(channel);
+        let Some(existing) = self.channels.get_mut(&id) else {
+            if !may_create {
+                return JoinOutcome::NoSuchChannel;
+            }
+            let created = ChannelMembers {
+                display_name: channel.to_string(),
+                owner: user.client.id.clone(),
+                members: vec![Arc::clone(user)],
+                kept: false,
+            };
+            self.channels.insert(id, created);
+            return JoinOutcome::Created;
+        };
+
+        existing.members.push(Arc::clone(user));
+        JoinOutcome::Joined
+    }
+
+    // How many channels the user is in. Counted from the memberships
+    // themselves, so leaving or disconnecting can't leave it out of step
+    pub fn channel_count(&self, user: &Arc<User>) -> usize {
+        self.channels
+            .values()
+            .filter(|channel| {
+                channel
+                    .members
+                    .iter()
+                    .any(|member| Arc::ptr_eq(member, user))
+            })
+            .count()
+    }
+
+    // Keep the channel around even once everyone has left it
+    pub fn keep(&mut self, channel: &str) {
+        let id = self.channel_id(channel);
+        if let Some(channel) = self.channels.get_mut(&id) {
+            channel.kept = true;
+        }
+    }
+
+    // Every channel and how many are in it, sorted by name
+    pub fn channels(&self) -> Vec<(String, usize)> {
+        let mut channels: Vec<(String, usize)> = self
+            .channels
+            .values()
+            .map(|channel| (channel.display_name.clone(), channel.members.len()))
+            .collect();
+        channels.sort();
+        channels
+    }
+
+    // The channel a name refers to, under the name it was created with
+    pub fn channel(&self, channel: &str) -> Option<Channel> {
+        self.channels
+            .get(&self.channel_id(channel))
+            .map(|channel| Channel::new(channel.display_name.clone()))
+    }
+
+    // The client id of whoever created the channel
+    pub fn owner(&self, channel: &str) -> Option<&str> {
+        self.channels
+            .get(&self.channel_id(channel))
+            .map(|channel| channel.owner.as_str())
+    }
+
+    // Remove the user from a channel, returning false if they weren't in it
+    pub fn leave(&mut self, user: &Arc<User>, channel: &str) -> bool {
+        let id = self.channel_id(channel);
+        match self.channels.get_mut(&id) {
+            Some(channel) => {
+                let before = channel.members.len();
+                channel.members.retain(|member| !Arc::ptr_eq(member, user));
+                let left = channel.members.len() != before;
+                self.drop_empty_channels();
+                left
+            }
+            None => false,
+        }
+    }
+
+    // Forget channels nobody is in any more, unless they are kept
+    fn drop_empty_channels(&mut self) {
+        self.channels
+            .retain(|_, channel| channel.kept || !channel.members.is_empty());
+    }
+
+    // Work out who should receive a message sent to the destination. A
+    // direct message also goes back to its author, so they see it was sent.
+    // Users on their way out and users who asked the server to ignore the
+    // author are left out
+    pub async fn broadcast_targets(
+        &self,
+        destination: &Destination,
+        author_id: Option<&str>,
+    ) -> Vec<Arc<User>> {
+        let candidates: Vec<&Arc<User>> = match destination {
+            Destination::Global => self.users.iter().collect(),
+            Destination::Channel(channel) => self
+                .channels
+                .get(&self.channel_id(channel.display_name()))
+                .map(|channel| channel.members.iter().collect())
+                .unwrap_or_default(),
+            Destination::Direct(client) => self
+                .users
+                .iter()
+                .filter(|user| {
+                    user.client.id == client.id || Some(user.client.id.as_str()) == author_id
+                })
+                .collect(),
+        };
+
+        let mut targets = Vec::with_capacity(candidates.len());
+        for user in candidates {
+            if !*user.is_active.lock().await {
+                continue;
+            }
+            if let Some(author_id) = author_id
+                && user.ignored.lock().await.contains(author_id)
+            {
+                continue;
+            }
+            targets.push(Arc::clone(user));
+        }
+        targets
+    }
+}
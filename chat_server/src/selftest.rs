@@ -0,0 +1,103 @@
+use crate::{ServerState, run_server_with};
+use chat_client::{Settings, run_client_with};
+use chat_shared::{BoxedConnection, Client, Config, Message, User, message::MessageKind};
+use std::{
+    future::pending,
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::duplex,
+    sync::mpsc::{Sender, channel},
+    time::{sleep, timeout},
+};
+
+const PROBE: &str = "selftest probe";
+
+// Start a server and a client in this process, joined by an in-memory
+// pipe, send a message through them and check that it makes the round
+// trip. No port is bound, so this works wherever the binary can run
+pub async fn run() -> Result<(), String> {
+    // Nothing is shown, the client is only checked on
+    let config = Arc::new(Config {
+        prompt: String::new(),
+        echo_own_messages: false,
+        ..Config::default()
+    });
+    let state = Arc::new(ServerState::new(config.as_ref().clone()));
+    let (connections, incoming) = channel::<(BoxedConnection, SocketAddr)>(1);
+    let server = tokio::spawn(run_server_with(
+        Arc::clone(&state),
+        Vec::new(),
+        incoming,
+        pending(),
+    ));
+
+    // Each connection the client makes is one end of a new pipe, with the
+    // other end handed to the server
+    let connect = move || {
+        let connections = connections.clone();
+        async move {
+            let (ours, theirs) = duplex(64 * 1024);
+            let address = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+            connections
+                .send((Box::new(theirs), address))
+                .await
+                .map_err(|_| io::Error::other("the server has stopped"))?;
+            Ok(User::over(Box::new(ours), address.to_string()))
+        }
+    };
+    let settings = Arc::new(Settings::from_config(&config));
+    let (tx, rx) = channel(1);
+    let client = tokio::spawn(run_client_with(
+        Arc::clone(&config),
+        String::from("selftest"),
+        connect,
+        rx,
+        Arc::clone(&settings),
+    ));
+
+    let result = match timeout(Duration::from_secs(5), round_trip(&state, &settings, tx)).await {
+        Ok(result) => result,
+        Err(_) => Err(String::from("Timed out waiting for the probe")),
+    };
+
+    client.abort();
+    server.abort();
+    result
+}
+
+// Send the probe as the client, then wait for the server to have taken it
+// and the client to have had it relayed back
+async fn round_trip(
+    state: &ServerState,
+    settings: &Settings,
+    tx: Sender<Message>,
+) -> Result<(), String> {
+    let author = Arc::new(Client::new(String::new()));
+    let message = Message::from_string(author, PROBE.to_string(), MessageKind::Message);
+    tx.send(message)
+        .await
+        .map_err(|_| String::from("The client stopped"))?;
+
+    loop {
+        let accepted = state
+            .history
+            .lock()
+            .await
+            .recent_global(1)
+            .first()
+            .and_then(|message| message.id);
+        if let Some(id) = accepted
+            && let Some(line) = settings.recall(id).await
+        {
+            if !line.ends_with(PROBE) {
+                return Err(format!("Expected {PROBE:?} but the client saw {line:?}"));
+            }
+            return Ok(());
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+// The upper bound, in bytes, of every bucket but the last, which takes
+// everything larger
+const BUCKET_LIMITS: [usize; 6] = [16, 64, 256, 1024, 4096, 16384];
+
+// Counts how large the chat messages relayed by the server are, so the
+// operator can tell what sizes and buffers suit the traffic. Buckets are
+// fixed and counted with atomics, so recording costs next to nothing
+pub struct SizeHistogram {
+    buckets: [AtomicU64; BUCKET_LIMITS.len() + 1],
+}
+
+impl SizeHistogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: Default::default(),
+        }
+    }
+
+    pub fn record(&self, size: usize) {
+        let bucket = BUCKET_LIMITS
+            .iter()
+            .position(|&limit| size <= limit)
+            .unwrap_or(BUCKET_LIMITS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // How many messages landed in each bucket, smallest first
+    pub fn counts(&self) -> Vec<u64> {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    // One line per bucket, such as "<= 64 B: 12"
+    pub fn report(&self) -> String {
+        let mut report = String::from("Message sizes:");
+        for (bucket, count) in self.counts().into_iter().enumerate() {
+            let _ = match BUCKET_LIMITS.get(bucket) {
+                Some(limit) => write!(report, "\n<= {limit} B: {count}"),
+                None => write!(report, "\n> {} B: {count}", BUCKET_LIMITS[bucket - 1]),
+            };
+        }
+        report
+    }
+}
+
+impl Default for SizeHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// How deep a queue is and the deepest it has been, for :stats
+pub struct QueueDepth {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl QueueDepth {
+    pub fn new() -> Self {
+        Self {
+            current: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn record(&self, depth: usize) {
+        self.current.store(depth, Ordering::Relaxed);
+        self.peak.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for QueueDepth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// What changed about a queue since it was last checked
+#[derive(Debug, PartialEq, Eq)]
+pub enum QueueAlert {
+    // It has reached the high-water mark
+    Backlogged,
+    // It has emptied again after being backlogged
+    CaughtUp,
+}
+
+// Watches the depth of one queue, so whoever takes from it can warn once
+// when it backs up rather than on every message while it stays that way
+pub struct HighWater {
+    mark: usize,
+    backlogged: bool,
+}
+
+impl HighWater {
+    pub fn new(mark: usize) -> Self {
+        Self {
+            mark,
+            backlogged: false,
+        }
+    }
+
+    pub fn check(&mut self, depth: usize) -> Option<QueueAlert> {
+        if !self.backlogged && depth >= self.mark {
+            self.backlogged = true;
+            return Some(QueueAlert::Backlogged);
+        }
+        if self.backlogged && depth == 0 {
+            self.backlogged = false;
+            return Some(QueueAlert::CaughtUp);
+        }
+        None
+    }
+}
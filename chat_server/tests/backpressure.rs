@@ -0,0 +1,52 @@
+mod common;
+
+use chat_shared::Config;
+use common::{Logs, TIMEOUT, connect_test_client, spawn_test_server_with};
+use std::time::Duration;
+use tokio::time::{Instant, sleep};
+
+// The test runtime runs every task on this thread, so a logger set for the
+// thread hears from the whole server
+#[tokio::test]
+async fn slow_client_trips_the_high_water_warning() {
+    let logs = Logs::default();
+    let logger = tracing_subscriber::fmt()
+        .with_writer(logs.clone())
+        .with_ansi(false)
+        .finish();
+    let _logger = tracing::subscriber::set_default(logger);
+
+    // Pace every connection, so two clients talking fill up the outbox of
+    // a third faster than it can be written out
+    let config = Config {
+        bandwidth_limit_bps: Some(4000),
+        queue_high_water: 4,
+        ..Config::default()
+    };
+    let server = spawn_test_server_with(config).await;
+    let mut alice = connect_test_client(&server).await;
+    let mut carol = connect_test_client(&server).await;
+    // Bob never reads another thing, so he only falls further behind
+    let bob = connect_test_client(&server).await;
+    let bob_address = bob.stream.local_addr().unwrap().to_string();
+
+    let warned = |logs: &Logs| {
+        logs.contents().lines().any(|line| {
+            line.contains("Outbox is backing up") && line.contains(bob_address.as_str())
+        })
+    };
+    let deadline = Instant::now() + TIMEOUT;
+    while !warned(&logs) {
+        assert!(
+            Instant::now() < deadline,
+            "no high-water warning for the slow client:\n{}",
+            logs.contents()
+        );
+        alice.say("are you keeping up?").await;
+        carol.say("apparently not").await;
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    assert!(server.state.outbox_depth.peak() >= 4);
+    server.stop().await;
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use chat_shared::{Config, frame, message::MessageKind};
+use common::{connect_test_client, spawn_test_server_with};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const LIMIT_BPS: u64 = 8 * 1024;
+
+#[tokio::test]
+async fn a_capped_client_is_sent_no_faster_than_its_limit() {
+    let server = spawn_test_server_with(Config {
+        bandwidth_limit_bps: Some(LIMIT_BPS),
+        ..Config::default()
+    })
+    .await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    let max_frame_size = server.state.config.max_frame_size;
+
+    // Content goes out as a list of byte values, so these frames come to
+    // around a couple of seconds' worth at the limit
+    let text = "x".repeat(128);
+    let count = 20;
+    let started = Instant::now();
+    for _ in 0..count {
+        alice.say(&text).await;
+    }
+    // Alice's echoes are read so her outbox never holds anything up
+    let alice_id = alice.id.clone();
+    tokio::spawn(async move { while alice.recv().await.is_some() {} });
+
+    let mut received = 0;
+    let mut bytes = 0;
+    while received < count {
+        let message = bob.recv().await.expect("bob stopped hearing alice");
+        if message.kind == MessageKind::Message && message.author_id.as_deref() == Some(&alice_id) {
+            received += 1;
+        }
+        bytes += frame::encode(&message, max_frame_size).unwrap().len() as u64;
+    }
+    let elapsed = started.elapsed();
+
+    // The writer may start with a second's worth saved up, and after that
+    // keeps to the limit, never dropping anything to do so
+    let allowed = LIMIT_BPS as f64 * (1.0 + elapsed.as_secs_f64());
+    assert!(
+        (bytes as f64) <= allowed,
+        "sent {bytes} bytes in {elapsed:?}, more than the {allowed:.0} allowed"
+    );
+    let fastest =
+        Duration::from_secs_f64(bytes.saturating_sub(LIMIT_BPS) as f64 / LIMIT_BPS as f64);
+    assert!(
+        elapsed >= fastest,
+        "{bytes} bytes arrived in only {elapsed:?}"
+    );
+
+    server.stop().await;
+}
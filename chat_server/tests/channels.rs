@@ -0,0 +1,392 @@
+mod common;
+
+use chat_server::roster::Roster;
+use chat_shared::{
+    Config, DisconnectReason, User,
+    message::{Channel, Destination, MessageKind},
+    user::Client,
+};
+use common::{
+    ServerHandle, TestClient, connect_test_client, spawn_test_server, spawn_test_server_with,
+    wait_for_roster,
+};
+use std::sync::Arc;
+use tokio::{io::duplex, sync::Barrier};
+
+// Wait until the server has handled everything the client sent before now.
+// Commands from one client are handled in order, so once :whoami is
+// answered so is everything before it
+async fn settle(client: &mut TestClient) {
+    client.command(":whoami").await;
+    loop {
+        let message = client.recv().await.expect("no reply to :whoami");
+        if message.kind == MessageKind::ServerBroadcast
+            && message.as_string().starts_with("You are ")
+        {
+            return;
+        }
+    }
+}
+
+// How many of the connected clients the roster has in the channel
+async fn members_of(server: &ServerHandle, channel: &str) -> usize {
+    let roster = server.state.roster.lock().await;
+    roster
+        .users()
+        .iter()
+        .filter(|user| roster.in_channel(user, channel))
+        .count()
+}
+
+#[tokio::test]
+async fn racing_joins_and_leaves_settle_consistently() {
+    let server = spawn_test_server().await;
+    let mut tasks = Vec::new();
+    for _ in 0..4 {
+        let mut client = connect_test_client(&server).await;
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..20 {
+                client.command(":join games").await;
+                // Joining twice in a row is the same as joining once
+                client.command(":join games").await;
+                client.command(":leave games").await;
+            }
+            client.command(":join games").await;
+            settle(&mut client).await;
+            client
+        }));
+    }
+    let mut clients = Vec::new();
+    for task in tasks {
+        clients.push(task.await.expect("a client task failed"));
+    }
+
+    // Everyone ended on a join, and nobody is in it twice
+    assert_eq!(members_of(&server, "games").await, 4);
+    let listed = server.state.roster.lock().await.channels();
+    assert_eq!(listed, [(String::from("games"), 4)]);
+
+    // Hanging up takes a client out of the channel too
+    clients.truncate(2);
+    wait_for_roster(&server, 2).await;
+    let listed = server.state.roster.lock().await.channels();
+    assert_eq!(listed, [(String::from("games"), 2)]);
+
+    clients.clear();
+    wait_for_roster(&server, 0).await;
+    assert!(server.state.roster.lock().await.channels().is_empty());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn racing_first_joins_create_the_channel_once() {
+    let server = spawn_test_server().await;
+    let mut clients = Vec::new();
+    for _ in 0..4 {
+        clients.push(connect_test_client(&server).await);
+    }
+
+    // Everyone asks for the channel at the same moment, before it exists
+    let start = Arc::new(Barrier::new(clients.len()));
+    let mut tasks = Vec::new();
+    for mut client in clients {
+        let start = Arc::clone(&start);
+        tasks.push(tokio::spawn(async move {
+            start.wait().await;
+            client.command(":join games").await;
+            loop {
+                let reply = client.recv().await.expect("no reply to :join");
+                let text = reply.as_string();
+                if reply.kind == MessageKind::ServerBroadcast
+                    && (text.starts_with("Created ") || text.starts_with("Joined "))
+                {
+                    return (client, text);
+                }
+            }
+        }));
+    }
+    let mut created = Vec::new();
+    let mut clients = Vec::new();
+    for task in tasks {
+        let (client, reply) = task.await.expect("a client task failed");
+        if reply.starts_with("Created ") {
+            created.push(client.id.clone());
+        }
+        clients.push(client);
+    }
+
+    // Exactly one of them made it, and owns it, and the rest joined it
+    assert_eq!(created.len(), 1, "the channel was created {created:?}");
+    let owner = server
+        .state
+        .roster
+        .lock()
+        .await
+        .owner("games")
+        .map(String::from);
+    assert_eq!(owner.as_deref(), Some(created[0].as_str()));
+    assert_eq!(members_of(&server, "games").await, 4);
+
+    server.stop().await;
+}
+
+// Join a channel and return the reply, skipping news of channels created
+async fn join(client: &mut TestClient, channel: &str) -> String {
+    client.command(&format!(":join {channel}")).await;
+    loop {
+        let reply = client.recv().await.expect("no reply to :join");
+        let text = reply.as_string();
+        if reply.kind == MessageKind::ServerBroadcast && !text.contains(" created ") {
+            return text;
+        }
+    }
+}
+
+#[tokio::test]
+async fn names_differing_only_by_case_are_one_channel() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+
+    assert_eq!(
+        join(&mut alice, "General").await,
+        "Created General, which you own"
+    );
+    // Joining under another case joins the channel that is there
+    assert_eq!(join(&mut bob, "GENERAL").await, "Joined GENERAL");
+    // And creating it again under another case creates nothing
+    assert_eq!(join(&mut alice, "general").await, "Already in general");
+
+    let listed = server.state.roster.lock().await.channels();
+    assert_eq!(listed, [(String::from("General"), 2)]);
+    let owner = server
+        .state
+        .roster
+        .lock()
+        .await
+        .owner("general")
+        .map(String::from);
+    assert_eq!(owner.as_deref(), Some(alice.id.as_str()));
+
+    // What is said in it goes out under the name it was created with
+    bob.say_in("general", "hello").await;
+    let heard = alice.recv_from(&bob.id).await.expect("alice heard nothing");
+    match heard.channel {
+        Destination::Channel(channel) => assert_eq!(channel.display_name(), "General"),
+        _ => panic!("expected a channel message"),
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn case_sensitive_names_are_separate_channels() {
+    let server = spawn_test_server_with(Config {
+        case_insensitive_channels: false,
+        ..Config::default()
+    })
+    .await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+
+    assert_eq!(
+        join(&mut alice, "General").await,
+        "Created General, which you own"
+    );
+    assert_eq!(
+        join(&mut bob, "general").await,
+        "Created general, which you own"
+    );
+
+    let mut listed = server.state.roster.lock().await.channels();
+    listed.sort();
+    assert_eq!(
+        listed,
+        [(String::from("General"), 1), (String::from("general"), 1)]
+    );
+
+    server.stop().await;
+}
+
+// A user on the roster with nothing behind their connection, as only
+// who the roster picks is being checked
+fn roster_user(roster: &mut Roster, name: &str) -> Arc<User> {
+    let (connection, _) = duplex(64);
+    let user = Arc::new(User::over(Box::new(connection), name.to_string()));
+    roster.add(Arc::clone(&user));
+    user
+}
+
+// Who a message from the author to the destination goes to, by address
+async fn targets(roster: &Roster, destination: &Destination, author: Option<&User>) -> Vec<String> {
+    let author_id = author.map(|author| author.client.id.as_str());
+    let mut targets: Vec<String> = roster
+        .broadcast_targets(destination, author_id)
+        .await
+        .iter()
+        .map(|user| user.client.address.clone())
+        .collect();
+    targets.sort();
+    targets
+}
+
+#[tokio::test]
+async fn broadcast_targets_follow_the_destination() {
+    let mut roster = Roster::new(true);
+    let alice = roster_user(&mut roster, "alice");
+    let bob = roster_user(&mut roster, "bob");
+    let _carol = roster_user(&mut roster, "carol");
+    roster.join(&alice, "games", true, None);
+    roster.join(&bob, "games", true, None);
+
+    // Everyone hears what is said to everyone, the author included
+    let everyone = targets(&roster, &Destination::Global, Some(&alice)).await;
+    assert_eq!(everyone, ["alice", "bob", "carol"]);
+
+    // Only members hear a channel, under whatever case it is named
+    let games = Destination::Channel(Channel::new(String::from("Games")));
+    assert_eq!(
+        targets(&roster, &games, Some(&alice)).await,
+        ["alice", "bob"]
+    );
+    let nowhere = Destination::Channel(Channel::new(String::from("nowhere")));
+    assert!(targets(&roster, &nowhere, Some(&alice)).await.is_empty());
+
+    // A direct message goes to its recipient and back to its author
+    let to_bob = Destination::Direct(Client {
+        id: bob.client.id.clone(),
+        address: String::new(),
+    });
+    assert_eq!(
+        targets(&roster, &to_bob, Some(&alice)).await,
+        ["alice", "bob"]
+    );
+}
+
+#[tokio::test]
+async fn broadcast_targets_skip_the_ignoring_and_the_inactive() {
+    let mut roster = Roster::new(true);
+    let alice = roster_user(&mut roster, "alice");
+    let bob = roster_user(&mut roster, "bob");
+    let carol = roster_user(&mut roster, "carol");
+    roster.join(&alice, "games", true, None);
+    roster.join(&bob, "games", true, None);
+
+    // Bob has ignored alice, so he gets nothing of hers wherever it goes
+    bob.ignored.lock().await.insert(alice.client.id.clone());
+    let games = Destination::Channel(Channel::new(String::from("games")));
+    let to_bob = Destination::Direct(Client {
+        id: bob.client.id.clone(),
+        address: String::new(),
+    });
+    let everyone = targets(&roster, &Destination::Global, Some(&alice)).await;
+    assert_eq!(everyone, ["alice", "carol"]);
+    assert_eq!(targets(&roster, &games, Some(&alice)).await, ["alice"]);
+    assert_eq!(targets(&roster, &to_bob, Some(&alice)).await, ["alice"]);
+    // But the server has no author id, so it still reaches him
+    let notice = targets(&roster, &Destination::Global, None).await;
+    assert_eq!(notice, ["alice", "bob", "carol"]);
+
+    // Someone on their way out isn't sent anything more
+    carol.deactivate(DisconnectReason::Quit).await;
+    let everyone = targets(&roster, &Destination::Global, Some(&bob)).await;
+    assert_eq!(everyone, ["alice", "bob"]);
+}
+
+#[tokio::test]
+async fn channel_messages_only_reach_its_members() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    let mut carol = connect_test_client(&server).await;
+    join(&mut alice, "games").await;
+    join(&mut bob, "games").await;
+    join(&mut carol, "music").await;
+
+    alice.say_in("games", "anyone for chess?").await;
+    let heard = bob.recv_from(&alice.id).await.expect("bob heard nothing");
+    assert_eq!(heard.as_string(), "anyone for chess?");
+
+    // Carol is in another channel, so the next thing she hears is her own
+    carol.say_in("music", "la la la").await;
+    let heard = carol
+        .recv_kind(MessageKind::Message)
+        .await
+        .expect("carol heard nothing");
+    assert_eq!(heard.as_string(), "la la la");
+    assert_eq!(heard.author_id.as_deref(), Some(carol.id.as_str()));
+
+    // Nor can she say anything in a channel she isn't in
+    carol.say_in("games", "let me in").await;
+    let reply = carol
+        .recv_kind(MessageKind::ServerBroadcast)
+        .await
+        .expect("carol was not told");
+    assert_eq!(reply.as_string(), "Not in games");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn joining_past_the_channel_cap_is_refused() {
+    let config = Config {
+        max_channels_per_user: Some(2),
+        ..Config::default()
+    };
+    let server = spawn_test_server_with(config).await;
+    let mut alice = connect_test_client(&server).await;
+
+    assert_eq!(
+        join(&mut alice, "games").await,
+        "Created games, which you own"
+    );
+    assert_eq!(
+        join(&mut alice, "music").await,
+        "Created music, which you own"
+    );
+    assert_eq!(
+        join(&mut alice, "films").await,
+        "You can't join films, you are already in too many channels"
+    );
+
+    // Rejoining a channel she is in doesn't count against the cap
+    assert_eq!(join(&mut alice, "games").await, "Already in games");
+
+    server.stop().await;
+}
+
+// The answer to :channels, skipping the creation notices still to be read
+async fn channel_list(client: &mut TestClient) -> String {
+    client.command(":channels").await;
+    loop {
+        let reply = client.recv().await.expect("no reply to :channels");
+        let text = reply.as_string();
+        if text.starts_with("Channels: ") || text == "There are no channels" {
+            return text;
+        }
+    }
+}
+
+#[tokio::test]
+async fn channels_are_listed_with_their_member_counts() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    let mut carol = connect_test_client(&server).await;
+    assert_eq!(channel_list(&mut alice).await, "There are no channels");
+
+    join(&mut alice, "games").await;
+    join(&mut bob, "games").await;
+    join(&mut carol, "music").await;
+    assert_eq!(
+        channel_list(&mut alice).await,
+        "Channels: games (2), music (1)"
+    );
+
+    // A channel left empty is forgotten
+    carol.command(":leave music").await;
+    assert_eq!(channel_list(&mut carol).await, "Channels: games (2)");
+
+    server.stop().await;
+}
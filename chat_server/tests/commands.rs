@@ -0,0 +1,103 @@
+mod common;
+
+use chat_shared::{Config, message::MessageKind};
+use common::{
+    TestClient, connect_test_client, spawn_test_server, spawn_test_server_with, wait_for_roster,
+};
+
+// Send a command and return everything the server answers it with. The
+// answer to a :whoami sent straight after marks the end, as commands from
+// one client are answered in order
+async fn replies_to(client: &mut TestClient, command: &str) -> Vec<String> {
+    client.command(command).await;
+    client.command(":whoami").await;
+    let mut replies = Vec::new();
+    loop {
+        let reply = client.recv().await.expect("no reply from the server");
+        let text = reply.as_string();
+        if text.starts_with("You are ") {
+            return replies;
+        }
+        assert_eq!(reply.kind, MessageKind::ServerBroadcast, "{text}");
+        replies.push(text);
+    }
+}
+
+#[tokio::test]
+async fn unknown_command_gets_exactly_one_reply() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+
+    let replies = replies_to(&mut alice, ":frobnicate").await;
+    assert_eq!(
+        replies,
+        ["Unknown command :frobnicate, type :help to see the commands"]
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn disabled_commands_are_refused_and_the_rest_still_work() {
+    let config = Config {
+        disabled_commands: vec![String::from("list"), String::from("quit")],
+        ..Config::default()
+    };
+    let server = spawn_test_server_with(config).await;
+    let mut alice = connect_test_client(&server).await;
+
+    // replies_to ends on a :whoami, which is still enabled
+    assert_eq!(replies_to(&mut alice, ":list").await, ["command disabled"]);
+
+    // :quit can't be turned off, so alice can still leave
+    alice.command(":quit").await;
+    while alice.recv().await.is_some() {}
+    wait_for_roster(&server, 0).await;
+
+    server.stop().await;
+}
+
+// The frame counts :whoami reports, received from the client then sent to it
+async fn frame_counts(client: &mut TestClient) -> (u64, u64) {
+    client.command(":whoami").await;
+    let reply = client
+        .recv_kind(MessageKind::ServerBroadcast)
+        .await
+        .expect("no reply to :whoami");
+    let text = reply.as_string();
+    let (head, tail) = text
+        .split_once(" frames received from you, ")
+        .unwrap_or_else(|| panic!("no frame counts in {text}"));
+    let received = head.rsplit(' ').next().unwrap().parse().unwrap();
+    let sent = tail.strip_suffix(" sent to you").unwrap().parse().unwrap();
+    (received, sent)
+}
+
+#[tokio::test]
+async fn whoami_counts_the_frames_each_way() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+
+    let (received, sent) = frame_counts(&mut alice).await;
+    for i in 0..3 {
+        alice.say(&format!("message {i}")).await;
+    }
+    // Each message comes back once as itself and once as its Ack
+    let (mut echoes, mut acks) = (0, 0);
+    while echoes < 3 || acks < 3 {
+        let reply = alice.recv().await.expect("a message was not answered");
+        match reply.kind {
+            MessageKind::Message => echoes += 1,
+            MessageKind::Ack => acks += 1,
+            kind => panic!("unexpected {kind:?}"),
+        }
+    }
+
+    // Three messages and a :whoami came in, while their echoes, Acks and
+    // the previous :whoami's reply went out
+    let (now_received, now_sent) = frame_counts(&mut alice).await;
+    assert_eq!(now_received - received, 4);
+    assert_eq!(now_sent - sent, 7);
+
+    server.stop().await;
+}
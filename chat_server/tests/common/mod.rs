@@ -0,0 +1,317 @@
+// Helpers shared by the integration tests. Each test file only uses some
+// of them, so the rest would otherwise be reported as dead code
+#![allow(dead_code)]
+
+use chat_server::{ServerState, bind_listener, run_server};
+use chat_shared::{
+    Client, Config, Message, frame,
+    message::{Channel, Destination, MessageKind},
+};
+use std::{
+    io::{self, Write},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+    task::JoinHandle,
+    time::{Instant, sleep, timeout},
+};
+use tracing_subscriber::fmt::MakeWriter;
+
+// How long a test waits on the server before failing, rather than hanging
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+// A server running in the background of a test. Dropping it stops the
+// server, and stop does so cleanly, waiting for it to finish. The address
+// is the first of the addresses it listens on
+pub struct ServerHandle {
+    pub address: SocketAddr,
+    pub addresses: Vec<SocketAddr>,
+    pub state: Arc<ServerState>,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    // Ask the server to shut down and wait for it to finish
+    pub async fn stop(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        timeout(TIMEOUT, &mut self.task)
+            .await
+            .expect("server did not shut down in time")
+            .expect("server task failed");
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// Start a server with the default config on an ephemeral loopback port
+pub async fn spawn_test_server() -> ServerHandle {
+    spawn_test_server_with(Config::default()).await
+}
+
+// Start a server with the given config on an ephemeral loopback port. The
+// address and port in the config are ignored
+pub async fn spawn_test_server_with(config: Config) -> ServerHandle {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("could not bind a test listener");
+    spawn_on(ServerState::new(config), vec![listener])
+}
+
+// Start a server from state the test has already set up, such as one that
+// has opened its event log, on an ephemeral loopback port
+pub async fn spawn_test_server_from(state: ServerState) -> ServerHandle {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("could not bind a test listener");
+    spawn_on(state, vec![listener])
+}
+
+// Start a server listening on both the IPv4 and IPv6 loopback addresses,
+// each on its own ephemeral port. None if this machine has no IPv6
+// loopback to bind
+pub fn spawn_dual_stack_test_server() -> Option<ServerHandle> {
+    let ipv4 = bind_listener("127.0.0.1:0".parse().unwrap()).expect("could not bind IPv4");
+    let ipv6 = bind_listener("[::1]:0".parse().unwrap()).ok()?;
+    Some(spawn_on(
+        ServerState::new(Config::default()),
+        vec![ipv4, ipv6],
+    ))
+}
+
+fn spawn_on(state: ServerState, listeners: Vec<TcpListener>) -> ServerHandle {
+    let addresses: Vec<SocketAddr> = listeners
+        .iter()
+        .map(|listener| listener.local_addr().expect("listener has no address"))
+        .collect();
+    let state = Arc::new(state);
+    let (shutdown, stopped) = oneshot::channel::<()>();
+    let task = tokio::spawn(run_server(Arc::clone(&state), listeners, async {
+        let _ = stopped.await;
+    }));
+    ServerHandle {
+        address: addresses[0],
+        addresses,
+        state,
+        shutdown: Some(shutdown),
+        task,
+    }
+}
+
+// A raw connection to a test server, speaking frames directly. The id and
+// session token are the ones the server gave the connection in its welcome
+pub struct TestClient {
+    pub stream: TcpStream,
+    pub client: Arc<Client>,
+    pub id: String,
+    pub session_token: String,
+    pub max_frame_size: u32,
+}
+
+// Connect to the server and return once it is serving the client, so
+// anything sent to everyone from then on reaches it. The welcome and the
+// reply used to check this are read and thrown away
+pub async fn connect_test_client(server: &ServerHandle) -> TestClient {
+    connect_test_client_at(server, server.address).await
+}
+
+// As connect_test_client, but through one address in particular of those
+// the server listens on
+pub async fn connect_test_client_at(server: &ServerHandle, address: SocketAddr) -> TestClient {
+    let mut client = join_test_client_at(server, address).await;
+    // The server only reads from a client once it is on the roster, so an
+    // answer to a command means it is
+    client.command(":whoami").await;
+    client
+        .recv_kind(MessageKind::ServerBroadcast)
+        .await
+        .expect("no reply to :whoami");
+    client
+}
+
+// Connect to the server and read only the welcome, leaving the history
+// replay and anything after it for the test to read
+pub async fn join_test_client(server: &ServerHandle) -> TestClient {
+    join_test_client_at(server, server.address).await
+}
+
+async fn join_test_client_at(server: &ServerHandle, address: SocketAddr) -> TestClient {
+    let stream = timeout(TIMEOUT, TcpStream::connect(address))
+        .await
+        .expect("timed out connecting")
+        .expect("could not connect");
+    let local = stream.local_addr().expect("stream has no address");
+    let mut client = TestClient {
+        stream,
+        client: Arc::new(Client::new(local.to_string())),
+        id: String::new(),
+        session_token: String::new(),
+        max_frame_size: server.state.config.max_frame_size,
+    };
+
+    // The welcome is always the first thing the server sends
+    let welcome = client.recv().await.expect("no welcome from the server");
+    assert_eq!(
+        welcome.kind,
+        MessageKind::Ack,
+        "the first frame was not the welcome"
+    );
+    client.session_token = welcome.as_string();
+    client.id = welcome.author_id.expect("welcome carries no author id");
+    client
+}
+
+impl TestClient {
+    pub async fn send(&mut self, message: Message) {
+        timeout(
+            TIMEOUT,
+            frame::write_frame(&mut self.stream, &message, self.max_frame_size),
+        )
+        .await
+        .expect("timed out sending")
+        .expect("could not send");
+    }
+
+    // Say something to everyone
+    pub async fn say(&mut self, text: &str) {
+        let message = Message::from_string(
+            Arc::clone(&self.client),
+            text.to_string(),
+            MessageKind::Message,
+        );
+        self.send(message).await;
+    }
+
+    // Say something in a channel
+    pub async fn say_in(&mut self, channel: &str, text: &str) {
+        let mut message = Message::from_string(
+            Arc::clone(&self.client),
+            text.to_string(),
+            MessageKind::Message,
+        );
+        message.channel = Destination::Channel(Channel::new(channel.to_string()));
+        self.send(message).await;
+    }
+
+    pub async fn command(&mut self, command: &str) {
+        let message = Message::from_string(
+            Arc::clone(&self.client),
+            command.to_string(),
+            MessageKind::Command,
+        );
+        self.send(message).await;
+    }
+
+    // Take a nickname and wait for the server to ack it
+    pub async fn name(&mut self, name: &str) {
+        self.command(&format!(":name {name}")).await;
+        self.recv_kind(MessageKind::Ack)
+            .await
+            .expect("the name was not acked");
+    }
+
+    // The next frame from the server, or None if the connection closed
+    // or nothing arrived in time
+    pub async fn recv(&mut self) -> Option<Message> {
+        timeout(
+            TIMEOUT,
+            frame::read_frame(&mut self.stream, self.max_frame_size),
+        )
+        .await
+        .ok()?
+        .ok()
+    }
+
+    // The next frame of the given kind, skipping any others
+    pub async fn recv_kind(&mut self, kind: MessageKind) -> Option<Message> {
+        loop {
+            let message = self.recv().await?;
+            if message.kind == kind {
+                return Some(message);
+            }
+        }
+    }
+
+    // The text of the next notice from the server, skipping anything else
+    pub async fn next_notice(&mut self) -> String {
+        self.recv_kind(MessageKind::ServerBroadcast)
+            .await
+            .expect("no notice from the server")
+            .as_string()
+    }
+
+    // As next_notice, but also skipping news of arrivals, which can come
+    // in at any time ahead of the reply a test is waiting on
+    pub async fn next_reply(&mut self) -> String {
+        loop {
+            let notice = self.next_notice().await;
+            if !notice.ends_with(" joined") {
+                return notice;
+            }
+        }
+    }
+
+    // The next chat message from the given author, skipping anything else,
+    // such as the echo of what this client said itself
+    pub async fn recv_from(&mut self, author_id: &str) -> Option<Message> {
+        loop {
+            let message = self.recv_kind(MessageKind::Message).await?;
+            if message.author_id.as_deref() == Some(author_id) {
+                return Some(message);
+            }
+        }
+    }
+}
+
+// Wait until the server has the given number of users on its roster.
+// Joining and leaving each finish on the server's own time, so a test
+// checks the count through this rather than straight away
+pub async fn wait_for_roster(server: &ServerHandle, count: usize) {
+    let deadline = Instant::now() + TIMEOUT;
+    while server.state.roster.lock().await.len() != count {
+        assert!(
+            Instant::now() < deadline,
+            "the roster never reached {count} users"
+        );
+        sleep(Duration::from_millis(10)).await;
+    }
+}
+
+// Collects what the server logs, so a test can look for what it wrote
+#[derive(Clone, Default)]
+pub struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Logs {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for Logs {
+    type Writer = Logs;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
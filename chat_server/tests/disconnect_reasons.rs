@@ -0,0 +1,138 @@
+mod common;
+
+use chat_shared::{
+    Config,
+    message::{Message, MessageKind},
+};
+use common::{ServerHandle, TestClient, connect_test_client, spawn_test_server_with};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+// Connect alice, who is about to leave, and bob, who sees her go
+async fn alice_and_bob(server: &ServerHandle) -> (TestClient, TestClient) {
+    let mut bob = connect_test_client(server).await;
+    let mut alice = connect_test_client(server).await;
+    alice.command(":name alice").await;
+    assert_eq!(bob.next_notice().await, "alice joined");
+    (alice, bob)
+}
+
+#[tokio::test]
+async fn quitting_is_announced_as_such() {
+    // Departures are announced with their reason as soon as they happen
+    let server = spawn_test_server_with(Config {
+        departure_reasons: true,
+        reconnect_grace_secs: 0,
+        ..Config::default()
+    })
+    .await;
+    let (mut alice, mut bob) = alice_and_bob(&server).await;
+
+    alice.command(":quit").await;
+    assert_eq!(bob.next_notice().await, "alice left (quit)");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn hanging_up_is_announced_as_such() {
+    let server = spawn_test_server_with(Config {
+        departure_reasons: true,
+        reconnect_grace_secs: 0,
+        ..Config::default()
+    })
+    .await;
+    let (mut alice, mut bob) = alice_and_bob(&server).await;
+
+    alice.stream.shutdown().await.unwrap();
+    assert_eq!(bob.next_notice().await, "alice left (connection closed)");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn being_kicked_is_announced_as_such() {
+    // Everyone on loopback is an admin, so bob can kick
+    let server = spawn_test_server_with(Config {
+        admin_ips: vec!["127.0.0.1".parse().unwrap()],
+        departure_reasons: true,
+        reconnect_grace_secs: 0,
+        ..Config::default()
+    })
+    .await;
+    let (_alice, mut bob) = alice_and_bob(&server).await;
+
+    bob.command(":kick alice").await;
+    assert_eq!(bob.next_notice().await, "Kicked alice");
+    assert_eq!(bob.next_notice().await, "alice left (kicked)");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn breaking_the_protocol_is_announced_as_such() {
+    let server = spawn_test_server_with(Config {
+        departure_reasons: true,
+        reconnect_grace_secs: 0,
+        ..Config::default()
+    })
+    .await;
+    let (mut alice, mut bob) = alice_and_bob(&server).await;
+
+    // A frame longer than the server will ever read
+    let too_long = (alice.max_frame_size + 1).to_be_bytes();
+    alice.stream.write_all(&too_long).await.unwrap();
+    assert_eq!(bob.next_notice().await, "alice left (protocol error)");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn a_reset_connection_is_announced_as_a_network_error() {
+    let server = spawn_test_server_with(Config {
+        departure_reasons: true,
+        reconnect_grace_secs: 0,
+        ..Config::default()
+    })
+    .await;
+    let (alice, mut bob) = alice_and_bob(&server).await;
+
+    // Alice never read the ack of her name, and closing a socket with
+    // data still unread resets the connection rather than closing it
+    drop(alice);
+    assert_eq!(bob.next_notice().await, "alice left (network error)");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn missing_pings_is_announced_as_such() {
+    let server = spawn_test_server_with(Config {
+        heartbeat_secs: Some(1),
+        heartbeat_miss_limit: 1,
+        departure_reasons: true,
+        reconnect_grace_secs: 0,
+        ..Config::default()
+    })
+    .await;
+    let (_alice, mut bob) = alice_and_bob(&server).await;
+
+    // Bob answers his pings, alice never does
+    loop {
+        let message = bob.recv().await.expect("the server went quiet");
+        match message.kind {
+            MessageKind::Ping => {
+                let pong =
+                    Message::from_string(Arc::clone(&bob.client), String::new(), MessageKind::Pong);
+                bob.send(pong).await;
+            }
+            MessageKind::ServerBroadcast => {
+                assert_eq!(message.as_string(), "alice left (stopped responding)");
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    server.stop().await;
+}
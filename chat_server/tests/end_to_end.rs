@@ -0,0 +1,1010 @@
+mod common;
+
+use chat_server::{
+    ServerState, announce, handle_client, handle_writes, join_roster, relay_message, send_message,
+};
+use chat_shared::{
+    Config, User, frame,
+    message::{Destination, Message, MessageKind},
+    user::Client,
+};
+use common::{
+    TIMEOUT, TestClient, connect_test_client, connect_test_client_at, join_test_client,
+    spawn_dual_stack_test_server, spawn_test_server, spawn_test_server_with,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncWriteExt, DuplexStream, duplex},
+    sync::mpsc::{self, Receiver},
+    time::{Instant, sleep, timeout},
+};
+
+#[tokio::test]
+async fn message_reaches_another_client() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+
+    alice.say("hello bob").await;
+
+    let received = bob
+        .recv_kind(MessageKind::Message)
+        .await
+        .expect("bob received nothing");
+    assert_eq!(received.as_string(), "hello bob");
+    assert_eq!(received.author_id.as_deref(), Some(alice.id.as_str()));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn relayed_message_carries_its_authors_name_and_id() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    alice.command(":name alice").await;
+    alice
+        .recv_kind(MessageKind::Ack)
+        .await
+        .expect("alice's name was not acked");
+
+    // Whoever the client claims wrote it, the server says who really did
+    let mut message = Message::from_string(
+        Arc::clone(&alice.client),
+        String::from("it's me"),
+        MessageKind::Message,
+    );
+    message.author = Some(String::from("mallory"));
+    message.author_id = Some(bob.id.clone());
+    alice.send(message).await;
+
+    let received = bob
+        .recv_kind(MessageKind::Message)
+        .await
+        .expect("bob received nothing");
+    assert_eq!(received.as_string(), "it's me");
+    assert_eq!(received.author.as_deref(), Some("alice"));
+    assert_eq!(received.author_id.as_deref(), Some(alice.id.as_str()));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn clients_on_ipv4_and_ipv6_chat_together() {
+    let Some(server) = spawn_dual_stack_test_server() else {
+        eprintln!("skipping, no IPv6 loopback to listen on");
+        return;
+    };
+    let [ipv4, ipv6] = server.addresses[..] else {
+        panic!("expected two listening addresses");
+    };
+    assert!(ipv4.is_ipv4() && ipv6.is_ipv6());
+
+    let mut alice = connect_test_client_at(&server, ipv4).await;
+    let mut bob = connect_test_client_at(&server, ipv6).await;
+
+    alice.say("hello over ipv4").await;
+    let received = bob
+        .recv_from(&alice.id)
+        .await
+        .expect("bob received nothing");
+    assert_eq!(received.as_string(), "hello over ipv4");
+
+    bob.say("hello over ipv6").await;
+    let received = alice
+        .recv_from(&bob.id)
+        .await
+        .expect("alice received nothing");
+    assert_eq!(received.as_string(), "hello over ipv6");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn quotes_are_relayed_byte_for_byte() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+
+    let text = r#"she said "hi""#;
+    alice.say(text).await;
+    let received = bob
+        .recv_from(&alice.id)
+        .await
+        .expect("bob received nothing");
+    assert_eq!(received.content, text.as_bytes());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn line_endings_are_normalized() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+
+    alice.say("one\r\ntwo\rthree\nfour\r\n").await;
+    let received = bob
+        .recv_from(&alice.id)
+        .await
+        .expect("bob received nothing");
+    assert_eq!(received.as_string(), "one\ntwo\nthree\nfour\n");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn line_endings_are_kept_when_normalizing_is_off() {
+    let config = Config {
+        normalize_newlines: false,
+        ..Config::default()
+    };
+    let server = spawn_test_server_with(config).await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+
+    alice.say("one\r\ntwo").await;
+    let received = bob
+        .recv_from(&alice.id)
+        .await
+        .expect("bob received nothing");
+    assert_eq!(received.as_string(), "one\r\ntwo");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn resumed_session_keeps_its_start_time() {
+    let server = spawn_test_server().await;
+    let alice = connect_test_client(&server).await;
+    let token = alice.session_token.clone();
+    let began = {
+        let user = server.state.roster.lock().await.users();
+        let user = user.iter().find(|user| user.client.id == alice.id).unwrap();
+        *user.connected_at.lock().await
+    };
+
+    // Drop off, and wait for the server to notice
+    drop(alice);
+    let deadline = Instant::now() + TIMEOUT;
+    while !server.state.departures.lock().await.contains_key(&token) {
+        assert!(
+            Instant::now() < deadline,
+            "the server never saw alice leave"
+        );
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    let mut again = connect_test_client(&server).await;
+    again.command(&format!(":resume {token}")).await;
+    // Commands are handled in order, so once this is answered so is :resume
+    again.command(":whoami").await;
+    again
+        .recv_kind(MessageKind::ServerBroadcast)
+        .await
+        .expect("no reply to :whoami");
+
+    let users = server.state.roster.lock().await.users();
+    let user = users
+        .iter()
+        .find(|user| user.client.id == again.id)
+        .unwrap();
+    assert_eq!(*user.connected_at.lock().await, began);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn notice_to_nobody_is_kept_for_later() {
+    let server = spawn_test_server().await;
+    announce(&server.state, String::from("Maintenance at noon")).await;
+
+    let recent = server.state.history.lock().await.recent_global(10);
+    assert!(
+        recent
+            .iter()
+            .any(|message| message.as_string() == "Maintenance at noon")
+    );
+
+    // Someone who turns up afterwards can still read it
+    let mut alice = connect_test_client(&server).await;
+    alice.command(":history").await;
+    let replayed = alice
+        .recv_kind(MessageKind::ServerBroadcast)
+        .await
+        .expect("nothing came back from :history");
+    assert_eq!(replayed.as_string(), "Maintenance at noon");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn history_requests_are_capped() {
+    let server = spawn_test_server_with(Config {
+        max_history_request: 4,
+        ..Config::default()
+    })
+    .await;
+    let mut alice = connect_test_client(&server).await;
+    let alice_id = alice.id.clone();
+    for n in 0..6 {
+        alice.say(&format!("message {n}")).await;
+        alice.recv_from(&alice_id).await.expect("alice got no echo");
+    }
+
+    // However many are asked for, only the last four come back
+    alice.command(":history 100000").await;
+    let notice = alice.recv().await.expect("no reply to :history");
+    assert_eq!(notice.as_string(), "Only the last 4 messages can be shown");
+    for n in 2..6 {
+        let replayed = alice.recv().await.expect("the history was cut short");
+        assert_eq!(replayed.kind, MessageKind::Message);
+        assert_eq!(replayed.as_string(), format!("message {n}"));
+    }
+    alice.command(":whoami").await;
+    let next = alice.recv().await.expect("no reply to :whoami");
+    assert!(next.as_string().starts_with("You are "));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn unusable_history_counts_are_refused() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+
+    for count in ["0", "100000000000000000000000"] {
+        alice.command(&format!(":history {count}")).await;
+        let reply = alice.recv().await.expect("no reply to :history");
+        assert_eq!(reply.kind, MessageKind::ServerBroadcast);
+        assert_eq!(reply.as_string(), ":history was given an invalid <count>");
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn direct_message_keeps_its_destination() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    let mut carol = connect_test_client(&server).await;
+
+    // The destination is whatever the client put in the frame, and the
+    // server relays it as such rather than working it out from the text
+    let mut message = Message::from_string(
+        Arc::clone(&alice.client),
+        String::from("just for bob"),
+        MessageKind::Message,
+    );
+    message.channel = Destination::Direct(Client {
+        id: bob.id.clone(),
+        address: String::new(),
+    });
+    alice.send(message).await;
+
+    let received = bob
+        .recv_from(&alice.id)
+        .await
+        .expect("bob received nothing");
+    assert_eq!(received.as_string(), "just for bob");
+    match received.channel {
+        Destination::Direct(client) => assert_eq!(client.id, bob.id),
+        _ => panic!("expected a direct message"),
+    }
+
+    // Nobody else hears it
+    carol.say("anyone there?").await;
+    let next = carol
+        .recv_kind(MessageKind::Message)
+        .await
+        .expect("carol got no echo");
+    assert_eq!(next.as_string(), "anyone there?");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn direct_message_stays_out_of_the_history() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    bob.command(":name bob").await;
+    bob.recv_kind(MessageKind::Ack)
+        .await
+        .expect("bob's name was not acked");
+
+    alice.command(":msg bob just for bob").await;
+    let received = bob
+        .recv_from(&alice.id)
+        .await
+        .expect("bob received nothing");
+    assert_eq!(received.as_string(), "just for bob");
+    let id = received.id.expect("the message has no id");
+
+    let history = server.state.history.lock().await;
+    assert!(history.get(id).is_none());
+    drop(history);
+
+    // So it can't be replied to in public, or replayed to a newcomer
+    bob.command(&format!(":reply {id} thanks")).await;
+    let reply = bob
+        .recv_kind(MessageKind::ServerBroadcast)
+        .await
+        .expect("no reply to :reply");
+    assert_eq!(reply.as_string(), format!("No message with id {id}"));
+
+    let mut carol = connect_test_client(&server).await;
+    carol.command(":history").await;
+    carol.command(":whoami").await;
+    let next = carol
+        .recv_kind(MessageKind::ServerBroadcast)
+        .await
+        .expect("no reply from the server");
+    assert!(
+        next.as_string().starts_with("You are "),
+        "{}",
+        next.as_string()
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn reply_is_linked_to_the_message_it_answers() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+
+    alice.say("anyone up for a game?").await;
+    let question = bob
+        .recv_from(&alice.id)
+        .await
+        .expect("bob received nothing");
+    let id = question.id.expect("the message has no id");
+
+    bob.command(&format!(":reply {id} count me in")).await;
+    let reply = alice.recv_from(&bob.id).await.expect("alice got no reply");
+    assert_eq!(reply.as_string(), "count me in");
+    assert_eq!(reply.in_reply_to, Some(id));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn reply_to_a_missing_message_is_refused() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+
+    alice.command(":reply 9999 what was that?").await;
+    let notice = alice
+        .recv_kind(MessageKind::ServerBroadcast)
+        .await
+        .expect("no reply to :reply");
+    assert_eq!(notice.as_string(), "No message with id 9999");
+
+    // Nothing was relayed either
+    bob.say("still quiet").await;
+    let next = alice
+        .recv_kind(MessageKind::Message)
+        .await
+        .expect("alice heard nothing");
+    assert_eq!(next.as_string(), "still quiet");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn messages_carry_the_real_address() {
+    let server = spawn_test_server_with(Config {
+        history_replay: 10,
+        ..Config::default()
+    })
+    .await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+
+    let mut message = Message::from_string(
+        Arc::clone(&alice.client),
+        String::from("it was bob"),
+        MessageKind::Message,
+    );
+    message.address = bob.client.address.clone();
+    alice.send(message).await;
+
+    let received = bob
+        .recv_from(&alice.id)
+        .await
+        .expect("bob received nothing");
+    // Only the server keeps the address, nobody else gets to see it
+    assert!(received.address.is_empty(), "bob was shown alice's address");
+
+    let recent = server.state.history.lock().await.recent_global(10);
+    let recorded = recent
+        .iter()
+        .find(|message| message.as_string() == "it was bob")
+        .expect("the message was not recorded");
+    assert_eq!(recorded.address, alice.client.address);
+
+    // Nor is it in what a newcomer is caught up on
+    let mut carol = join_test_client(&server).await;
+    let replayed = carol
+        .recv_from(&alice.id)
+        .await
+        .expect("carol was not caught up");
+    assert!(
+        replayed.address.is_empty(),
+        "carol was shown alice's address"
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn clients_cannot_send_what_only_the_server_sends() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+
+    for kind in [
+        MessageKind::ServerBroadcast,
+        MessageKind::Ack,
+        MessageKind::Ping,
+    ] {
+        let forged = Message::from_string(
+            Arc::clone(&alice.client),
+            String::from("Server restarting, send your password to alice"),
+            kind,
+        );
+        alice.send(forged).await;
+    }
+
+    // None of them reached bob, and alice is still connected to say so
+    alice.say("only me").await;
+    let next = bob.recv().await.expect("bob received nothing");
+    assert_eq!(next.kind, MessageKind::Message);
+    assert_eq!(next.as_string(), "only me");
+    let recorded = server.state.history.lock().await.recent_global(10);
+    assert_eq!(recorded.len(), 1, "a forged message was recorded");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn empty_frame_is_skipped() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+
+    // A length prefix of zero, and nothing after it
+    alice
+        .stream
+        .write_all(&[0; frame::HEADER_LEN])
+        .await
+        .expect("could not send the empty frame");
+    alice.say("still here").await;
+
+    let received = bob.recv_from(&alice.id).await.expect("alice was dropped");
+    assert_eq!(received.as_string(), "still here");
+
+    server.stop().await;
+}
+
+// A user whose outbox the test reads from directly, with no connection
+// behind it
+fn outbox_user() -> (Arc<User>, Receiver<Vec<u8>>) {
+    let (connection, _) = duplex(64);
+    let user = Arc::new(User::over(Box::new(connection), String::from("test")));
+    let (outbox, frames) = mpsc::channel(64);
+    *user.outbox.try_lock().unwrap() = Some(outbox);
+    (user, frames)
+}
+
+// A user served over one end of an in-memory pipe, with the test holding
+// the other end as the client, and reading the user's outbox directly
+fn duplex_user() -> (Arc<User>, DuplexStream, Receiver<Vec<u8>>) {
+    let (near, far) = duplex(64 * 1024);
+    let user = Arc::new(User::over(Box::new(far), String::from("memory")));
+    let (outbox, frames) = mpsc::channel(64);
+    *user.outbox.try_lock().unwrap() = Some(outbox);
+    (user, near, frames)
+}
+
+// The next frame in a user's outbox, decoded
+async fn next_in(frames: &mut Receiver<Vec<u8>>) -> Message {
+    let bytes = timeout(TIMEOUT, frames.recv())
+        .await
+        .expect("nothing was queued for the user")
+        .expect("the outbox closed");
+    frame::decode(&bytes[frame::HEADER_LEN..]).expect("frame did not decode")
+}
+
+fn chat_in(frames: &mut Receiver<Vec<u8>>) -> Vec<String> {
+    let mut chat = Vec::new();
+    while let Ok(bytes) = frames.try_recv() {
+        let message = frame::decode(&bytes[frame::HEADER_LEN..]).expect("frame did not decode");
+        if message.kind == MessageKind::Message {
+            chat.push(message.as_string());
+        }
+    }
+    chat
+}
+
+#[tokio::test]
+async fn handle_client_runs_over_an_in_memory_pipe() {
+    let state = Arc::new(ServerState::new(Config::default()));
+    let (user, mut client, mut frames) = duplex_user();
+    let (tx, mut relayed) = mpsc::channel(8);
+    let reader = tokio::spawn(handle_client(Arc::clone(&state), Arc::clone(&user), tx));
+    let max = state.config.max_frame_size;
+    let author = Arc::new(Client::new(String::from("client")));
+
+    // Chat goes on to the writer with the user as its author
+    let said = Message::from_string(Arc::clone(&author), "hello".into(), MessageKind::Message);
+    frame::write_frame(&mut client, &said, max).await.unwrap();
+    let message = timeout(TIMEOUT, relayed.recv())
+        .await
+        .expect("nothing was relayed")
+        .expect("the relay closed");
+    assert_eq!(message.as_string(), "hello");
+    assert_eq!(message.author_id.as_deref(), Some(user.client.id.as_str()));
+    assert_eq!(next_in(&mut frames).await.kind, MessageKind::Ack);
+
+    // Commands are answered to the user alone
+    let command = Message::from_string(author, ":whoami".into(), MessageKind::Command);
+    frame::write_frame(&mut client, &command, max)
+        .await
+        .unwrap();
+    let reply = next_in(&mut frames).await;
+    assert_eq!(reply.kind, MessageKind::ServerBroadcast);
+    assert!(reply.as_string().starts_with("You are "));
+
+    // Hanging up ends the session, and with it the user's outbox
+    drop(client);
+    timeout(TIMEOUT, reader)
+        .await
+        .expect("handle_client did not notice the hang up")
+        .expect("handle_client failed");
+    assert!(user.outbox.lock().await.is_none());
+}
+
+#[tokio::test]
+async fn direct_message_is_routed_by_id_to_its_recipient_alone() {
+    let state = Arc::new(ServerState::new(Config::default()));
+    let (alice, mut alice_frames) = outbox_user();
+    let (bob, mut bob_frames) = outbox_user();
+    let (carol, mut carol_frames) = outbox_user();
+    for user in [&alice, &bob, &carol] {
+        join_roster(&state, user).await;
+    }
+    let (tx, rx) = mpsc::channel(8);
+    let writer = tokio::spawn(handle_writes(Arc::clone(&state), rx));
+
+    // Whatever address the sender claims for bob, only his id counts
+    let to_bob = Destination::Direct(Client {
+        id: bob.client.id.clone(),
+        address: String::from("10.0.0.1:1"),
+    });
+    let text = b"just for bob".to_vec();
+    send_message(text, MessageKind::Message, to_bob, &alice, &tx, &state)
+        .await
+        .expect("could not send");
+
+    let received = next_in(&mut bob_frames).await;
+    assert_eq!(received.as_string(), "just for bob");
+    match received.channel {
+        Destination::Direct(client) => {
+            assert_eq!(client.id, bob.client.id);
+            assert!(client.address.is_empty(), "bob's address went out");
+        }
+        _ => panic!("expected a direct message"),
+    }
+
+    // Alice has her echo and its ack, and neither says where bob is
+    let echo = next_in(&mut alice_frames).await;
+    let ack = next_in(&mut alice_frames).await;
+    let echo = if echo.kind == MessageKind::Ack {
+        ack
+    } else {
+        echo
+    };
+    assert_eq!(echo.as_string(), "just for bob");
+    match echo.channel {
+        Destination::Direct(client) => assert!(client.address.is_empty()),
+        _ => panic!("expected a direct message"),
+    }
+
+    // Someone who isn't connected can't be sent anything
+    let to_nobody = Destination::Direct(Client {
+        id: String::from("nobody"),
+        address: String::new(),
+    });
+    let text = b"hello?".to_vec();
+    send_message(text, MessageKind::Message, to_nobody, &alice, &tx, &state)
+        .await
+        .expect("could not send");
+    let reply = next_in(&mut alice_frames).await;
+    assert_eq!(reply.kind, MessageKind::ServerBroadcast);
+    assert_eq!(reply.as_string(), "No such user is connected");
+
+    drop(tx);
+    writer.await.expect("the writer failed");
+    assert!(
+        chat_in(&mut carol_frames).is_empty(),
+        "carol heard a private message"
+    );
+    assert!(chat_in(&mut bob_frames).is_empty());
+}
+
+#[tokio::test]
+async fn sender_is_told_when_their_message_cannot_be_relayed() {
+    let state = Arc::new(ServerState::new(Config::default()));
+    let (user, mut client, mut frames) = duplex_user();
+    join_roster(&state, &user).await;
+    // Nothing will ever take what the reader relays
+    let (tx, relayed) = mpsc::channel(8);
+    drop(relayed);
+    let reader = tokio::spawn(handle_client(Arc::clone(&state), Arc::clone(&user), tx));
+
+    let author = Arc::new(Client::new(String::from("client")));
+    let said = Message::from_string(author, "anyone?".into(), MessageKind::Message);
+    let max = state.config.max_frame_size;
+    frame::write_frame(&mut client, &said, max).await.unwrap();
+
+    // The user is asked to send it again, and the connection is let go
+    let notice = next_in(&mut frames).await;
+    assert_eq!(notice.kind, MessageKind::ServerBroadcast);
+    assert_eq!(
+        notice.as_string(),
+        "Your message could not be delivered, please send it again"
+    );
+    timeout(TIMEOUT, reader)
+        .await
+        .expect("the reader did not finish")
+        .expect("the reader panicked");
+    assert!(state.roster.lock().await.is_empty());
+}
+
+#[tokio::test]
+async fn frame_trickling_in_two_bytes_at_a_time_is_one_message() {
+    let state = Arc::new(ServerState::new(Config::default()));
+    let (user, mut client, mut frames) = duplex_user();
+    let (tx, mut relayed) = mpsc::channel(8);
+    tokio::spawn(handle_client(Arc::clone(&state), Arc::clone(&user), tx));
+    let max = state.config.max_frame_size;
+    let author = Arc::new(Client::new(String::from("client")));
+
+    let said = Message::from_string(Arc::clone(&author), "slowly".into(), MessageKind::Message);
+    let bytes = frame::encode(&said, max).unwrap();
+    for pair in bytes.chunks(2) {
+        client.write_all(pair).await.unwrap();
+        client.flush().await.unwrap();
+        tokio::task::yield_now().await;
+    }
+
+    let message = timeout(TIMEOUT, relayed.recv())
+        .await
+        .expect("nothing was relayed")
+        .expect("the relay closed");
+    assert_eq!(message.as_string(), "slowly");
+    assert_eq!(next_in(&mut frames).await.kind, MessageKind::Ack);
+
+    // The connection is still open, and nothing else came of it
+    let command = Message::from_string(author, ":whoami".into(), MessageKind::Command);
+    frame::write_frame(&mut client, &command, max)
+        .await
+        .unwrap();
+    let reply = next_in(&mut frames).await;
+    assert!(reply.as_string().starts_with("You are "));
+    assert!(relayed.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn message_sent_during_a_join_arrives_once_and_in_order() {
+    let config = Config {
+        history_replay: 10,
+        ..Config::default()
+    };
+    let state = Arc::new(ServerState::new(config));
+    let (alice, _alice_frames) = outbox_user();
+    let (bob, mut bob_frames) = outbox_user();
+    join_roster(&state, &alice).await;
+
+    // Alice's message is accepted, but the writer hasn't got to it yet
+    // when bob joins, so he has it in his replay
+    let (tx, rx) = mpsc::channel(8);
+    let text = String::from("said as bob joined");
+    relay_message(
+        text,
+        MessageKind::Message,
+        None,
+        Destination::Global,
+        &alice,
+        &tx,
+        &state,
+    )
+    .await
+    .expect("could not relay");
+    join_roster(&state, &bob).await;
+
+    // Then the writer catches up, and bob says something himself
+    let writer = tokio::spawn(handle_writes(Arc::clone(&state), rx));
+    let text = String::from("bob here");
+    relay_message(
+        text,
+        MessageKind::Message,
+        None,
+        Destination::Global,
+        &bob,
+        &tx,
+        &state,
+    )
+    .await
+    .expect("could not relay");
+    drop(tx);
+    writer.await.expect("the writer failed");
+
+    assert_eq!(chat_in(&mut bob_frames), ["said as bob joined", "bob here"]);
+}
+
+#[tokio::test]
+async fn newcomer_gets_exactly_the_last_messages_in_order() {
+    let config = Config {
+        history_replay: 5,
+        ..Config::default()
+    };
+    let server = spawn_test_server_with(config).await;
+    let mut alice = connect_test_client(&server).await;
+    let alice_id = alice.id.clone();
+    for n in 0..10 {
+        alice.say(&format!("message {n}")).await;
+    }
+    // Once alice has heard herself say them all, every one is in the history
+    for n in 0..10 {
+        let echo = alice.recv_from(&alice_id).await.expect("alice got no echo");
+        assert_eq!(echo.as_string(), format!("message {n}"));
+    }
+
+    let mut bob = join_test_client(&server).await;
+    for n in 5..10 {
+        let replayed = bob.recv().await.expect("the replay was cut short");
+        assert_eq!(replayed.kind, MessageKind::Message);
+        assert_eq!(replayed.as_string(), format!("message {n}"));
+    }
+    // Nothing more is replayed after them
+    bob.command(":whoami").await;
+    let next = bob.recv().await.expect("no reply to :whoami");
+    assert_eq!(next.kind, MessageKind::ServerBroadcast);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn only_the_first_to_ask_gets_a_nickname() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+
+    alice.command(":name alice").await;
+    alice.command(":whoami").await;
+    let reply = alice.recv_kind(MessageKind::ServerBroadcast).await.unwrap();
+    assert!(reply.as_string().starts_with("You are alice "));
+
+    let notice = bob.recv_kind(MessageKind::ServerBroadcast).await.unwrap();
+    assert_eq!(notice.as_string(), "alice joined");
+
+    // Differently cased, it is still the same name
+    bob.command(":name Alice").await;
+    let reply = bob.recv_kind(MessageKind::ServerBroadcast).await.unwrap();
+    assert_eq!(reply.as_string(), "nickname taken");
+    bob.command(":whoami").await;
+    let reply = bob.recv_kind(MessageKind::ServerBroadcast).await.unwrap();
+    assert!(
+        !reply
+            .as_string()
+            .to_lowercase()
+            .starts_with("you are alice ")
+    );
+
+    // And it is alice who is found by it
+    bob.command(":msg ALICE psst").await;
+    let received = alice
+        .recv_from(&bob.id)
+        .await
+        .expect("alice received nothing");
+    assert_eq!(received.as_string(), "psst");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn a_departing_user_gives_up_their_name() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    let mut carol = connect_test_client(&server).await;
+    alice.command(":name alice").await;
+    alice
+        .recv_kind(MessageKind::Ack)
+        .await
+        .expect("alice's name was not acked");
+
+    // Alice is on her way out but still on the roster, as she is while
+    // her connection is torn down
+    let users = server.state.roster.lock().await.users();
+    for user in users {
+        if user.client.id == alice.id {
+            *user.is_active.lock().await = false;
+        }
+    }
+
+    carol.command(":name alice").await;
+    carol
+        .recv_kind(MessageKind::Ack)
+        .await
+        .expect("carol's name was not acked");
+    bob.command(":msg alice hello").await;
+    let received = carol
+        .recv_from(&bob.id)
+        .await
+        .expect("carol received nothing");
+    assert_eq!(received.as_string(), "hello");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn unusable_nicknames_are_refused_with_the_rule() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+
+    let too_long = "a".repeat(server.state.config.nick_max_len + 1);
+    let refusals = [
+        (
+            too_long.as_str(),
+            "Nicknames can be at most 32 characters long",
+        ),
+        ("al\u{7}ice", "Nicknames can't contain control characters"),
+    ];
+    for (name, rule) in refusals {
+        alice.command(&format!(":name {name}")).await;
+        let reply = alice.recv().await.expect("no reply to :name");
+        assert_eq!(reply.kind, MessageKind::ServerBroadcast);
+        assert_eq!(reply.as_string(), rule);
+    }
+
+    // Neither was taken
+    alice.command(":whoami").await;
+    let reply = alice.recv().await.expect("no reply to :whoami");
+    assert!(
+        reply
+            .as_string()
+            .starts_with(&format!("You are {} ", alice.client.address)),
+        "{}",
+        reply.as_string()
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn only_accepted_changes_are_acked() {
+    let config = Config {
+        auto_create_channels: false,
+        ..Config::default()
+    };
+    let server = spawn_test_server_with(config).await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    alice.command(":name alice").await;
+    let ack = alice
+        .recv_kind(MessageKind::Ack)
+        .await
+        .expect("no ack of the name");
+    assert_eq!(ack.author.as_deref(), Some("alice"));
+
+    let notice = bob.recv().await.expect("bob was not told alice joined");
+    assert_eq!(notice.as_string(), "alice joined");
+
+    // Refused, so nothing comes before the reply saying why
+    bob.command(":name alice").await;
+    bob.command(":join nowhere").await;
+    let reply = bob.recv().await.expect("no reply to :name");
+    assert_eq!(reply.as_string(), "nickname taken");
+    let reply = bob.recv().await.expect("no reply to :join");
+    assert_eq!(reply.kind, MessageKind::ServerBroadcast);
+    assert_eq!(reply.as_string(), "No channel named nowhere");
+
+    server.stop().await;
+}
+
+// An action is relayed as one, under the name of whoever did it, so the
+// client can show it as "* alice waves"
+#[tokio::test]
+async fn actions_are_relayed_with_the_senders_name() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    alice.command(":name alice").await;
+    alice
+        .recv_kind(MessageKind::Ack)
+        .await
+        .expect("the name was not acked");
+
+    let action = Message::from_string(
+        Arc::clone(&alice.client),
+        String::from("waves"),
+        MessageKind::Action,
+    );
+    alice.send(action).await;
+    let heard = bob
+        .recv_kind(MessageKind::Action)
+        .await
+        .expect("bob never saw the action");
+    assert_eq!(heard.as_string(), "waves");
+    assert_eq!(heard.author.as_deref(), Some("alice"));
+    assert_eq!(heard.author_id.as_deref(), Some(alice.id.as_str()));
+
+    server.stop().await;
+}
+
+// The kind and text of everything a client is sent for saying each text,
+// up to the answer to a :whoami sent after them, which marks where it ends
+async fn sent_for_saying(client: &mut TestClient, texts: &[&str]) -> Vec<(MessageKind, String)> {
+    for text in texts {
+        client.say(text).await;
+    }
+    client.command(":whoami").await;
+    let mut sent = Vec::new();
+    loop {
+        let message = client.recv().await.expect("the server went quiet");
+        let text = message.as_string();
+        if text.starts_with("You are ") {
+            return sent;
+        }
+        sent.push((message.kind, text));
+    }
+}
+
+#[tokio::test]
+async fn the_first_message_hint_is_sent_once_before_the_first_message() {
+    let hint = "Tip: use :help for commands";
+    let server = spawn_test_server_with(Config {
+        first_message_hint: Some(hint.to_string()),
+        ..Config::default()
+    })
+    .await;
+    let mut alice = connect_test_client(&server).await;
+
+    let sent = sent_for_saying(&mut alice, &["one", "two", "three"]).await;
+    let hinted = (MessageKind::ServerBroadcast, hint.to_string());
+    assert_eq!(sent.iter().filter(|&m| *m == hinted).count(), 1, "{sent:?}");
+    let first_echo = sent
+        .iter()
+        .position(|(kind, _)| *kind == MessageKind::Message);
+    assert!(
+        sent.iter().position(|m| *m == hinted) < first_echo,
+        "the hint came after the message: {sent:?}"
+    );
+
+    // It was only ever the first
+    let sent = sent_for_saying(&mut alice, &["four"]).await;
+    assert!(!sent.contains(&hinted), "{sent:?}");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn there_is_no_first_message_hint_unless_configured() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+
+    let sent = sent_for_saying(&mut alice, &["one"]).await;
+    let notices = sent
+        .iter()
+        .filter(|(kind, _)| *kind == MessageKind::ServerBroadcast);
+    assert_eq!(notices.count(), 0, "{sent:?}");
+
+    server.stop().await;
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use chat_server::ServerState;
+use chat_shared::{Config, message::MessageKind};
+use common::{connect_test_client, spawn_test_server_from};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+// Remove the event log and everything kept beside it
+fn remove_log(path: &Path) {
+    for suffix in ["", ".1", ".members"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        let _ = fs::remove_file(file);
+    }
+}
+
+async fn open(config: &Config) -> ServerState {
+    let mut state = ServerState::new(config.clone());
+    state
+        .open_event_log()
+        .await
+        .expect("could not open the event log");
+    state
+}
+
+// Each test keeps its own log, so they can run side by side
+fn with_log(name: &str) -> (PathBuf, Config) {
+    let path = env::temp_dir().join(format!("chat_event_log_{name}_{}.log", process::id()));
+    remove_log(&path);
+    let config = Config {
+        event_log_path: Some(path.clone()),
+        replay_event_log: true,
+        ..Config::default()
+    };
+    (path, config)
+}
+
+#[tokio::test]
+async fn history_survives_a_restart() {
+    let (path, config) = with_log("restart");
+
+    let server = spawn_test_server_from(open(&config).await).await;
+    let mut alice = connect_test_client(&server).await;
+    let mut last_id = 0;
+    for text in ["one", "two", "three"] {
+        alice.say(text).await;
+        let echo = alice
+            .recv_kind(MessageKind::Message)
+            .await
+            .expect("no echo of the message");
+        last_id = echo.id.expect("the echo has no id");
+    }
+    server.stop().await;
+
+    // Start over from the same log, as the server would after a restart
+    let restarted = open(&config).await;
+    let texts: Vec<String> = restarted
+        .history
+        .lock()
+        .await
+        .recent_global(10)
+        .iter()
+        .map(|message| message.as_string())
+        .collect();
+    assert_eq!(texts, ["one", "two", "three"]);
+    assert_eq!(restarted.assign_message_id(), last_id + 1);
+
+    remove_log(&path);
+}
+
+#[tokio::test]
+async fn private_messages_are_not_logged() {
+    let (path, config) = with_log("private");
+
+    let server = spawn_test_server_from(open(&config).await).await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    bob.command(":name bob").await;
+    bob.recv_kind(MessageKind::Ack)
+        .await
+        .expect("bob's name was not acked");
+    alice.command(":msg bob just for bob").await;
+    alice.say("for everyone").await;
+    for _ in 0..2 {
+        alice
+            .recv_kind(MessageKind::Message)
+            .await
+            .expect("no echo of the message");
+    }
+    server.stop().await;
+
+    let stored = open(&config)
+        .await
+        .storage
+        .recent_messages(10)
+        .await
+        .expect("could not read the log");
+    let texts: Vec<String> = stored.iter().map(|message| message.as_string()).collect();
+    assert_eq!(texts, ["for everyone"]);
+
+    remove_log(&path);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use chat_shared::{
+    Config,
+    message::{Message, MessageKind},
+};
+use common::{TIMEOUT, connect_test_client, spawn_test_server_with, wait_for_roster};
+use std::sync::Arc;
+use tokio::time::Instant;
+
+// A client that never answers the server's pings is disconnected once it
+// has missed too many, while one that answers stays on
+#[tokio::test]
+async fn client_that_never_answers_pings_is_evicted() {
+    // Nobody is going to resume, so whoever is evicted leaves straight away
+    let config = Config {
+        heartbeat_secs: Some(1),
+        heartbeat_miss_limit: 1,
+        reconnect_grace_secs: 0,
+        ..Config::default()
+    };
+    let server = spawn_test_server_with(config).await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    wait_for_roster(&server, 2).await;
+
+    // Alice answers the next few pings she is sent, which takes longer
+    // than it takes not answering to get bob thrown out
+    let answering = tokio::spawn(async move {
+        for _ in 0..3 {
+            alice
+                .recv_kind(MessageKind::Ping)
+                .await
+                .expect("alice stopped being pinged");
+            let pong =
+                Message::from_string(Arc::clone(&alice.client), String::new(), MessageKind::Pong);
+            alice.send(pong).await;
+        }
+        alice
+    });
+
+    // Bob reads his pings but never answers them
+    let deadline = Instant::now() + TIMEOUT;
+    while bob.recv().await.is_some() {
+        assert!(Instant::now() < deadline, "bob was never disconnected");
+    }
+    wait_for_roster(&server, 1).await;
+
+    let alice = answering.await.expect("alice's task failed");
+    let roster = server.state.roster.lock().await.users();
+    assert_eq!(roster[0].client.id, alice.id);
+
+    server.stop().await;
+}
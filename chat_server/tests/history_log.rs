@@ -0,0 +1,83 @@
+mod common;
+
+use chat_server::ServerState;
+use chat_shared::{Config, Message};
+use common::{Logs, TIMEOUT, connect_test_client, spawn_test_server_from};
+use std::{env, fs, path::PathBuf, process, time::Duration};
+use tokio::time::{Instant, sleep};
+
+fn with_history(path: PathBuf) -> ServerState {
+    let mut state = ServerState::new(Config {
+        history_path: Some(path),
+        ..Config::default()
+    });
+    state.open_history_log();
+    state
+}
+
+#[tokio::test]
+async fn each_message_is_one_line_of_the_transcript() {
+    let path = env::temp_dir().join(format!("chat_history_{}.ron", process::id()));
+    let _ = fs::remove_file(&path);
+    let server = spawn_test_server_from(with_history(path.clone())).await;
+    let mut alice = connect_test_client(&server).await;
+    let alice_id = alice.id.clone();
+    for n in 0..3 {
+        alice.say(&format!("message {n}")).await;
+        alice.recv_from(&alice_id).await.expect("alice got no echo");
+    }
+
+    // The file is written on a thread of its own, so wait for it to catch up
+    let deadline = Instant::now() + TIMEOUT;
+    while fs::read_to_string(&path)
+        .unwrap_or_default()
+        .lines()
+        .count()
+        < 3
+    {
+        assert!(
+            Instant::now() < deadline,
+            "the transcript was never written"
+        );
+        sleep(Duration::from_millis(10)).await;
+    }
+    server.stop().await;
+
+    let transcript = fs::read_to_string(&path).unwrap();
+    let said: Vec<String> = transcript
+        .lines()
+        .map(|line| ron::from_str::<Message>(line).unwrap().as_string())
+        .collect();
+    assert_eq!(said, ["message 0", "message 1", "message 2"]);
+    let _ = fs::remove_file(path);
+}
+
+// The test runtime runs every task on this thread, so a logger set for the
+// thread hears from the whole server
+#[tokio::test]
+async fn an_unusable_path_is_logged_and_the_server_carries_on() {
+    let logs = Logs::default();
+    let logger = tracing_subscriber::fmt()
+        .with_writer(logs.clone())
+        .with_ansi(false)
+        .finish();
+    let _logger = tracing::subscriber::set_default(logger);
+
+    let missing = env::temp_dir().join(format!("chat_missing_{}", process::id()));
+    let state = with_history(missing.join("history.ron"));
+    assert!(state.history_log.is_none());
+    let contents = logs.contents();
+    assert!(
+        contents.contains("Could not open the history log"),
+        "no error in:\n{contents}"
+    );
+
+    let server = spawn_test_server_from(state).await;
+    let mut alice = connect_test_client(&server).await;
+    let alice_id = alice.id.clone();
+    alice.say("still here").await;
+    let echo = alice.recv_from(&alice_id).await.expect("alice got no echo");
+    assert_eq!(echo.as_string(), "still here");
+
+    server.stop().await;
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use chat_shared::{
+    Config, IgnoreMode,
+    message::{Destination, MessageKind},
+};
+use common::{connect_test_client, spawn_test_server_with};
+
+#[tokio::test]
+async fn ignored_users_messages_are_held_back() {
+    let server = spawn_test_server_with(Config {
+        ignore_mode: IgnoreMode::Server,
+        ..Config::default()
+    })
+    .await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    let mut carol = connect_test_client(&server).await;
+    bob.name("bob").await;
+    carol.name("carol").await;
+
+    alice.command(":ignore bob").await;
+    assert_eq!(
+        alice.next_reply().await,
+        "The server will no longer send you messages from bob"
+    );
+
+    // Bob is never heard, so the first thing alice gets is from carol
+    bob.say("can you hear me?").await;
+    let bob_id = bob.id.clone();
+    bob.recv_from(&bob_id).await.expect("bob got no echo");
+    carol.say("I can").await;
+    let next = alice
+        .recv_kind(MessageKind::Message)
+        .await
+        .expect("alice heard nothing");
+    assert_eq!(next.author_id.as_deref(), Some(carol.id.as_str()));
+    assert_eq!(next.as_string(), "I can");
+
+    // Until she stops ignoring him
+    alice.command(":unignore bob").await;
+    alice.next_reply().await;
+    bob.say("how about now?").await;
+    let next = alice.recv_from(&bob.id).await.expect("alice heard nothing");
+    assert_eq!(next.as_string(), "how about now?");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn ignore_without_a_name_lists_who_is_ignored() {
+    let server = spawn_test_server_with(Config {
+        ignore_mode: IgnoreMode::Server,
+        ..Config::default()
+    })
+    .await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    bob.name("bob").await;
+
+    alice.command(":ignore").await;
+    assert_eq!(alice.next_reply().await, "You aren't ignoring anyone");
+
+    alice.command(":ignore bob").await;
+    alice.next_reply().await;
+    alice.command(":ignore").await;
+    assert_eq!(alice.next_reply().await, "Ignoring: bob");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn only_ignores_the_server_takes_are_acked() {
+    let server = spawn_test_server_with(Config {
+        ignore_mode: IgnoreMode::Server,
+        ..Config::default()
+    })
+    .await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    bob.name("bob").await;
+
+    // Nobody is called carol, so there is nothing to ack, and the first
+    // ack alice gets is for bob
+    alice.command(":ignore carol").await;
+    assert_eq!(alice.next_reply().await, "No user named carol is connected");
+    alice.command(":ignore bob").await;
+    let ack = alice
+        .recv_kind(MessageKind::Ack)
+        .await
+        .expect("the ignore was not acked");
+    assert_eq!(ack.as_string(), "ignore");
+    assert_eq!(ack.author.as_deref(), Some("bob"));
+    assert!(matches!(ack.channel, Destination::Direct(_)));
+
+    alice.command(":unignore bob").await;
+    let ack = alice
+        .recv_kind(MessageKind::Ack)
+        .await
+        .expect("the unignore was not acked");
+    assert_eq!(ack.as_string(), "unignore");
+
+    server.stop().await;
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use chat_shared::Role;
+use common::{ServerHandle, TestClient, connect_test_client, spawn_test_server};
+
+// Ask about someone and return the answer, skipping news of arrivals
+async fn info(client: &mut TestClient, name: &str) -> String {
+    client.command(&format!(":info {name}")).await;
+    client.next_reply().await
+}
+
+async fn make_admin(server: &ServerHandle, client: &TestClient) {
+    let users = server.state.roster.lock().await.users();
+    let user = users
+        .iter()
+        .find(|user| user.client.id == client.id)
+        .expect("the client is not on the roster");
+    *user.role.lock().await = Role::Admin;
+}
+
+#[tokio::test]
+async fn info_describes_a_connected_user() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    bob.name("bob").await;
+
+    let reply = info(&mut alice, "bob").await;
+    assert!(
+        reply.starts_with("bob (online, member) connected for "),
+        "{reply}"
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn info_on_an_unknown_user_says_so() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+
+    let reply = info(&mut alice, "nobody").await;
+    assert_eq!(reply, "No user named nobody is connected");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn only_admins_see_the_address() {
+    let server = spawn_test_server().await;
+    let mut admin = connect_test_client(&server).await;
+    let mut member = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    make_admin(&server, &admin).await;
+    bob.name("bob").await;
+    let address = bob.stream.local_addr().unwrap().to_string();
+
+    let reply = info(&mut admin, "bob").await;
+    assert!(reply.ends_with(&format!(" from {address}")), "{reply}");
+
+    let reply = info(&mut member, "bob").await;
+    assert!(!reply.contains(" from "), "{reply}");
+    assert!(!reply.contains(&address), "{reply}");
+
+    server.stop().await;
+}
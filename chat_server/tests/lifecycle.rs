@@ -0,0 +1,104 @@
+mod common;
+
+use chat_shared::{Config, frame, message::MessageKind};
+use common::{TIMEOUT, connect_test_client, spawn_test_server_with, wait_for_roster};
+use tokio::{net::TcpStream, time::timeout};
+
+// Everything a session goes through when nothing goes wrong, from the
+// first connection to the last goodbye
+#[tokio::test]
+async fn session_from_connect_to_leave() {
+    // Nobody is going to resume, so say they have left straight away
+    let config = Config {
+        reconnect_grace_secs: 0,
+        ..Config::default()
+    };
+    let server = spawn_test_server_with(config).await;
+
+    // Both are on the roster once the handshake is done
+    let mut alice = connect_test_client(&server).await;
+    wait_for_roster(&server, 1).await;
+    let mut bob = connect_test_client(&server).await;
+    wait_for_roster(&server, 2).await;
+
+    // Naming themselves tells everyone else they have arrived
+    alice.command(":name alice").await;
+    let notice = bob
+        .recv_kind(MessageKind::ServerBroadcast)
+        .await
+        .expect("bob was not told alice joined");
+    assert_eq!(notice.as_string(), "alice joined");
+
+    bob.command(":name bob").await;
+    let notice = alice
+        .recv_kind(MessageKind::ServerBroadcast)
+        .await
+        .expect("alice was not told bob joined");
+    assert_eq!(notice.as_string(), "bob joined");
+
+    // Chat goes out under the name they chose
+    alice.say("hello bob").await;
+    let received = bob
+        .recv_from(&alice.id)
+        .await
+        .expect("bob received nothing");
+    assert_eq!(received.as_string(), "hello bob");
+    assert_eq!(received.author.as_deref(), Some("alice"));
+
+    // Quitting ends the connection and the one left behind hears of it
+    alice.command(":quit").await;
+    while alice.recv().await.is_some() {}
+    wait_for_roster(&server, 1).await;
+    let notice = bob
+        .recv_kind(MessageKind::ServerBroadcast)
+        .await
+        .expect("bob was not told alice left");
+    assert_eq!(notice.as_string(), "alice left");
+
+    bob.command(":quit").await;
+    while bob.recv().await.is_some() {}
+    wait_for_roster(&server, 0).await;
+
+    server.stop().await;
+}
+
+// Past max_clients a newcomer is turned away, and those already on are
+// none the worse for it
+#[tokio::test]
+async fn client_past_the_limit_is_told_the_server_is_full() {
+    let config = Config {
+        max_clients: Some(2),
+        ..Config::default()
+    };
+    let server = spawn_test_server_with(config).await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    wait_for_roster(&server, 2).await;
+
+    // The newcomer gets no welcome, only the refusal and a hang-up
+    let mut carol = timeout(TIMEOUT, TcpStream::connect(server.address))
+        .await
+        .expect("timed out connecting")
+        .expect("could not connect");
+    let max = server.state.config.max_frame_size;
+    let refusal = timeout(TIMEOUT, frame::read_frame(&mut carol, max))
+        .await
+        .expect("carol was told nothing")
+        .expect("carol was not told why");
+    assert_eq!(refusal.kind, MessageKind::ServerBroadcast);
+    assert_eq!(refusal.as_string(), "Server full");
+    let next = timeout(TIMEOUT, frame::read_frame(&mut carol, max))
+        .await
+        .expect("carol's connection stayed open");
+    assert!(next.is_err(), "carol was sent more than the refusal");
+    assert_eq!(server.state.roster.lock().await.len(), 2);
+
+    alice.say("still here?").await;
+    let received = bob
+        .recv_from(&alice.id)
+        .await
+        .expect("bob received nothing");
+    assert_eq!(received.as_string(), "still here?");
+
+    server.stop().await;
+}
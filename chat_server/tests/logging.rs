@@ -0,0 +1,67 @@
+mod common;
+
+use chat_shared::Config;
+use common::{Logs, connect_test_client, spawn_test_server_with};
+use serde_json::Value;
+
+// Say something, and return everything the server logged as JSON by the
+// time it was echoed back. The test runtime runs every task on this thread,
+// so a logger set for the thread hears from the whole server
+async fn logged_while_chatting(config: Config, text: &str) -> (String, Vec<Value>) {
+    let logs = Logs::default();
+    let logger = tracing_subscriber::fmt()
+        .json()
+        .with_writer(logs.clone())
+        .finish();
+    let _logger = tracing::subscriber::set_default(logger);
+
+    let server = spawn_test_server_with(config).await;
+    let mut alice = connect_test_client(&server).await;
+    alice.say(text).await;
+    let alice_id = alice.id.clone();
+    alice.recv_from(&alice_id).await.expect("alice got no echo");
+    server.stop().await;
+
+    let entries = logs
+        .contents()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{e} in {line}")))
+        .collect();
+    (alice.client.address.clone(), entries)
+}
+
+// The entry with the given message, if there is one
+fn entry<'a>(entries: &'a [Value], message: &str) -> Option<&'a Value> {
+    entries
+        .iter()
+        .find(|entry| entry["fields"]["message"] == message)
+}
+
+#[tokio::test]
+async fn json_logs_carry_structured_fields() {
+    let (address, entries) = logged_while_chatting(Config::default(), "hello").await;
+
+    let connected = entry(&entries, "Client connected").expect("no connection was logged");
+    assert_eq!(connected["level"], "INFO");
+    assert_eq!(connected["fields"]["address"], address);
+}
+
+#[tokio::test]
+async fn chat_is_only_logged_when_asked_for() {
+    let (_, entries) = logged_while_chatting(Config::default(), "secret plans").await;
+    assert!(entry(&entries, "Message").is_none());
+    assert!(
+        entries
+            .iter()
+            .all(|entry| !entry.to_string().contains("secret plans"))
+    );
+
+    let config = Config {
+        log_chat_content: true,
+        ..Config::default()
+    };
+    let (_, entries) = logged_while_chatting(config, "secret plans").await;
+    let message = entry(&entries, "Message").expect("the message was not logged");
+    assert_eq!(message["fields"]["content"], "secret plans");
+    assert!(message["fields"]["id"].is_u64());
+}
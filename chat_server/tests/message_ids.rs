@@ -0,0 +1,81 @@
+mod common;
+
+use chat_server::ServerState;
+use chat_shared::{Config, message::MessageKind};
+use common::{connect_test_client, spawn_test_server};
+use std::{collections::HashSet, sync::Arc};
+
+#[tokio::test]
+async fn ids_handed_out_at_once_are_unique_and_only_grow() {
+    let state = Arc::new(ServerState::new(Config::default()));
+    let mut tasks = Vec::new();
+    for _ in 0..8 {
+        let state = Arc::clone(&state);
+        tasks.push(tokio::spawn(async move {
+            let mut ids = Vec::new();
+            for _ in 0..1000 {
+                ids.push(state.assign_message_id());
+                tokio::task::yield_now().await;
+            }
+            ids
+        }));
+    }
+
+    let mut seen = HashSet::new();
+    for task in tasks {
+        let ids = task.await.expect("a task failed");
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        for id in ids {
+            assert!(seen.insert(id), "id {id} was handed out twice");
+        }
+    }
+    assert_eq!(seen.len(), 8000);
+    assert_eq!(state.assign_message_id(), 8001);
+}
+
+#[tokio::test]
+async fn messages_sent_at_once_get_unique_ids_in_relay_order() {
+    let server = spawn_test_server().await;
+    let mut listener = connect_test_client(&server).await;
+    let mut tasks = Vec::new();
+    for _ in 0..4 {
+        let mut client = connect_test_client(&server).await;
+        tasks.push(tokio::spawn(async move {
+            for n in 0..10 {
+                client.say(&format!("message {n}")).await;
+            }
+            // The ack to each message carries the id it was given
+            let mut acked = Vec::new();
+            while acked.len() < 10 {
+                let ack = client
+                    .recv_kind(MessageKind::Ack)
+                    .await
+                    .expect("a message went unacked");
+                acked.extend(ack.id);
+            }
+            acked
+        }));
+    }
+
+    let mut acked = HashSet::new();
+    for task in tasks {
+        let ids = task.await.expect("a client task failed");
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        acked.extend(ids);
+    }
+    assert_eq!(acked.len(), 40);
+
+    // Everyone hears them in the order their ids were given out
+    let mut heard = Vec::new();
+    while heard.len() < 40 {
+        let message = listener
+            .recv_kind(MessageKind::Message)
+            .await
+            .expect("the listener missed a message");
+        heard.push(message.id.expect("a relayed message has no id"));
+    }
+    assert!(heard.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(heard.into_iter().collect::<HashSet<_>>(), acked);
+
+    server.stop().await;
+}
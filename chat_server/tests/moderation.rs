@@ -0,0 +1,97 @@
+mod common;
+
+use chat_shared::{Config, frame};
+use common::{Logs, TIMEOUT, connect_test_client, spawn_test_server_with};
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn kicked_client_is_told_why_before_being_let_go() {
+    // Everyone connecting from loopback is an admin
+    let server = spawn_test_server_with(Config {
+        admin_ips: vec!["127.0.0.1".parse().unwrap()],
+        ..Config::default()
+    })
+    .await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    let mut carol = connect_test_client(&server).await;
+    alice.name("alice").await;
+    bob.name("bob").await;
+    carol.name("carol").await;
+
+    alice.command(":kick bob flooding the channel").await;
+    loop {
+        let notice = bob.next_notice().await;
+        if notice.starts_with("You were kicked") {
+            assert_eq!(notice, "You were kicked by alice: flooding the channel");
+            break;
+        }
+    }
+    // And then the connection closes
+    let closed = timeout(TIMEOUT, async {
+        while frame::read_frame(&mut bob.stream, bob.max_frame_size)
+            .await
+            .is_ok()
+        {}
+    })
+    .await;
+    assert!(closed.is_ok(), "bob's connection stayed open");
+
+    // Without a reason, the notice just says who did it
+    alice.command(":kick carol").await;
+    loop {
+        let notice = carol.next_notice().await;
+        if notice.starts_with("You were kicked") {
+            assert_eq!(notice, "You were kicked by alice");
+            break;
+        }
+    }
+
+    server.stop().await;
+}
+
+// The test runtime runs every task on this thread, so a logger set for the
+// thread hears from the whole server
+#[tokio::test]
+async fn admin_actions_are_audited() {
+    let logs = Logs::default();
+    let logger = tracing_subscriber::fmt()
+        .with_writer(logs.clone())
+        .with_ansi(false)
+        .finish();
+    let _logger = tracing::subscriber::set_default(logger);
+
+    let server = spawn_test_server_with(Config {
+        admin_ips: vec!["127.0.0.1".parse().unwrap()],
+        ..Config::default()
+    })
+    .await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    alice.name("alice").await;
+    bob.name("bob").await;
+
+    alice.command(":kick bob").await;
+    loop {
+        let notice = alice.next_notice().await;
+        if notice == "Kicked bob" {
+            break;
+        }
+    }
+
+    let logs = logs.contents();
+    let entry = logs
+        .lines()
+        .find(|line| line.contains("Admin action"))
+        .unwrap_or_else(|| panic!("no audit entry in:\n{logs}"));
+    for field in [
+        "audit:",
+        "admin=alice",
+        "command=\"kick\"",
+        "target=\"bob\"",
+    ] {
+        assert!(entry.contains(field), "{field} missing from {entry}");
+    }
+
+    server.stop().await;
+}
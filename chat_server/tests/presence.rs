@@ -0,0 +1,103 @@
+mod common;
+
+use chat_shared::Config;
+use common::{TIMEOUT, connect_test_client, spawn_test_server_with};
+use std::time::Duration;
+use tokio::time::{Instant, sleep};
+
+#[tokio::test]
+async fn reconnecting_within_the_grace_period_says_nothing() {
+    let server = spawn_test_server_with(Config {
+        reconnect_grace_secs: 1,
+        ..Config::default()
+    })
+    .await;
+    let mut bob = connect_test_client(&server).await;
+    let mut alice = connect_test_client(&server).await;
+    alice.command(":name alice").await;
+    assert_eq!(bob.next_notice().await, "alice joined");
+
+    // Drop off, and wait for the server to notice
+    let token = alice.session_token.clone();
+    drop(alice);
+    let deadline = Instant::now() + TIMEOUT;
+    while !server.state.departures.lock().await.contains_key(&token) {
+        assert!(
+            Instant::now() < deadline,
+            "the server never saw alice leave"
+        );
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    // Come back as a client does, resuming and then naming ourselves
+    let mut again = connect_test_client(&server).await;
+    again.command(&format!(":resume {token}")).await;
+    again.command(":name alice").await;
+    again.command(":whoami").await;
+    assert!(again.next_notice().await.starts_with("You are alice "));
+
+    // Wait out the grace period. The first notice bob sees afterwards is
+    // the answer to his own command
+    sleep(Duration::from_millis(1500)).await;
+    bob.command(":whoami").await;
+    let notice = bob.next_notice().await;
+    assert!(notice.starts_with("You are "), "bob was told {notice:?}");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn staying_away_past_the_grace_period_is_announced() {
+    let server = spawn_test_server_with(Config {
+        reconnect_grace_secs: 0,
+        ..Config::default()
+    })
+    .await;
+    let mut bob = connect_test_client(&server).await;
+    let mut alice = connect_test_client(&server).await;
+    alice.command(":name alice").await;
+    assert_eq!(bob.next_notice().await, "alice joined");
+
+    let token = alice.session_token.clone();
+    drop(alice);
+    assert_eq!(bob.next_notice().await, "alice left");
+
+    // Too late to resume, so coming back is a fresh arrival
+    let mut again = connect_test_client(&server).await;
+    again.command(&format!(":resume {token}")).await;
+    again.command(":name alice").await;
+    assert_eq!(bob.next_notice().await, "alice joined");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn resuming_takes_over_a_session_not_yet_known_to_be_gone() {
+    let server = spawn_test_server_with(Config {
+        reconnect_grace_secs: 1,
+        ..Config::default()
+    })
+    .await;
+    let mut bob = connect_test_client(&server).await;
+    let mut alice = connect_test_client(&server).await;
+    alice.command(":name alice").await;
+    assert_eq!(bob.next_notice().await, "alice joined");
+
+    // As far as the server knows, alice is still connected
+    let mut again = connect_test_client(&server).await;
+    again
+        .command(&format!(":resume {}", alice.session_token))
+        .await;
+    again.command(":name alice").await;
+    again.command(":whoami").await;
+    assert!(again.next_notice().await.starts_with("You are alice "));
+
+    // The old connection is closed, and nobody hears about any of it
+    while alice.recv().await.is_some() {}
+    sleep(Duration::from_millis(1500)).await;
+    bob.command(":whoami").await;
+    let notice = bob.next_notice().await;
+    assert!(notice.starts_with("You are "), "bob was told {notice:?}");
+
+    server.stop().await;
+}
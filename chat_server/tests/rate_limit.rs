@@ -0,0 +1,147 @@
+mod common;
+
+use chat_shared::{Config, message::MessageKind};
+use common::{TestClient, connect_test_client, spawn_test_server_with};
+use std::time::Duration;
+use tokio::time::timeout;
+
+// Say each text in a quick burst, then collect what comes back until the
+// server tells the client it went too fast, or it has echoed them all
+async fn burst(client: &mut TestClient, count: usize) -> (Vec<String>, bool) {
+    for i in 0..count {
+        client.say(&format!("message {i}")).await;
+    }
+    let mut echoed = Vec::new();
+    while echoed.len() < count {
+        let message = client.recv().await.expect("the server went quiet");
+        match message.kind {
+            MessageKind::Message => echoed.push(message.as_string()),
+            _ if message.as_string().contains("too fast") => return (echoed, true),
+            _ => (),
+        }
+    }
+    (echoed, false)
+}
+
+#[tokio::test]
+async fn members_are_held_to_the_limit() {
+    // A burst of one, so anything past the first in a quick burst is over
+    // the limit
+    let server = spawn_test_server_with(Config {
+        rate_limit_per_sec: Some(1),
+        rate_limit_burst: 1,
+        ..Config::default()
+    })
+    .await;
+    let mut alice = connect_test_client(&server).await;
+
+    let (echoed, warned) = burst(&mut alice, 5).await;
+    assert!(warned, "a member was not told they went too fast");
+    assert_eq!(echoed, ["message 0"]);
+}
+
+#[tokio::test]
+async fn admins_are_not_held_to_the_limit() {
+    let server = spawn_test_server_with(Config {
+        admin_ips: vec!["127.0.0.1".parse().unwrap()],
+        rate_limit_per_sec: Some(1),
+        rate_limit_burst: 1,
+        ..Config::default()
+    })
+    .await;
+    let mut admin = connect_test_client(&server).await;
+
+    let (echoed, warned) = burst(&mut admin, 5).await;
+    assert!(!warned, "an admin was told they went too fast");
+    let expected: Vec<String> = (0..5).map(|i| format!("message {i}")).collect();
+    assert_eq!(echoed, expected);
+}
+
+#[tokio::test]
+async fn only_the_burst_is_forwarded() {
+    let server = spawn_test_server_with(Config {
+        rate_limit_per_sec: Some(1),
+        rate_limit_burst: 5,
+        ..Config::default()
+    })
+    .await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+
+    for i in 0..20 {
+        alice.say(&format!("message {i}")).await;
+    }
+
+    // Bob hears the first five, then nothing more of them
+    let mut forwarded = Vec::new();
+    for _ in 0..5 {
+        let message = bob
+            .recv_from(&alice.id)
+            .await
+            .expect("bob heard too little");
+        forwarded.push(message.as_string());
+    }
+    let expected: Vec<String> = (0..5).map(|i| format!("message {i}")).collect();
+    assert_eq!(forwarded, expected);
+    let more = timeout(Duration::from_millis(500), bob.recv_from(&alice.id)).await;
+    assert!(more.is_err(), "more than the burst was forwarded");
+}
+
+#[tokio::test]
+async fn a_command_is_refused_inside_its_cooldown() {
+    let server = spawn_test_server_with(Config {
+        command_cooldowns_ms: [(String::from("info"), 60_000)].into(),
+        ..Config::default()
+    })
+    .await;
+    let mut alice = connect_test_client(&server).await;
+
+    alice.command(":info nobody").await;
+    assert_eq!(
+        alice.next_notice().await,
+        "No user named nobody is connected"
+    );
+    alice.command(":info nobody").await;
+    let second = alice.next_notice().await;
+    assert!(
+        second.starts_with("Please wait ") && second.ends_with("s before using :info again"),
+        "{second}"
+    );
+
+    // Other commands have no cooldown of their own
+    alice.command(":whoami").await;
+    assert!(alice.next_notice().await.starts_with("You are "));
+}
+
+#[tokio::test]
+async fn the_cooldown_reply_uses_the_configured_prefix() {
+    let server = spawn_test_server_with(Config {
+        prefix: '/',
+        command_cooldowns_ms: [(String::from("info"), 60_000)].into(),
+        ..Config::default()
+    })
+    .await;
+    let mut alice = connect_test_client(&server).await;
+
+    alice.command("/info nobody").await;
+    alice.next_notice().await;
+    alice.command("/info nobody").await;
+    let second = alice.next_notice().await;
+    assert!(second.ends_with("s before using /info again"), "{second}");
+}
+
+#[tokio::test]
+async fn admins_have_no_cooldowns() {
+    let server = spawn_test_server_with(Config {
+        admin_ips: vec!["127.0.0.1".parse().unwrap()],
+        command_cooldowns_ms: [(String::from("whoami"), 60_000)].into(),
+        ..Config::default()
+    })
+    .await;
+    let mut admin = connect_test_client(&server).await;
+
+    for _ in 0..3 {
+        admin.command(":whoami").await;
+        assert!(admin.next_notice().await.starts_with("You are "));
+    }
+}
@@ -0,0 +1,17 @@
+use chat_server::selftest;
+use std::process::Command;
+
+#[tokio::test]
+async fn selftest_passes_on_a_healthy_build() {
+    assert_eq!(selftest::run().await, Ok(()));
+}
+
+#[test]
+fn selftest_subcommand_prints_pass_and_succeeds() {
+    let output = Command::new(env!("CARGO_BIN_EXE_chat_server"))
+        .arg("selftest")
+        .output()
+        .expect("could not run the server binary");
+    assert!(output.status.success(), "selftest failed: {output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "PASS");
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use chat_shared::{Config, frame, message::MessageKind};
+use common::{
+    TIMEOUT, connect_test_client, spawn_test_server, spawn_test_server_with, wait_for_roster,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::time::{Instant, timeout};
+
+// Stopping the server tells everyone it is going, closes every
+// connection and waits for each client's tasks to finish
+#[tokio::test]
+async fn shutdown_tells_everyone_and_closes_every_connection() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+    let mut bob = connect_test_client(&server).await;
+    wait_for_roster(&server, 2).await;
+
+    // The server only returns once every client's tasks have ended
+    let state = Arc::clone(&server.state);
+    server.stop().await;
+    assert_eq!(state.roster.lock().await.len(), 0);
+
+    for client in [&mut alice, &mut bob] {
+        let notice = client
+            .recv_kind(MessageKind::ServerBroadcast)
+            .await
+            .expect("no shutdown notice");
+        assert_eq!(notice.as_string(), "Server shutting down");
+        // Nothing comes after it but the connection closing
+        let next = timeout(
+            TIMEOUT,
+            frame::read_frame(&mut client.stream, client.max_frame_size),
+        )
+        .await
+        .expect("the connection stayed open");
+        assert!(next.is_err(), "something came after the shutdown notice");
+    }
+}
+
+// A client that has stopped reading can't hold the server up for longer
+// than the shutdown timeout, even with a backlog of messages and the
+// notice still to be written
+#[tokio::test]
+async fn stuck_client_does_not_hold_up_shutdown() {
+    // Pace every connection, so what is said piles up in the outboxes
+    let config = Config {
+        bandwidth_limit_bps: Some(2000),
+        shutdown_timeout_secs: 1,
+        ..Config::default()
+    };
+    let server = spawn_test_server_with(config).await;
+    let mut alice = connect_test_client(&server).await;
+    // Bob never reads another thing
+    let _bob = connect_test_client(&server).await;
+
+    let deadline = Instant::now() + TIMEOUT;
+    while server.state.outbox_depth.peak() < 8 {
+        assert!(Instant::now() < deadline, "the outboxes never backed up");
+        alice.say("is anyone still reading this?").await;
+    }
+
+    let started = Instant::now();
+    server.stop().await;
+    let took = started.elapsed();
+    assert!(
+        took < Duration::from_secs(3),
+        "shutdown took {took:?} with a stuck client"
+    );
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use chat_server::stats::SizeHistogram;
+use chat_shared::message::MessageKind;
+use common::{connect_test_client, spawn_test_server};
+
+#[test]
+fn sizes_are_counted_in_their_buckets() {
+    let sizes = SizeHistogram::new();
+    // Each limit is the largest size its bucket takes
+    for size in [
+        0, 16, 17, 64, 100, 1024, 4096, 4097, 16384, 16385, 1_000_000,
+    ] {
+        sizes.record(size);
+    }
+    assert_eq!(sizes.counts(), [2, 2, 1, 1, 1, 2, 2]);
+}
+
+#[test]
+fn the_report_shows_every_bucket() {
+    let sizes = SizeHistogram::new();
+    sizes.record(10);
+    sizes.record(20_000);
+    assert_eq!(
+        sizes.report(),
+        "Message sizes:\n<= 16 B: 1\n<= 64 B: 0\n<= 256 B: 0\n<= 1024 B: 0\n<= 4096 B: 0\n<= 16384 B: 0\n> 16384 B: 1"
+    );
+}
+
+#[tokio::test]
+async fn relayed_messages_are_counted_by_size() {
+    let server = spawn_test_server().await;
+    let mut alice = connect_test_client(&server).await;
+
+    for text in [String::from("hi"), "x".repeat(100)] {
+        alice.say(&text).await;
+        alice
+            .recv_kind(MessageKind::Message)
+            .await
+            .expect("the message was not relayed");
+    }
+    assert_eq!(server.state.message_sizes.counts(), [1, 0, 1, 0, 0, 0, 0]);
+
+    server.stop().await;
+}
@@ -0,0 +1,199 @@
+// The certificates in tests/certs were made with openssl, valid for a
+// hundred years:
+// - server: self-signed for localhost and 127.0.0.1, without CA:TRUE
+// - ca: the CA trusted to issue client certificates
+// - alice: issued by ca, with alice as its DNS name
+// - bob: issued by ca, with bob only as its common name
+// - mallory: self-signed, so issued by nobody the server trusts
+mod common;
+
+use chat_server::ServerState;
+use chat_shared::{Client, Config, Message, TlsConfig, frame, message::MessageKind, tls};
+use common::{ServerHandle, TIMEOUT, spawn_test_server_from, wait_for_roster};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{net::TcpStream, time::timeout};
+use tokio_rustls::{client::TlsStream, rustls::pki_types::ServerName};
+
+fn cert(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/certs")
+        .join(name)
+}
+
+fn tls_config() -> TlsConfig {
+    TlsConfig {
+        cert_path: None,
+        key_path: None,
+        ca_path: None,
+        server_name: None,
+        verify: true,
+        client_ca_path: None,
+        client_cert_path: None,
+        client_key_path: None,
+    }
+}
+
+// A server over TLS, asking clients for a certificate issued by the test CA
+// when told to
+async fn spawn_tls_server(require_client_certs: bool) -> ServerHandle {
+    let tls = TlsConfig {
+        cert_path: Some(cert("server.pem")),
+        key_path: Some(cert("server.key")),
+        client_ca_path: require_client_certs.then(|| cert("ca.pem")),
+        ..tls_config()
+    };
+    let mut state = ServerState::new(Config {
+        tls: Some(tls),
+        ..Config::default()
+    });
+    state
+        .load_tls()
+        .expect("could not load the server certificate");
+    spawn_test_server_from(state).await
+}
+
+// Connect to the server over TLS, presenting the named client certificate
+// if there is one
+async fn connect(server: &ServerHandle, who: Option<&str>) -> Option<TlsStream<TcpStream>> {
+    let tls = TlsConfig {
+        ca_path: Some(cert("server.pem")),
+        client_cert_path: who.map(|who| cert(&format!("{who}.pem"))),
+        client_key_path: who.map(|who| cert(&format!("{who}.key"))),
+        ..tls_config()
+    };
+    let connector = tls::connector(&tls).expect("could not set up the client");
+    let socket = TcpStream::connect(server.address)
+        .await
+        .expect("could not connect");
+    let name = ServerName::try_from("localhost").unwrap();
+    timeout(TIMEOUT, connector.connect(name, socket))
+        .await
+        .expect("timed out in the handshake")
+        .ok()
+}
+
+async fn recv(stream: &mut TlsStream<TcpStream>) -> Option<Message> {
+    timeout(TIMEOUT, frame::read_frame(stream, 64 * 1024))
+        .await
+        .expect("the server went quiet")
+        .ok()
+}
+
+async fn send(stream: &mut TlsStream<TcpStream>, text: &str, kind: MessageKind) {
+    let client = Arc::new(Client::new(String::from("tls")));
+    let message = Message::from_string(client, text.to_string(), kind);
+    frame::write_frame(stream, &message, 64 * 1024)
+        .await
+        .expect("could not send");
+}
+
+// The nickname the server acks straight after the welcome
+async fn certified_name(stream: &mut TlsStream<TcpStream>) -> Option<String> {
+    let welcome = recv(stream).await.expect("no welcome from the server");
+    assert_eq!(welcome.kind, MessageKind::Ack);
+    let ack = recv(stream).await.expect("nothing after the welcome");
+    assert_eq!(ack.kind, MessageKind::Ack, "the name was not acked");
+    ack.author
+}
+
+// Whether the server hung up on a client rather than serving it. Under TLS
+// 1.3 the client finishes its side of the handshake before the server has
+// checked its certificate, so the refusal can come on the first read
+async fn turned_away(server: &ServerHandle, who: Option<&str>) -> bool {
+    match connect(server, who).await {
+        None => true,
+        Some(mut stream) => recv(&mut stream).await.is_none(),
+    }
+}
+
+#[tokio::test]
+async fn a_trusted_certificate_names_its_holder() {
+    let server = spawn_tls_server(true).await;
+
+    let mut alice = connect(&server, Some("alice"))
+        .await
+        .expect("alice was refused");
+    assert_eq!(certified_name(&mut alice).await.as_deref(), Some("alice"));
+
+    // Without a DNS name, the common name is used
+    let mut bob = connect(&server, Some("bob"))
+        .await
+        .expect("bob was refused");
+    assert_eq!(certified_name(&mut bob).await.as_deref(), Some("bob"));
+
+    // What alice says goes out under the name on her certificate
+    send(&mut alice, "hello", MessageKind::Message).await;
+    let heard = loop {
+        let message = recv(&mut bob).await.expect("bob heard nothing");
+        if message.kind == MessageKind::Message {
+            break message;
+        }
+    };
+    assert_eq!(heard.as_string(), "hello");
+    assert_eq!(heard.author.as_deref(), Some("alice"));
+}
+
+#[tokio::test]
+async fn a_certified_name_cannot_be_changed() {
+    let server = spawn_tls_server(true).await;
+    let mut alice = connect(&server, Some("alice"))
+        .await
+        .expect("alice was refused");
+    certified_name(&mut alice).await;
+
+    send(&mut alice, ":name eve", MessageKind::Command).await;
+    let reply = recv(&mut alice).await.expect("no reply to :name");
+    assert_eq!(reply.kind, MessageKind::ServerBroadcast);
+    assert!(
+        reply.as_string().contains("certificate"),
+        "{}",
+        reply.as_string()
+    );
+
+    send(&mut alice, ":whoami", MessageKind::Command).await;
+    let whoami = recv(&mut alice).await.expect("no reply to :whoami");
+    assert!(whoami.as_string().starts_with("You are alice "));
+}
+
+#[tokio::test]
+async fn an_untrusted_or_missing_certificate_is_turned_away() {
+    let server = spawn_tls_server(true).await;
+    assert!(turned_away(&server, Some("mallory")).await);
+    assert!(turned_away(&server, None).await);
+    assert_eq!(server.state.roster.lock().await.len(), 0);
+}
+
+#[tokio::test]
+async fn without_a_client_ca_no_certificate_is_needed() {
+    let server = spawn_tls_server(false).await;
+    let mut client = connect(&server, None)
+        .await
+        .expect("the client was refused");
+    let welcome = recv(&mut client).await.expect("no welcome from the server");
+    assert_eq!(welcome.kind, MessageKind::Ack);
+    assert!(welcome.author.is_none());
+}
+
+#[tokio::test]
+async fn clients_chat_over_a_self_signed_server() {
+    let server = spawn_tls_server(false).await;
+    let mut alice = connect(&server, None).await.expect("alice was refused");
+    let mut bob = connect(&server, None).await.expect("bob was refused");
+    for client in [&mut alice, &mut bob] {
+        let welcome = recv(client).await.expect("no welcome from the server");
+        assert_eq!(welcome.kind, MessageKind::Ack);
+    }
+    wait_for_roster(&server, 2).await;
+
+    send(&mut alice, "hello over tls", MessageKind::Message).await;
+    let heard = loop {
+        let message = recv(&mut bob).await.expect("bob heard nothing");
+        if message.kind == MessageKind::Message {
+            break message;
+        }
+    };
+    assert_eq!(heard.as_string(), "hello over tls");
+}
@@ -0,0 +1,157 @@
+// Command line parsing shared by the client and the server, so both read
+// their arguments the same way and only once
+use crate::CliError;
+use std::path::PathBuf;
+
+/// The options given to either binary on the command line, read in a single pass.
+///
+/// # Fields
+/// - `config_path`:
+///   The config file to load, from `-c <path>`, `--config <path>` or a plain argument.
+///   If `None`, `Config::from_path` goes looking for the default one.
+/// - `nick`:
+///   The nickname to use from the start, from `--nick <name>`. Only the client uses it.
+/// - `verbosity`:
+///   How many times `-v` was given. Each one makes the server log in more detail.
+/// - `subcommand`:
+///   Something to do instead of chatting, if one was asked for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CliArgs {
+    pub config_path: Option<PathBuf>,
+    pub nick: Option<String>,
+    pub verbosity: u8,
+    pub subcommand: Option<Subcommand>,
+}
+
+/// What a binary should do instead of its usual work.
+///
+/// # Variants
+/// - `Help`
+///   Print the usage and exit, from `-h` or `--help`.
+/// - `Version`
+///   Print the version and exit, from `-V` or `--version`.
+/// - `Selftest`
+///   Run a round trip against an in-process server and exit, from `selftest`. Only the
+///   server supports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subcommand {
+    Help,
+    Version,
+    Selftest,
+}
+
+impl CliArgs {
+    /// Parses the command line, starting with the program name as `std::env::args()` does.
+    ///
+    /// # Arguments
+    /// * `args` - The arguments to parse. The first one is skipped as the program name.
+    ///
+    /// # Returns
+    /// * `Ok(CliArgs)` - Every option that was given, with the rest left at their defaults.
+    /// * `Err(CliError)` - If an argument could not be understood.
+    ///
+    /// # Behavior
+    /// - `--help` and `--version` win over everything else on the line, so they work even
+    ///   when the rest of it is wrong.
+    /// - The first plain argument is the config path, unless it is `selftest`.
+    ///
+    /// # Errors
+    /// * `CliError::UnknownFlag` - If a flag is not one that either binary understands.
+    /// * `CliError::MissingValue` - If `-c`, `--config` or `--nick` is the last argument.
+    /// * `CliError::UnexpectedArgument` - If more than one config path is given.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_shared::{CliError, cli::{CliArgs, Subcommand}};
+    /// use std::path::PathBuf;
+    ///
+    /// let parse = |args: &[&str]| CliArgs::parse(args.iter().map(|arg| arg.to_string()));
+    ///
+    /// let parsed = parse(&["chat_client", "-v", "--nick", "alice", "-c", "env/config.ron", "-v"]);
+    /// let parsed = parsed.unwrap();
+    /// assert_eq!(parsed.config_path, Some(PathBuf::from("env/config.ron")));
+    /// assert_eq!(parsed.nick.as_deref(), Some("alice"));
+    /// assert_eq!(parsed.verbosity, 2);
+    /// assert_eq!(parsed.subcommand, None);
+    ///
+    /// // A plain argument is still taken as the config path
+    /// let parsed = parse(&["chat_server", "config.ron"]).unwrap();
+    /// assert_eq!(parsed.config_path, Some(PathBuf::from("config.ron")));
+    ///
+    /// let parsed = parse(&["chat_server", "selftest"]).unwrap();
+    /// assert_eq!(parsed.subcommand, Some(Subcommand::Selftest));
+    ///
+    /// let parsed = parse(&["chat_server", "--bogus", "--help"]).unwrap();
+    /// assert_eq!(parsed.subcommand, Some(Subcommand::Help));
+    ///
+    /// let parsed = parse(&["chat_client", "-V"]).unwrap();
+    /// assert_eq!(parsed.subcommand, Some(Subcommand::Version));
+    ///
+    /// // No arguments at all, not even the program name, still parses to the defaults
+    /// assert_eq!(parse(&[]), Ok(CliArgs::default()));
+    /// assert_eq!(parse(&["chat_client"]), Ok(CliArgs::default()));
+    ///
+    /// assert_eq!(parse(&["chat_client", "--nick"]), Err(CliError::MissingValue("--nick")));
+    /// assert_eq!(parse(&["chat_client", "--bogus"]), Err(CliError::UnknownFlag("--bogus".into())));
+    /// ```
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let args: Vec<String> = args.into_iter().skip(1).collect();
+        for arg in &args {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Self::with(Subcommand::Help)),
+                "-V" | "--version" => return Ok(Self::with(Subcommand::Version)),
+                _ => (),
+            }
+        }
+
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-c" | "--config" => {
+                    let flag = if arg == "-c" { "-c" } else { "--config" };
+                    let path = args.next().ok_or(CliError::MissingValue(flag))?;
+                    parsed.config_path = Some(PathBuf::from(path));
+                }
+                "--nick" => {
+                    parsed.nick = Some(args.next().ok_or(CliError::MissingValue("--nick"))?)
+                }
+                "-v" => parsed.verbosity = parsed.verbosity.saturating_add(1),
+                "selftest" if parsed.subcommand.is_none() => {
+                    parsed.subcommand = Some(Subcommand::Selftest)
+                }
+                flag if flag.starts_with('-') => return Err(CliError::UnknownFlag(arg)),
+                _ if parsed.config_path.is_none() => parsed.config_path = Some(PathBuf::from(arg)),
+                _ => return Err(CliError::UnexpectedArgument(arg)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn with(subcommand: Subcommand) -> Self {
+        Self {
+            subcommand: Some(subcommand),
+            ..Self::default()
+        }
+    }
+}
+
+/// Returns the usage text printed for `--help`.
+///
+/// # Arguments
+/// * `program` - The name of the binary, shown at the start of the usage line.
+pub fn usage(program: &str) -> String {
+    format!(
+        "Usage: {program} [OPTIONS] [CONFIG]\n\
+         \n\
+         Options:\n  \
+         -c, --config <path>  Load the config from <path>\n      \
+         --nick <name>    Use <name> as the nickname from the start (client)\n  \
+         -v                   Log in more detail, repeat for more (server)\n  \
+         -h, --help           Print this help and exit\n  \
+         -V, --version        Print the version and exit\n\
+         \n\
+         Subcommands:\n  \
+         selftest             Run a round trip against an in-process server (server)"
+    )
+}
@@ -0,0 +1,31 @@
+use std::fmt;
+
+/// `CliError` describes why the command line given to one of the binaries could not be parsed.
+///
+/// # Variants
+/// - `UnknownFlag(String)`
+///   An argument started with `-` but is not a flag either binary understands.
+/// - `MissingValue(&str)`
+///   The named flag takes a value, but it was the last argument.
+/// - `UnexpectedArgument(String)`
+///   A plain argument was given after the config path had already been set.
+///
+/// # Traits
+/// - `Debug`, `PartialEq`, `Eq`
+///   Allow errors to be compared, which keeps checking for a specific failure simple.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliError {
+    UnknownFlag(String),
+    MissingValue(&'static str),
+    UnexpectedArgument(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::UnknownFlag(flag) => write!(f, "Unknown flag {flag}"),
+            CliError::MissingValue(flag) => write!(f, "{flag} needs a value"),
+            CliError::UnexpectedArgument(arg) => write!(f, "Unexpected argument {arg}"),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{fmt, path::PathBuf};
 
 /// `ConfigError` is an enumeration that represents the possible errors that can occur
 /// while working with configuration files or settings in an application.
@@ -8,14 +8,27 @@ use std::fmt;
 ///   Indicates that neither a configuration file nor a flag with required settings is provided.
 /// - `NoValidSettings`
 ///   Represents a case where no valid settings could be determined from the provided configuration.
-/// - `ConfigReadFailed`
+/// - `ConfigReadFailed(String)`
 ///   Returned when the configuration file could not be read, possibly due to file system issues
-///   or incorrect file paths.
-/// - `ConfigParseFailed`
+///   or incorrect file paths. Carries the error from reading it.
+/// - `ConfigParseFailed(String)`
 ///   Occurs when the configuration file is read but fails to parse, typically due to incorrect
-///   formatting or invalid syntax.
+///   formatting or invalid syntax. Carries the parser's error, which says where it went wrong.
 /// - `MissingHostIp`
 ///   Signifies that a required field `HostIp` is missing in the configuration.
+/// - `ZeroFrameSize`
+///   The configuration sets `max_frame_size` to `0`, which no message could fit in.
+/// - `InvalidPort(usize)`
+///   The configuration sets `host_port` to a value outside `1` to `65535`.
+/// - `UnresolvedHost(String, String)`
+///   The `host_name` in the configuration could not be resolved to an address, for the
+///   reason given.
+/// - `InvalidEnvVar(String, String)`
+///   The environment variable with the first name is set to the second value, which the
+///   setting it overrides can't take.
+/// - `UnknownFormat(PathBuf)`
+///   The config file's extension is neither `.ron` nor `.json`, so it is not clear how to read
+///   or write it.
 ///
 /// # Traits
 /// - `Debug`
@@ -24,9 +37,14 @@ use std::fmt;
 pub enum ConfigError {
     NoConfigOrFlag,
     NoValidSettings,
-    ConfigReadFailed,
-    ConfigParseFailed,
-    MissingHostIp
+    ConfigReadFailed(String),
+    ConfigParseFailed(String),
+    MissingHostIp,
+    ZeroFrameSize,
+    InvalidPort(usize),
+    UnresolvedHost(String, String),
+    InvalidEnvVar(String, String),
+    UnknownFormat(PathBuf)
 }
 
 impl fmt::Display for ConfigError {
@@ -34,9 +52,14 @@ impl fmt::Display for ConfigError {
         match self {
             ConfigError::NoConfigOrFlag => write!(f, "No config file or flag provided."),
             ConfigError::NoValidSettings => write!(f, "No valid settings in provided config file. Review Template."),
-            ConfigError::ConfigReadFailed => write!(f, "Failed to read the config file, do you have permissions?"),
-            ConfigError::ConfigParseFailed => write!(f, "Failed to parse the config file, is it valid?"),
-            ConfigError::MissingHostIp => write!(f, "Missing host IP in the config file.")
+            ConfigError::ConfigReadFailed(e) => write!(f, "Failed to read the config file, do you have permissions? {e}"),
+            ConfigError::ConfigParseFailed(e) => write!(f, "Failed to parse the config file, is it valid? {e}"),
+            ConfigError::MissingHostIp => write!(f, "Missing host IP in the config file."),
+            ConfigError::ZeroFrameSize => write!(f, "max_frame_size in the config file must be more than 0."),
+            ConfigError::InvalidPort(port) => write!(f, "host_port {port} in the config file is not a valid port, use 1 to 65535."),
+            ConfigError::UnresolvedHost(host, reason) => write!(f, "Could not resolve host_name {host}: {reason}"),
+            ConfigError::InvalidEnvVar(name, value) => write!(f, "Environment variable {name} is set to {value:?}, which is not a valid value for it."),
+            ConfigError::UnknownFormat(path) => write!(f, "Config file {} must end in .ron or .json.", path.display())
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,61 @@
+use std::{fmt, io};
+
+/// `FrameError` describes why a message could not be written to or read from a connection.
+///
+/// # Variants
+/// - `TooLarge { size, max }`
+///   The serialized message is `size` bytes, which is more than the `max` allowed. When reading,
+///   the rest of the stream can no longer be trusted, so the connection should be dropped.
+/// - `Closed`
+///   The other end closed the connection.
+/// - `Empty`
+///   A frame arrived with nothing in it. There is no message to handle, but the stream is still
+///   in step, so the next frame can be read.
+/// - `Malformed(String)`
+///   A whole frame arrived, but it did not hold a valid `Message`. The stream is still in step,
+///   so the next frame can be read.
+/// - `Corrupt { expected, actual }`
+///   The message's content does not match the checksum sent with it, so it was damaged on the
+///   way. The stream is still in step, so the next frame can be read.
+/// - `Io(io::Error)`
+///   Reading or writing the connection failed.
+///
+/// # Traits
+/// - `Debug`
+///   Allows the `FrameError` enum to be formatted using the `{:?}` formatter, primarily for debugging purposes.
+#[derive(Debug)]
+pub enum FrameError {
+    TooLarge { size: usize, max: u32 },
+    Closed,
+    Empty,
+    Malformed(String),
+    Corrupt { expected: u32, actual: u32 },
+    Io(io::Error),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::TooLarge { size, max } => {
+                write!(f, "Message is {size} bytes, but at most {max} are allowed")
+            }
+            FrameError::Closed => write!(f, "The connection was closed"),
+            FrameError::Empty => write!(f, "The message was empty"),
+            FrameError::Malformed(e) => write!(f, "Could not parse message: {e}"),
+            FrameError::Corrupt { expected, actual } => write!(
+                f,
+                "Message is corrupt, its checksum is {actual:08x} but {expected:08x} was sent"
+            ),
+            FrameError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => FrameError::Closed,
+            _ => FrameError::Io(error),
+        }
+    }
+}
@@ -1,3 +1,13 @@
+mod cli_error;
 mod config_error;
+mod frame_error;
+mod nick_error;
+mod parse_error;
+mod tls_error;
 
-pub use config_error::ConfigError;
\ No newline at end of file
+pub use cli_error::CliError;
+pub use config_error::ConfigError;
+pub use frame_error::FrameError;
+pub use nick_error::NickError;
+pub use parse_error::ParseError;
+pub use tls_error::TlsError;
\ No newline at end of file
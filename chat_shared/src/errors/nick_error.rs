@@ -0,0 +1,50 @@
+use std::fmt;
+
+/// `NickError` describes why a nickname can't be used, as found by `User::validate_nickname`.
+/// Its message states the rule that was broken, so it can be shown to the user as it is.
+///
+/// # Variants
+/// - `TooShort { min }`
+///   The nickname has fewer than `min` characters. An empty nickname is too short.
+/// - `TooLong { max }`
+///   The nickname has more than `max` characters.
+/// - `ControlCharacter`
+///   The nickname contains a control character, such as a newline or an escape.
+/// - `SurroundingWhitespace`
+///   The nickname starts or ends with whitespace.
+/// - `InvalidCharacter(char)`
+///   The nickname contains a character outside the allowed set.
+///
+/// # Traits
+/// - `Debug`, `PartialEq`, `Eq`
+///   Allow errors to be compared, which keeps checking for a specific failure simple.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NickError {
+    TooShort { min: usize },
+    TooLong { max: usize },
+    ControlCharacter,
+    SurroundingWhitespace,
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for NickError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NickError::TooShort { min } => {
+                write!(f, "Nicknames must be at least {min} characters long")
+            }
+            NickError::TooLong { max } => {
+                write!(f, "Nicknames can be at most {max} characters long")
+            }
+            NickError::ControlCharacter => write!(f, "Nicknames can't contain control characters"),
+            NickError::SurroundingWhitespace => {
+                write!(f, "Nicknames can't start or end with whitespace")
+            }
+            NickError::InvalidCharacter(c) => write!(
+                f,
+                "Nicknames can't contain {c:?}, only letters, digits and {}",
+                crate::NICK_SYMBOLS
+            ),
+        }
+    }
+}
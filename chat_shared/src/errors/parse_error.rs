@@ -0,0 +1,41 @@
+use std::fmt;
+
+/// `ParseError` describes why a line typed by the user could not be parsed into a `Command`.
+///
+/// # Variants
+/// - `UnknownCommand(String)`
+///   The line started with the command prefix, but the word after it is not a known command.
+/// - `MissingArgument { cmd, arg }`
+///   The command `cmd`, as typed with its prefix, needs the argument `arg`, but it was not given.
+/// - `InvalidArgument { cmd, arg }`
+///   The argument `arg` was given to `cmd`, but its value could not be understood.
+/// - `TooManyArguments(String)`
+///   The named command was given more arguments than it takes.
+/// - `UnterminatedQuote`
+///   A quoted argument was opened with `"` but never closed.
+///
+/// # Traits
+/// - `Debug`, `PartialEq`, `Eq`
+///   Allow errors to be compared, which keeps checking for a specific failure simple.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnknownCommand(String),
+    MissingArgument { cmd: String, arg: &'static str },
+    InvalidArgument { cmd: String, arg: &'static str },
+    TooManyArguments(String),
+    UnterminatedQuote,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnknownCommand(cmd) => write!(f, "Unknown command {cmd}"),
+            ParseError::MissingArgument { cmd, arg } => write!(f, "{cmd} needs a <{arg}>"),
+            ParseError::InvalidArgument { cmd, arg } => {
+                write!(f, "{cmd} was given an invalid <{arg}>")
+            }
+            ParseError::TooManyArguments(cmd) => write!(f, "Too many arguments for {cmd}"),
+            ParseError::UnterminatedQuote => write!(f, "Missing closing quote"),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::{fmt, path::PathBuf};
+
+/// `TlsError` describes why TLS could not be set up from the `tls` section of the config.
+///
+/// # Variants
+/// - `MissingCertificate`
+///   The server needs both `cert_path` and `key_path`, but at least one of them is unset.
+/// - `MissingCa`
+///   The client is meant to verify the server, but `ca_path` is unset.
+/// - `MissingClientKey`
+///   The client has only one of `client_cert_path` and `client_key_path`, and needs both to
+///   present a certificate.
+/// - `ReadFailed { path, reason }`
+///   The PEM file at `path` could not be read or held nothing usable.
+/// - `InvalidServerName(String)`
+///   The name to check the server's certificate against is not a valid DNS name or IP address.
+/// - `Rejected(String)`
+///   The certificates and key were read, but could not be used together, for example because
+///   the key does not belong to the certificate.
+///
+/// # Traits
+/// - `Debug`
+///   Allows the `TlsError` enum to be formatted using the `{:?}` formatter, primarily for debugging purposes.
+#[derive(Debug)]
+pub enum TlsError {
+    MissingCertificate,
+    MissingCa,
+    MissingClientKey,
+    ReadFailed { path: PathBuf, reason: String },
+    InvalidServerName(String),
+    Rejected(String),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TlsError::MissingCertificate => {
+                write!(f, "TLS needs both cert_path and key_path on the server")
+            }
+            TlsError::MissingCa => {
+                write!(f, "TLS needs ca_path on the client unless verify is off")
+            }
+            TlsError::MissingClientKey => {
+                write!(f, "TLS needs both client_cert_path and client_key_path, or neither")
+            }
+            TlsError::ReadFailed { path, reason } => {
+                write!(f, "Could not read {}: {reason}", path.display())
+            }
+            TlsError::InvalidServerName(name) => write!(f, "{name} is not a valid server name"),
+            TlsError::Rejected(reason) => write!(f, "Could not set up TLS: {reason}"),
+        }
+    }
+}
@@ -0,0 +1,259 @@
+// Length prefixed framing for messages sent over a connection. Each frame is
+// a u32 big-endian length followed by that many bytes of RON serialized Message
+use crate::{FrameError, Message};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The number of bytes in the length prefix at the start of every frame.
+pub const HEADER_LEN: usize = 4;
+
+/// Serializes a message into a complete frame, ready to be written to a connection.
+///
+/// # Arguments
+/// * `message` - The message to serialize.
+/// * `max_size` - The largest serialized message allowed, in bytes.
+///
+/// # Returns
+/// * `Ok(Vec<u8>)` - The length prefix followed by the serialized message.
+/// * `Err(FrameError)` - If the message could not be serialized or is too large.
+///
+/// # Errors
+/// * `FrameError::Malformed` - If the message could not be serialized.
+/// * `FrameError::TooLarge` - If the serialized message is larger than `max_size`.
+pub fn encode(message: &Message, max_size: u32) -> Result<Vec<u8>, FrameError> {
+    let payload = ron::to_string(message)
+        .map_err(|e| FrameError::Malformed(e.to_string()))?
+        .into_bytes();
+    if payload.len() > max_size as usize {
+        return Err(FrameError::TooLarge {
+            size: payload.len(),
+            max: max_size,
+        });
+    }
+
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Parses the payload of a frame, without its length prefix, back into a message.
+///
+/// Fields this build doesn't know about are ignored, missing ones take their default and a
+/// kind it doesn't know reads as `MessageKind::Unknown`, so builds of different ages can still
+/// talk to each other. Builds from before `Unknown` was added can't read kinds newer than
+/// they are. If the message carries a checksum, its content is checked against it.
+///
+/// # Errors
+/// * `FrameError::Empty` - If the payload has nothing in it.
+/// * `FrameError::Malformed` - If the payload is not a valid `Message`.
+/// * `FrameError::Corrupt` - If the content does not match the message's checksum.
+///
+/// # Example
+/// ```
+/// use chat_shared::{FrameError, Message, frame, message::MessageKind};
+///
+/// let mut message = Message::from_server(String::from("hello"), MessageKind::ServerBroadcast);
+/// message.seal();
+/// let frame = frame::encode(&message, 1024).unwrap();
+/// assert!(frame::decode(&frame[frame::HEADER_LEN..]).is_ok());
+///
+/// // Flip a bit in the content after the checksum was taken
+/// message.content[0] ^= 1;
+/// let frame = frame::encode(&message, 1024).unwrap();
+/// let result = frame::decode(&frame[frame::HEADER_LEN..]);
+/// assert!(matches!(result, Err(FrameError::Corrupt { .. })));
+/// ```
+pub fn decode(payload: &[u8]) -> Result<Message, FrameError> {
+    if payload.is_empty() {
+        return Err(FrameError::Empty);
+    }
+    let message: Message =
+        ron::de::from_bytes(payload).map_err(|e| FrameError::Malformed(e.to_string()))?;
+    if let Some(expected) = message.checksum {
+        let actual = crc32(&message.content);
+        if actual != expected {
+            return Err(FrameError::Corrupt { expected, actual });
+        }
+    }
+    Ok(message)
+}
+
+/// Computes the CRC-32 (IEEE) of some bytes, as used for message checksums.
+///
+/// # Arguments
+/// * `bytes` - The bytes to checksum.
+///
+/// # Example
+/// ```
+/// assert_eq!(chat_shared::frame::crc32(b"123456789"), 0xcbf43926);
+/// ```
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Serializes a message and writes it to the connection as a single frame.
+///
+/// # Arguments
+/// * `writer` - The connection to write to.
+/// * `message` - The message to send.
+/// * `max_size` - The largest serialized message allowed, in bytes.
+///
+/// # Errors
+/// * `FrameError::TooLarge` - If the serialized message is larger than `max_size`. Nothing is written.
+/// * `FrameError::Io` - If writing to the connection fails.
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Message,
+    max_size: u32,
+) -> Result<(), FrameError> {
+    let frame = encode(message, max_size)?;
+    writer.write_all(&frame).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Reads the payload of the next frame from the connection, waiting until all of it has arrived.
+///
+/// # Arguments
+/// * `reader` - The connection to read from.
+/// * `max_size` - The largest payload that will be accepted, in bytes.
+///
+/// # Returns
+/// * `Ok(Vec<u8>)` - The payload, without its length prefix.
+/// * `Err(FrameError)` - If no complete frame could be read.
+///
+/// # Errors
+/// * `FrameError::Closed` - If the connection is closed before a whole frame arrives.
+/// * `FrameError::TooLarge` - If the length prefix is larger than `max_size`. The payload is not read.
+/// * `FrameError::Io` - If reading from the connection fails.
+pub async fn read_frame_bytes<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_size: u32,
+) -> Result<Vec<u8>, FrameError> {
+    let mut header = [0; HEADER_LEN];
+    reader.read_exact(&mut header).await?;
+
+    let len = u32::from_be_bytes(header);
+    if len > max_size {
+        return Err(FrameError::TooLarge {
+            size: len as usize,
+            max: max_size,
+        });
+    }
+
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload).await?;
+    Ok(payload)
+}
+
+/// Reads the next frame from the connection and parses it into a message.
+///
+/// # Errors
+/// * Any error from `read_frame_bytes`.
+/// * Any error from `decode`.
+///
+/// # Example
+/// ```
+/// # async fn example() -> Result<(), chat_shared::FrameError> {
+/// use chat_shared::{Message, frame, message::MessageKind};
+///
+/// let (mut client, mut server) = tokio::io::duplex(1024);
+/// let message = Message::from_server(String::from("hello"), MessageKind::ServerBroadcast);
+/// frame::write_frame(&mut client, &message, 1024).await?;
+///
+/// let received = frame::read_frame(&mut server, 1024).await?;
+/// assert_eq!(received.as_string(), "hello");
+/// # Ok(())
+/// # }
+/// ```
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_size: u32,
+) -> Result<Message, FrameError> {
+    let payload = read_frame_bytes(reader, max_size).await?;
+    decode(&payload)
+}
+
+/// Reads frames from a connection, keeping any bytes that arrive ahead of a complete frame.
+///
+/// Unlike `read_frame`, nothing is lost if a read is abandoned part way through, for example
+/// when it loses a `tokio::select!` race. The bytes read so far stay in the buffer and the next
+/// call carries on from them, however the frames were split across reads.
+///
+/// # Fields
+/// - `reader`: The connection being read from.
+/// - `buffer`: Bytes that have been read but not yet returned as a frame.
+/// - `max_size`: The largest payload that will be accepted, in bytes.
+pub struct FrameReader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    max_size: u32,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub fn new(reader: R, max_size: u32) -> Self {
+        Self {
+            reader,
+            buffer: Vec::new(),
+            max_size,
+        }
+    }
+
+    /// Returns the payload of the next frame, reading from the connection until it is complete.
+    ///
+    /// # Errors
+    /// * `FrameError::Closed` - If the connection is closed before a whole frame arrives.
+    /// * `FrameError::TooLarge` - If the length prefix is larger than `max_size`.
+    /// * `FrameError::Io` - If reading from the connection fails.
+    pub async fn next_frame_bytes(&mut self) -> Result<Vec<u8>, FrameError> {
+        loop {
+            if let Some(payload) = self.take_frame()? {
+                return Ok(payload);
+            }
+            if self.reader.read_buf(&mut self.buffer).await? == 0 {
+                return Err(FrameError::Closed);
+            }
+        }
+    }
+
+    /// Returns the next frame parsed into a message.
+    ///
+    /// # Errors
+    /// * Any error from `next_frame_bytes`.
+    /// * Any error from `decode`.
+    pub async fn next_message(&mut self) -> Result<Message, FrameError> {
+        let payload = self.next_frame_bytes().await?;
+        decode(&payload)
+    }
+
+    // Split the first frame off the buffer, if all of it has arrived
+    fn take_frame(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        let Some(header) = self.buffer.get(..HEADER_LEN) else {
+            return Ok(None);
+        };
+
+        let len = u32::from_be_bytes(header.try_into().unwrap());
+        if len > self.max_size {
+            return Err(FrameError::TooLarge {
+                size: len as usize,
+                max: self.max_size,
+            });
+        }
+
+        let end = HEADER_LEN + len as usize;
+        if self.buffer.len() < end {
+            return Ok(None);
+        }
+        let payload = self.buffer[HEADER_LEN..end].to_vec();
+        self.buffer.drain(..end);
+        Ok(Some(payload))
+    }
+}
@@ -1 +0,0 @@
-// Placeholder mod
\ No newline at end of file
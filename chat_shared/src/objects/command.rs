@@ -0,0 +1,582 @@
+use crate::ParseError;
+
+/// A command typed by a user, such as `:join lobby` or `:kick bob "being rude"`. Commands
+/// start with the `prefix` from the config, which is `:` unless it has been changed.
+///
+/// Both the client and the server parse commands with `Command::parse`. The client does it
+/// to catch mistakes before anything is sent, and the server does it again because it is the
+/// one that decides what actually happens.
+///
+/// # Variants
+/// - `Quit`
+///   Leave the chat.
+/// - `Name(Option<String>)`
+///   Set the nickname, or go back to the address when no name is given.
+/// - `Info(String)`
+///   Show information about the named user.
+/// - `List`
+///   Show who is connected.
+/// - `Whoami`
+///   Show how the server sees this connection, including how many frames it has read and written.
+/// - `History(Option<usize>)`
+///   Show the most recent messages sent to everyone, or as many as the server allows when
+///   no count is given.
+/// - `Timestamps(bool)`
+///   Turn timestamps in front of messages on or off. Only the client acts on this.
+/// - `Ignore(Option<String>)`
+///   Ignore the named user, or list who is ignored when no name is given.
+/// - `Unignore(String)`
+///   Stop ignoring the named user.
+/// - `Kick { nick, reason }`
+///   Disconnect a user, optionally telling them why. Admins only.
+/// - `Reply { id, text }`
+///   Send `text` as a reply to the message with the given id.
+/// - `Msg { nick, text }`
+///   Send `text` privately to the named user.
+/// - `Me(String)`
+///   Describe an action, shown to others as `* alice waves`. The client sends it as a
+///   `MessageKind::Action` message rather than as a command.
+/// - `Join(String)`
+///   Join the named channel.
+/// - `Leave(String)`
+///   Leave the named channel.
+/// - `Channels`
+///   List every channel and how many users are in each.
+/// - `Reconnect`
+///   Drop the connection to the server and make a new one. Only the client acts on this.
+/// - `Resume(String)`
+///   Pick up the session with the given token after reconnecting. The client sends this itself.
+/// - `Config`
+///   Show the config the server is running with, leaving out anything secret. Admins only.
+/// - `Stats`
+///   Show how many chat messages of each size the server has relayed. Admins only.
+/// - `Help`
+///   List the commands that can be used.
+/// - `Prefix`
+///   Show which prefix commands start with, typed as `:prefix?`. Only the client acts on this.
+/// - `Commands`
+///   List the commands the client handles by itself. Only the client acts on this.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Quit,
+    Name(Option<String>),
+    Info(String),
+    List,
+    Whoami,
+    History(Option<usize>),
+    Timestamps(bool),
+    Ignore(Option<String>),
+    Unignore(String),
+    Kick {
+        nick: String,
+        reason: Option<String>,
+    },
+    Reply {
+        id: u64,
+        text: String,
+    },
+    Msg {
+        nick: String,
+        text: String,
+    },
+    Me(String),
+    Join(String),
+    Leave(String),
+    Channels,
+    Reconnect,
+    Resume(String),
+    Config,
+    Stats,
+    Help,
+    Prefix,
+    Commands,
+}
+
+impl Command {
+    /// Returns the name of the command without its prefix, such as `"join"` for `:join`.
+    ///
+    /// # Returns
+    /// * `&'static str` - The name, as used to key settings like `command_cooldowns_ms`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Quit => "quit",
+            Command::Name(_) => "name",
+            Command::Info(_) => "info",
+            Command::List => "list",
+            Command::Whoami => "whoami",
+            Command::History(_) => "history",
+            Command::Timestamps(_) => "timestamps",
+            Command::Ignore(_) => "ignore",
+            Command::Unignore(_) => "unignore",
+            Command::Kick { .. } => "kick",
+            Command::Reply { .. } => "reply",
+            Command::Msg { .. } => "msg",
+            Command::Me(_) => "me",
+            Command::Join(_) => "join",
+            Command::Leave(_) => "leave",
+            Command::Channels => "channels",
+            Command::Reconnect => "reconnect",
+            Command::Resume(_) => "resume",
+            Command::Config => "config",
+            Command::Stats => "stats",
+            Command::Help => "help",
+            Command::Prefix => "prefix?",
+            Command::Commands => "commands",
+        }
+    }
+
+    /// Returns the arguments the command takes and a one-line description of what it does.
+    ///
+    /// Every variant has to be described here, so a new command can't be added without one.
+    pub fn usage(&self) -> (&'static str, &'static str) {
+        match self {
+            Command::Quit => ("", "Leave the chat"),
+            Command::Name(_) => ("[nick]", "Set your nickname, or clear it"),
+            Command::Info(_) => ("<nick>", "Show information about a user"),
+            Command::List => ("", "Show who is connected"),
+            Command::Whoami => ("", "Show how the server sees you"),
+            Command::History(_) => ("[count]", "Show recent messages sent to everyone"),
+            Command::Timestamps(_) => ("<on|off>", "Show or hide message times"),
+            Command::Ignore(_) => ("[nick]", "Ignore a user, or list who is ignored"),
+            Command::Unignore(_) => ("<nick>", "Stop ignoring a user"),
+            Command::Kick { .. } => ("<nick> [reason]", "Disconnect a user (admins)"),
+            Command::Reply { .. } => ("<id> <text>", "Reply to a message"),
+            Command::Msg { .. } => ("<nick> <text>", "Send a private message"),
+            Command::Me(_) => ("<action>", "Describe what you are doing"),
+            Command::Join(_) => ("<channel>", "Join a channel"),
+            Command::Leave(_) => ("<channel>", "Leave a channel"),
+            Command::Channels => ("", "Show the channels and how many are in each"),
+            Command::Reconnect => ("", "Make a fresh connection to the server"),
+            Command::Resume(_) => ("<token>", "Pick up an earlier session"),
+            Command::Config => ("", "Show the server's config (admins)"),
+            Command::Stats => ("", "Show message size counts (admins)"),
+            Command::Help => ("", "Show this list"),
+            Command::Prefix => ("", "Show what commands start with"),
+            Command::Commands => ("", "Show the commands the client handles itself"),
+        }
+    }
+
+    /// Returns one of every command a user is expected to type, in the order `:help` lists
+    /// them. `:resume` is left out, as the client sends it by itself.
+    pub fn listed() -> Vec<Command> {
+        let name = String::new;
+        vec![
+            Command::Quit,
+            Command::Name(None),
+            Command::Info(name()),
+            Command::List,
+            Command::Whoami,
+            Command::History(None),
+            Command::Timestamps(true),
+            Command::Ignore(None),
+            Command::Unignore(name()),
+            Command::Kick {
+                nick: name(),
+                reason: None,
+            },
+            Command::Reply {
+                id: 0,
+                text: name(),
+            },
+            Command::Msg {
+                nick: name(),
+                text: name(),
+            },
+            Command::Me(name()),
+            Command::Join(name()),
+            Command::Leave(name()),
+            Command::Channels,
+            Command::Reconnect,
+            Command::Config,
+            Command::Stats,
+            Command::Help,
+            Command::Prefix,
+            Command::Commands,
+        ]
+    }
+
+    /// Returns whether only the client acts on the command, so it never needs the server.
+    ///
+    /// `:ignore` and `:unignore` count as client commands even though the client passes them
+    /// on when the server is the one enforcing ignores.
+    pub fn is_local(&self) -> bool {
+        matches!(
+            self,
+            Command::Timestamps(_)
+                | Command::Ignore(_)
+                | Command::Unignore(_)
+                | Command::Reconnect
+                | Command::Prefix
+                | Command::Commands
+        )
+    }
+
+    /// Builds the text `:help` replies with, one line per command.
+    ///
+    /// # Arguments
+    /// * `prefix` - The character commands start with, shown in front of each one.
+    /// * `disabled` - Names of commands that have been turned off, which are left out.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_shared::Command;
+    ///
+    /// let help = Command::help('/', &[String::from("kick")]);
+    /// assert!(help.contains("/quit - Leave the chat"));
+    /// assert!(help.contains("/name [nick]"));
+    /// assert!(help.contains("/list"));
+    /// assert!(!help.contains("kick"));
+    /// ```
+    pub fn help(prefix: char, disabled: &[String]) -> String {
+        let commands = Command::listed()
+            .into_iter()
+            .filter(|command| !disabled.iter().any(|disabled| disabled == command.name()));
+        describe("Commands:", prefix, commands)
+    }
+
+    /// Builds the text `:commands` replies with, listing only the commands the client
+    /// handles itself, as picked out by `Command::is_local`.
+    ///
+    /// # Arguments
+    /// * `prefix` - The character commands start with, shown in front of each one.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_shared::Command;
+    ///
+    /// let listed: Vec<String> = Command::local_help(':')
+    ///     .lines()
+    ///     .skip(1)
+    ///     .map(|line| line.split([' ', '-']).next().unwrap().to_string())
+    ///     .collect();
+    /// assert_eq!(
+    ///     listed,
+    ///     [":timestamps", ":ignore", ":unignore", ":reconnect", ":prefix?", ":commands"]
+    /// );
+    /// ```
+    pub fn local_help(prefix: char) -> String {
+        let commands = Command::listed().into_iter().filter(Command::is_local);
+        describe("Client commands:", prefix, commands)
+    }
+
+    /// Parses a line of user input into a `Command`.
+    ///
+    /// # Arguments
+    /// * `input` - The line typed by the user, starting with the command prefix.
+    /// * `prefix` - The character commands start with, as set by `Config.prefix`.
+    ///
+    /// # Returns
+    /// * `Ok(Command)` - The parsed command.
+    /// * `Err(ParseError)` - Why the line is not a valid command.
+    ///
+    /// # Behavior
+    /// - Arguments are separated by whitespace. An argument wrapped in double quotes may
+    ///   contain whitespace, so `:kick bob "being rude"` has a single reason argument.
+    /// - Only the configured prefix marks a command. With `prefix: '/'`, `/quit` is a
+    ///   command and `:quit` is not.
+    /// - The trailing text of `:kick`, `:reply`, `:msg` and `:me` is kept as it was typed, quotes and
+    ///   spacing included. Only the whitespace around it goes, along with a pair of double quotes
+    ///   wrapping the whole of it.
+    ///
+    /// # Errors
+    /// * `ParseError::UnterminatedQuote` - If a quoted argument is never closed.
+    /// * `ParseError::UnknownCommand` - If the first word is not a known command, or does not
+    ///   start with the prefix.
+    /// * `ParseError::MissingArgument` - If a required argument is not given.
+    /// * `ParseError::InvalidArgument` - If an argument has a value that can't be used.
+    /// * `ParseError::TooManyArguments` - If more arguments are given than the command takes.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_shared::{Command, ParseError};
+    ///
+    /// assert_eq!(Command::parse(":join lobby", ':'), Ok(Command::Join(String::from("lobby"))));
+    /// assert_eq!(
+    ///     Command::parse(":join", ':'),
+    ///     Err(ParseError::MissingArgument { cmd: String::from(":join"), arg: "channel" })
+    /// );
+    ///
+    /// // Everything after :me is the action
+    /// assert_eq!(
+    ///     Command::parse(":me waves hello", ':'),
+    ///     Ok(Command::Me(String::from("waves hello")))
+    /// );
+    ///
+    /// // Trailing text is passed on as typed, even with a stray quote in it
+    /// assert_eq!(
+    ///     Command::parse(r#":msg bob  the 6" one,  "please""#, ':'),
+    ///     Ok(Command::Msg {
+    ///         nick: String::from("bob"),
+    ///         text: String::from(r#"the 6" one,  "please""#),
+    ///     })
+    /// );
+    /// assert_eq!(
+    ///     Command::parse(r#":kick bob "being rude""#, ':'),
+    ///     Ok(Command::Kick {
+    ///         nick: String::from("bob"),
+    ///         reason: Some(String::from("being rude")),
+    ///     })
+    /// );
+    ///
+    /// assert_eq!(
+    ///     Command::parse(":join lobby games", ':'),
+    ///     Err(ParseError::TooManyArguments(String::from(":join")))
+    /// );
+    /// assert_eq!(Command::parse(r#":name "bob"#, ':'), Err(ParseError::UnterminatedQuote));
+    ///
+    /// // Without a name, :name goes back to showing the address
+    /// assert_eq!(Command::parse(":name", ':'), Ok(Command::Name(None)));
+    /// assert_eq!(Command::parse(":help", ':'), Ok(Command::Help));
+    /// assert_eq!(
+    ///     Command::parse(":frobnicate", ':'),
+    ///     Err(ParseError::UnknownCommand(String::from(":frobnicate")))
+    /// );
+    ///
+    /// // Another prefix replaces the default one entirely
+    /// assert_eq!(Command::parse("/quit", '/'), Ok(Command::Quit));
+    /// assert_eq!(
+    ///     Command::parse(":quit", '/'),
+    ///     Err(ParseError::UnknownCommand(String::from(":quit")))
+    /// );
+    /// ```
+    pub fn parse(input: &str, prefix: char) -> Result<Self, ParseError> {
+        let Some((cmd, after)) = next_token(input)? else {
+            return Err(ParseError::UnknownCommand(String::new()));
+        };
+        let Some(name) = cmd.strip_prefix(prefix) else {
+            return Err(ParseError::UnknownCommand(cmd));
+        };
+        let cmd = cmd.as_str();
+
+        // Commands that end in free text only split off the arguments in
+        // front of it, the text itself is left as it was typed
+        let (args, text) = match name {
+            "kick" | "reply" | "msg" => leading(after, 1)?,
+            "me" => leading(after, 0)?,
+            _ => (tokenize(after)?, None),
+        };
+        let rest = args.as_slice();
+
+        let command = match name {
+            "quit" => {
+                no_more(cmd, rest, 0)?;
+                Command::Quit
+            }
+            "name" => {
+                no_more(cmd, rest, 1)?;
+                Command::Name(rest.first().cloned())
+            }
+            "info" => {
+                no_more(cmd, rest, 1)?;
+                Command::Info(required(cmd, "nick", rest)?)
+            }
+            "list" => {
+                no_more(cmd, rest, 0)?;
+                Command::List
+            }
+            "whoami" => {
+                no_more(cmd, rest, 0)?;
+                Command::Whoami
+            }
+            "history" => {
+                no_more(cmd, rest, 1)?;
+                // Zero, negative and overly large counts are all mistakes
+                let count = match rest.first() {
+                    Some(count) => match count.parse::<usize>() {
+                        Ok(count) if count > 0 => Some(count),
+                        _ => {
+                            return Err(ParseError::InvalidArgument {
+                                cmd: cmd.to_string(),
+                                arg: "count",
+                            });
+                        }
+                    },
+                    None => None,
+                };
+                Command::History(count)
+            }
+            "timestamps" => {
+                no_more(cmd, rest, 1)?;
+                match required(cmd, "on|off", rest)?.as_str() {
+                    "on" => Command::Timestamps(true),
+                    "off" => Command::Timestamps(false),
+                    _ => {
+                        return Err(ParseError::InvalidArgument {
+                            cmd: cmd.to_string(),
+                            arg: "on|off",
+                        });
+                    }
+                }
+            }
+            "ignore" => {
+                no_more(cmd, rest, 1)?;
+                Command::Ignore(rest.first().cloned())
+            }
+            "unignore" => {
+                no_more(cmd, rest, 1)?;
+                Command::Unignore(required(cmd, "nick", rest)?)
+            }
+            "kick" => Command::Kick {
+                nick: required(cmd, "nick", rest)?,
+                reason: text,
+            },
+            "reply" => {
+                let id = required(cmd, "id", rest)?.parse::<u64>().map_err(|_| {
+                    ParseError::InvalidArgument {
+                        cmd: cmd.to_string(),
+                        arg: "id",
+                    }
+                })?;
+                Command::Reply {
+                    id,
+                    text: text.ok_or_else(|| ParseError::MissingArgument {
+                        cmd: cmd.to_string(),
+                        arg: "text",
+                    })?,
+                }
+            }
+            "msg" => {
+                let nick = required(cmd, "nick", rest)?;
+                Command::Msg {
+                    nick,
+                    text: text.ok_or_else(|| ParseError::MissingArgument {
+                        cmd: cmd.to_string(),
+                        arg: "text",
+                    })?,
+                }
+            }
+            "me" => Command::Me(text.ok_or_else(|| ParseError::MissingArgument {
+                cmd: cmd.to_string(),
+                arg: "action",
+            })?),
+            "join" => {
+                no_more(cmd, rest, 1)?;
+                Command::Join(required(cmd, "channel", rest)?)
+            }
+            "leave" => {
+                no_more(cmd, rest, 1)?;
+                Command::Leave(required(cmd, "channel", rest)?)
+            }
+            "channels" => {
+                no_more(cmd, rest, 0)?;
+                Command::Channels
+            }
+            "reconnect" => {
+                no_more(cmd, rest, 0)?;
+                Command::Reconnect
+            }
+            "resume" => {
+                no_more(cmd, rest, 1)?;
+                Command::Resume(required(cmd, "token", rest)?)
+            }
+            "config" => {
+                no_more(cmd, rest, 0)?;
+                Command::Config
+            }
+            "stats" => {
+                no_more(cmd, rest, 0)?;
+                Command::Stats
+            }
+            "help" => {
+                no_more(cmd, rest, 0)?;
+                Command::Help
+            }
+            "prefix?" => {
+                no_more(cmd, rest, 0)?;
+                Command::Prefix
+            }
+            "commands" => {
+                no_more(cmd, rest, 0)?;
+                Command::Commands
+            }
+            _ => return Err(ParseError::UnknownCommand(cmd.to_string())),
+        };
+        Ok(command)
+    }
+}
+
+// One line per command under a heading, as in ":name [nick] - Set your nickname"
+fn describe(heading: &str, prefix: char, commands: impl Iterator<Item = Command>) -> String {
+    let mut text = String::from(heading);
+    for command in commands {
+        let name = command.name();
+        match command.usage() {
+            ("", about) => text.push_str(&format!("\n{prefix}{name} - {about}")),
+            (args, about) => text.push_str(&format!("\n{prefix}{name} {args} - {about}")),
+        }
+    }
+    text
+}
+
+// Split a command line on whitespace, keeping "quoted text" together
+fn tokenize(mut input: &str) -> Result<Vec<String>, ParseError> {
+    let mut args = Vec::new();
+    while let Some((arg, after)) = next_token(input)? {
+        args.push(arg);
+        input = after;
+    }
+    Ok(args)
+}
+
+// The first whitespace separated argument, with any quotes taken out, and
+// the input that follows it. None if there is nothing but whitespace
+fn next_token(input: &str) -> Result<Option<(String, &str)>, ParseError> {
+    let input = input.trim_start();
+    if input.is_empty() {
+        return Ok(None);
+    }
+    let mut token = String::new();
+    let mut quoted = false;
+    for (at, c) in input.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => return Ok(Some((token, &input[at..]))),
+            c => token.push(c),
+        }
+    }
+    match quoted {
+        true => Err(ParseError::UnterminatedQuote),
+        false => Ok(Some((token, ""))),
+    }
+}
+
+// Split off the first count arguments, leaving the rest of the input as
+// typed. The rest is None when nothing but whitespace is left
+fn leading(mut input: &str, count: usize) -> Result<(Vec<String>, Option<String>), ParseError> {
+    let mut args = Vec::with_capacity(count);
+    while args.len() < count {
+        let Some((arg, after)) = next_token(input)? else {
+            break;
+        };
+        args.push(arg);
+        input = after;
+    }
+    let text = input.trim();
+    // Quotes around the whole of it were only there to group it
+    let text = match text
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+    {
+        Some(inner) if !inner.contains('"') => inner,
+        _ => text,
+    };
+    Ok((args, (!text.is_empty()).then(|| text.to_string())))
+}
+
+// The first argument, which the command can't do without
+fn required(cmd: &str, arg: &'static str, rest: &[String]) -> Result<String, ParseError> {
+    rest.first()
+        .cloned()
+        .ok_or_else(|| ParseError::MissingArgument {
+            cmd: cmd.to_string(),
+            arg,
+        })
+}
+
+// Refuse arguments beyond the number the command takes
+fn no_more(cmd: &str, rest: &[String], max: usize) -> Result<(), ParseError> {
+    match rest.len() > max {
+        true => Err(ParseError::TooManyArguments(cmd.to_string())),
+        false => Ok(()),
+    }
+}
@@ -1,13 +1,17 @@
-use crate::ConfigError;
+use crate::{Client, ConfigError};
 use ron::ser::PrettyConfig;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
+    env,
     fs::{self, File},
     io::{Read, Write},
-    net::{Ipv4Addr, Ipv6Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    num::{NonZeroU16, NonZeroU32},
     path::{Path, PathBuf},
     str::FromStr,
 };
+use tokio::net::lookup_host;
 
 /// A constant string slice that defines the default configuration file path.
 ///
@@ -19,7 +23,7 @@ use std::{
 ///
 /// This constant can be used whenever the application requires the default configuration file path:
 ///
-/// ```rust
+/// ```ignore
 /// let config_path = DEFAULT_CONFIG_FILE;
 /// println!("Loading configuration from: {}", config_path);
 /// ```
@@ -28,6 +32,204 @@ use std::{
 /// Ensure that the file exists and is properly formatted to prevent runtime errors during configuration loading.
 const DEFAULT_CONFIG_FILE: &str = "env/config.ron";
 
+/// Decides where `:ignore` is enforced.
+///
+/// # Variants
+/// - `Client`
+///   The client hides messages from ignored users, but the server still sends them.
+/// - `Server`
+///   The server stops sending messages from ignored users at all, saving bandwidth.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IgnoreMode {
+    #[default]
+    Client,
+    Server,
+}
+
+/// Decides how the server writes its logs.
+///
+/// # Variants
+/// - `Plain`
+///   Human readable lines, one event per line.
+/// - `Json`
+///   One JSON object per line, for feeding into log tooling.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Plain,
+    Json,
+}
+
+/// Decides what users who haven't set a nickname are shown as.
+///
+/// # Variants
+/// - `Address`
+///   The address they connected from, such as `127.0.0.1:50312`.
+/// - `Guest`
+///   Simply `Guest`, so nothing about the connection is given away.
+/// - `MemberIdPrefix`
+///   The first eight characters of their client id, which tells unnamed users apart without
+///   giving away where they connected from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisplayFallback {
+    #[default]
+    Address,
+    Guest,
+    MemberIdPrefix,
+}
+
+impl DisplayFallback {
+    /// Returns what an unnamed user with the given `Client` is shown as.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_shared::{Client, DisplayFallback};
+    ///
+    /// let client = Client::new(String::from("127.0.0.1:50312"));
+    /// assert_eq!(DisplayFallback::Address.name_for(&client), "127.0.0.1:50312");
+    /// assert_eq!(DisplayFallback::Guest.name_for(&client), "Guest");
+    /// assert_eq!(DisplayFallback::MemberIdPrefix.name_for(&client), client.id[..8]);
+    /// ```
+    pub fn name_for(&self, client: &Client) -> String {
+        match self {
+            DisplayFallback::Address => client.address.clone(),
+            DisplayFallback::Guest => String::from("Guest"),
+            DisplayFallback::MemberIdPrefix => client.id.chars().take(8).collect(),
+        }
+    }
+}
+
+/// The formats a config file can be written in, told apart by the file's extension.
+///
+/// # Variants
+/// - `Ron`
+///   Rusty Object Notation, for files ending in `.ron`. This is what the default config uses.
+/// - `Json`
+///   JSON, for files ending in `.json`, which more tooling understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Ron,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Works out the format of a config file from its extension.
+    ///
+    /// # Errors
+    /// * `ConfigError::UnknownFormat` - If the extension is neither `ron` nor `json`, or
+    ///   there isn't one.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_shared::{ConfigError, ConfigFormat};
+    /// use std::path::Path;
+    ///
+    /// let format = ConfigFormat::from_path(Path::new("env/config.json")).unwrap();
+    /// assert_eq!(format, ConfigFormat::Json);
+    ///
+    /// let unknown = ConfigFormat::from_path(Path::new("env/config.toml"));
+    /// assert!(matches!(unknown, Err(ConfigError::UnknownFormat(_))));
+    /// ```
+    pub fn from_path(path: &Path) -> Result<Self, ConfigError> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ron") => Ok(ConfigFormat::Ron),
+            Some("json") => Ok(ConfigFormat::Json),
+            _ => Err(ConfigError::UnknownFormat(path.to_path_buf())),
+        }
+    }
+
+    /// Parses the contents of a config file written in this format.
+    ///
+    /// # Errors
+    /// * `ConfigError::ConfigParseFailed` - If the contents are not a valid config.
+    pub fn parse(&self, contents: &str) -> Result<Config, ConfigError> {
+        match self {
+            ConfigFormat::Ron => {
+                ron::from_str(contents).map_err(|e| ConfigError::ConfigParseFailed(e.to_string()))
+            }
+            ConfigFormat::Json => serde_json::from_str(contents)
+                .map_err(|e| ConfigError::ConfigParseFailed(e.to_string())),
+        }
+    }
+
+    /// Writes a config out in this format, laid out to be read and edited by hand.
+    ///
+    /// # Errors
+    /// * `ConfigError::NoConfigOrFlag` - If the config could not be serialized.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_shared::{Config, ConfigFormat};
+    ///
+    /// let config = Config::default();
+    /// for format in [ConfigFormat::Ron, ConfigFormat::Json] {
+    ///     let written = format.serialize(&config).unwrap();
+    ///     assert_eq!(format.parse(&written).unwrap(), config);
+    /// }
+    /// ```
+    pub fn serialize(&self, config: &Config) -> Result<String, ConfigError> {
+        match self {
+            ConfigFormat::Ron => {
+                let pretty_config = PrettyConfig::new().indentor("\t").struct_names(false);
+                ron::ser::to_string_pretty(config, pretty_config)
+                    .map_err(|_| ConfigError::NoConfigOrFlag)
+            }
+            ConfigFormat::Json => {
+                serde_json::to_string_pretty(config).map_err(|_| ConfigError::NoConfigOrFlag)
+            }
+        }
+    }
+}
+
+/// Settings for running connections over TLS. The server and the client read the same
+/// struct from their own config files, and each only looks at the fields it needs.
+///
+/// # Fields
+/// - `cert_path` (*`Option<PathBuf>`*):
+///   The PEM certificate chain the server presents. Required by the server.
+/// - `key_path` (*`Option<PathBuf>`*):
+///   The PEM private key for `cert_path`. Required by the server.
+/// - `ca_path` (*`Option<PathBuf>`*):
+///   The PEM certificates the client trusts when checking the server, for example the
+///   server's own certificate when it is self-signed. Required by the client unless
+///   `verify` is off.
+///   A self-signed certificate has to be made without `CA:TRUE`, as a CA certificate is
+///   refused when the server presents it as its own.
+/// - `server_name` (*`Option<String>`*):
+///   The name the client expects the server's certificate to be issued for. If `None`,
+///   the host IP from the config is used.
+/// - `verify` (*`bool`*):
+///   Whether the client checks the server's certificate at all. Turning it off still
+///   encrypts traffic but lets anyone pretend to be the server, so only do it for testing.
+/// - `client_ca_path` (*`Option<PathBuf>`*):
+///   The PEM certificates the server trusts to issue client certificates. If set, the server
+///   turns away any client that doesn't present a certificate issued by one of them, and each
+///   client goes by the name its certificate is issued for. If `None`, clients need none.
+/// - `client_cert_path` (*`Option<PathBuf>`*):
+///   The PEM certificate chain the client presents when the server asks for one. Needs
+///   `client_key_path` too. If `None`, the client presents no certificate.
+/// - `client_key_path` (*`Option<PathBuf>`*):
+///   The PEM private key for `client_cert_path`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    #[serde(default)]
+    pub cert_path: Option<PathBuf>,
+    #[serde(default)]
+    pub key_path: Option<PathBuf>,
+    #[serde(default)]
+    pub ca_path: Option<PathBuf>,
+    #[serde(default)]
+    pub server_name: Option<String>,
+    #[serde(default = "default_verify")]
+    pub verify: bool,
+    #[serde(default)]
+    pub client_ca_path: Option<PathBuf>,
+    #[serde(default)]
+    pub client_cert_path: Option<PathBuf>,
+    #[serde(default)]
+    pub client_key_path: Option<PathBuf>,
+}
+
 /// The `Config` struct is used to define the configuration settings for a specific application or functionality.
 ///
 /// This struct supports serialization and deserialization using Serde, and includes the following fields:
@@ -40,14 +242,135 @@ const DEFAULT_CONFIG_FILE: &str = "env/config.ron";
 ///   An optional IPv6 address specifying the host IP.
 ///   If `None`, no IPv6 address is configured.
 /// - `host_port` (*usize*):
+///   Must be a valid TCP port, from `1` to `65535`.
 ///   The port number on which the host operates.
 ///   This is required and must be a valid TCP/UDP port.
-/// - `msg_size` (*u8*):
-///   Specifies the size of the message in bytes.
-///   Represents the configured message buffer size.
 /// - `prefix` (*char*):
 ///   A character used as a prefix within the application.
 ///   This may be used for message parsing or other internal purposes.
+/// - `admin_ips` (*`Vec<IpAddr>`*):
+///   Addresses whose connections are given the `Admin` role by the server.
+///   Defaults to an empty list when omitted from the config file.
+/// - `bandwidth_limit_bps` (*`Option<u64>`*):
+///   An optional cap, in bytes per second, on how fast the server reads from and writes to
+///   each connection. If `None`, connections are not limited.
+/// - `show_timestamps` (*`bool`*):
+///   Whether the client shows the time next to each message it prints. This can be
+///   toggled while chatting with `:timestamps on` and `:timestamps off`.
+/// - `ignore_list_path` (*`Option<PathBuf>`*):
+///   An optional file the client uses to remember the users ignored with `:ignore`.
+///   If `None`, the ignore list only lasts until the client exits.
+/// - `ignore_mode` (*`IgnoreMode`*):
+///   Whether `:ignore` is only applied by the client or also enforced by the server.
+/// - `log_format` (*`LogFormat`*):
+///   Whether the server logs plain text lines or JSON objects.
+/// - `log_chat_content` (*`bool`*):
+///   Whether the server includes the content of chat messages in its logs. Off by
+///   default so conversations stay private.
+/// - `history_size` (*`usize`*):
+///   How many of the most recent messages the server remembers, for example so
+///   `:reply` can check the message being replied to exists.
+/// - `event_log_path` (*`Option<PathBuf>`*):
+///   Where the server appends every message it accepts, with member records kept in
+///   `<path>.members`. If `None`, the server keeps both in memory and forgets them on restart.
+/// - `event_log_max_bytes` (*`u64`*):
+///   How large the event log may grow before it is rotated to `<path>.1`.
+/// - `replay_event_log` (*`bool`*):
+///   Whether the server reloads its history from the event log on startup, so it
+///   survives a restart.
+/// - `first_message_hint` (*`Option<String>`*):
+///   A hint sent privately to each user after their first message, for example
+///   `"Tip: use :help for commands"`. If `None`, no hint is sent.
+/// - `command_cooldowns_ms` (*`HashMap<String, u64>`*):
+///   How long, in milliseconds, a user must wait between uses of a command, keyed
+///   by command name without the prefix, for example `{"info": 5000}`. Commands that
+///   are not listed can be used as often as the user likes.
+/// - `max_frame_size` (*`u32`*):
+///   The largest serialized message, in bytes, that will be sent or accepted. Anything
+///   larger is refused rather than cut short. It must not be `0`. Config files from before
+///   messages were length prefixed may still set `msg_size`, which is ignored.
+/// - `disabled_commands` (*`Vec<String>`*):
+///   Commands the server refuses, by name without the prefix, for example `["info"]`.
+///   `quit` is always allowed, even if it is listed.
+/// - `time_format` (*`Option<String>`*):
+///   The strftime format used for the time shown in front of messages when timestamps are on.
+///   If `None`, `%H:%M:%S` is used.
+/// - `history_path` (*`Option<PathBuf>`*):
+///   An optional file the server appends every message sent to everyone to, one RON line each.
+///   It is created if missing. If `None`, no transcript is kept.
+/// - `echo_own_messages` (*`bool`*):
+///   Whether the client shows the messages it sent when the server relays them back,
+///   as confirmation they were delivered. Otherwise they are only shown as typed.
+/// - `history_replay` (*`usize`*):
+///   How many of the most recent messages sent to everyone a newly connected client is sent,
+///   so it joins with some context. At most `history_size` are kept to replay.
+/// - `max_history_request` (*`usize`*):
+///   The most messages a single `:history` will send back. Larger requests are cut down to it.
+/// - `auto_create_channels` (*`bool`*):
+///   Whether `:join` creates a channel that doesn't exist yet, making the joiner its owner.
+///   Otherwise only admins can create channels, and everyone else is told there is no such channel.
+/// - `heartbeat_secs` (*`Option<u64>`*):
+///   An optional interval, in seconds, at which the server pings every client to check it is
+///   still there. If `None`, no pings are sent. Clients from before pings were added can't answer them.
+/// - `heartbeat_miss_limit` (*`u32`*):
+///   How many pings in a row a client may leave unanswered before the server disconnects it.
+/// - `rate_limit_per_sec` (*`Option<u64>`*):
+///   An optional cap on how many chat messages each user may send per second, on average.
+///   Messages over the limit are dropped. If `None`, users are not limited.
+/// - `rate_limit_burst` (*`u64`*):
+///   How many chat messages a user may send at once before `rate_limit_per_sec` applies.
+/// - `max_clients` (*`Option<usize>`*):
+///   An optional cap on how many clients may be connected at once. Anyone connecting
+///   beyond it is told the server is full and disconnected. If `None`, there is no cap.
+/// - `presence_notices` (*`bool`*):
+///   Whether everyone is told when a user joins, once they have set a nickname, and when they leave.
+/// - `reconnect_grace_secs` (*`u64`*):
+///   How long, in seconds, the server waits before announcing that a user left. If they
+///   reconnect and resume their session in that time, neither the leave nor the rejoin is announced.
+/// - `max_channels_per_user` (*`Option<usize>`*):
+///   An optional cap on how many channels a single user can be in at once. If `None`,
+///   users can join as many as they like.
+/// - `tls` (*`Option<TlsConfig>`*):
+///   Certificates and settings for running connections over TLS. If `None`, traffic is
+///   sent as plain text, which is only fit for localhost.
+/// - `frame_checksums` (*`bool`*):
+///   Whether a CRC32 of the content is sent with every message, so the receiver can drop
+///   one that arrived corrupted instead of showing it. Checksums are checked whenever
+///   they are present, whatever this is set to.
+/// - `departure_reasons` (*`bool`*):
+///   Whether the notice that a user left says why, for example `alice left (kicked)`.
+/// - `reconnect_base_ms` (*`u64`*):
+///   How long, in milliseconds, the client waits before trying to reconnect after losing
+///   the server. The wait doubles after each failed try, up to `reconnect_max_ms`.
+/// - `reconnect_max_ms` (*`u64`*):
+///   The longest, in milliseconds, the client waits between tries to reconnect.
+/// - `display_fallback` (*`DisplayFallback`*):
+///   What the server shows for users who haven't set a nickname. Defaults to their address.
+/// - `case_insensitive_channels` (*`bool`*):
+///   Whether channel names are matched ignoring case, so `#General` and `#general` are the
+///   same channel. The channel keeps the casing it was created with.
+/// - `shutdown_timeout_secs` (*`u64`*):
+///   How long, in seconds, the server waits on shutdown for clients to be sent what is
+///   queued for them. Clients still not done after that are cut off.
+/// - `nick_min_len` (*`usize`*):
+///   The fewest characters a nickname may have.
+/// - `nick_max_len` (*`usize`*):
+///   The most characters a nickname may have.
+/// - `prompt` (*`String`*):
+///   What the client prints when it is ready for input. It is left out when stdin isn't
+///   a terminal, such as when input is piped in, and an empty prompt turns it off entirely.
+/// - `host_name` (*`Option<String>`*):
+///   A host name for the client to connect to, such as `chat.example.com`. It is
+///   resolved when the client starts and takes the place of `host_ipv4` and `host_ipv6`.
+///   If `None`, the client connects to the literal address instead.
+/// - `queue_high_water` (*`usize`*):
+///   How many frames may wait in the server's writer queue, or in any one client's outbox,
+///   before the server warns that it can't keep up. Each queue holds 32, and once one is
+///   full the messages for it are dropped, so this gives warning before that happens.
+/// - `normalize_newlines` (*`bool`*):
+///   Whether the server turns `\r\n` and lone `\r` line endings in chat messages into `\n`
+///   before relaying them, so clients on other systems don't see stray carriage returns.
+///   Newlines within a message are kept.
 ///
 /// ## Derives
 /// - `Serialize`: Allows the `Config` struct to be serialized into RON.
@@ -56,26 +379,206 @@ const DEFAULT_CONFIG_FILE: &str = "env/config.ron";
 ///
 /// ## Example Usage
 /// ```rust
-/// use std::net::{Ipv4Addr, Ipv6Addr};
-/// use serde::{Serialize, Deserialize};
+/// use chat_shared::Config;
+/// use std::net::Ipv4Addr;
 ///
 /// let config = Config {
 ///     host_ipv4: Some(Ipv4Addr::new(192, 168, 0, 1)),
 ///     host_ipv6: None,
 ///     host_port: 8080,
-///     msg_size: 64,
 ///     prefix: '#',
+///     ..Config::default()
 /// };
 ///
 /// println!("{:?}", config);
 /// ```
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Config {
     pub host_ipv4: Option<Ipv4Addr>,
     pub host_ipv6: Option<Ipv6Addr>,
     pub host_port: usize,
-    pub msg_size: u8,
     pub prefix: char,
+    #[serde(default)]
+    pub admin_ips: Vec<IpAddr>,
+    #[serde(default)]
+    pub bandwidth_limit_bps: Option<u64>,
+    #[serde(default)]
+    pub show_timestamps: bool,
+    #[serde(default)]
+    pub ignore_list_path: Option<PathBuf>,
+    #[serde(default)]
+    pub ignore_mode: IgnoreMode,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    #[serde(default)]
+    pub log_chat_content: bool,
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+    #[serde(default)]
+    pub event_log_path: Option<PathBuf>,
+    #[serde(default = "default_event_log_max_bytes")]
+    pub event_log_max_bytes: u64,
+    #[serde(default)]
+    pub replay_event_log: bool,
+    #[serde(default)]
+    pub first_message_hint: Option<String>,
+    #[serde(default)]
+    pub command_cooldowns_ms: HashMap<String, u64>,
+    #[serde(default = "default_max_frame_size")]
+    pub max_frame_size: u32,
+    #[serde(default)]
+    pub disabled_commands: Vec<String>,
+    #[serde(default)]
+    pub time_format: Option<String>,
+    #[serde(default)]
+    pub history_path: Option<PathBuf>,
+    #[serde(default)]
+    pub echo_own_messages: bool,
+    #[serde(default)]
+    pub history_replay: usize,
+    #[serde(default = "default_max_history_request")]
+    pub max_history_request: usize,
+    #[serde(default = "default_auto_create_channels")]
+    pub auto_create_channels: bool,
+    #[serde(default)]
+    pub heartbeat_secs: Option<u64>,
+    #[serde(default = "default_heartbeat_miss_limit")]
+    pub heartbeat_miss_limit: u32,
+    #[serde(default)]
+    pub rate_limit_per_sec: Option<u64>,
+    #[serde(default = "default_rate_limit_burst")]
+    pub rate_limit_burst: u64,
+    #[serde(default)]
+    pub max_clients: Option<usize>,
+    #[serde(default = "default_presence_notices")]
+    pub presence_notices: bool,
+    #[serde(default = "default_reconnect_grace_secs")]
+    pub reconnect_grace_secs: u64,
+    #[serde(default)]
+    pub max_channels_per_user: Option<usize>,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub frame_checksums: bool,
+    #[serde(default)]
+    pub departure_reasons: bool,
+    #[serde(default = "default_reconnect_base_ms")]
+    pub reconnect_base_ms: u64,
+    #[serde(default = "default_reconnect_max_ms")]
+    pub reconnect_max_ms: u64,
+    #[serde(default)]
+    pub display_fallback: DisplayFallback,
+    #[serde(default = "default_case_insensitive_channels")]
+    pub case_insensitive_channels: bool,
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    #[serde(default = "default_nick_min_len")]
+    pub nick_min_len: usize,
+    #[serde(default = "default_nick_max_len")]
+    pub nick_max_len: usize,
+    #[serde(default = "default_prompt")]
+    pub prompt: String,
+    #[serde(default)]
+    pub host_name: Option<String>,
+    #[serde(default = "default_queue_high_water")]
+    pub queue_high_water: usize,
+    #[serde(default = "default_normalize_newlines")]
+    pub normalize_newlines: bool,
+}
+
+// Used by serde when `history_size` is missing from the config file
+fn default_history_size() -> usize {
+    100
+}
+
+// Used by serde when `max_history_request` is missing from the config file
+fn default_max_history_request() -> usize {
+    50
+}
+
+// Used by serde when `auto_create_channels` is missing from the config file
+fn default_auto_create_channels() -> bool {
+    true
+}
+
+// Used by serde when `case_insensitive_channels` is missing from the config file
+fn default_case_insensitive_channels() -> bool {
+    true
+}
+
+// Used by serde when `shutdown_timeout_secs` is missing from the config file
+fn default_shutdown_timeout_secs() -> u64 {
+    5
+}
+
+// Used by serde when `nick_min_len` is missing from the config file
+fn default_nick_min_len() -> usize {
+    1
+}
+
+// Used by serde when `nick_max_len` is missing from the config file
+fn default_nick_max_len() -> usize {
+    32
+}
+
+// Used by serde when `prompt` is missing from the config file
+fn default_prompt() -> String {
+    String::from("> ")
+}
+
+// Used by serde when `queue_high_water` is missing from the config file
+fn default_queue_high_water() -> usize {
+    24
+}
+
+// Used by serde when `normalize_newlines` is missing from the config file
+fn default_normalize_newlines() -> bool {
+    true
+}
+
+// Used by serde when `heartbeat_miss_limit` is missing from the config file
+fn default_heartbeat_miss_limit() -> u32 {
+    3
+}
+
+// Used by serde when `rate_limit_burst` is missing from the config file
+fn default_rate_limit_burst() -> u64 {
+    5
+}
+
+// Used by serde when `presence_notices` is missing from the config file
+fn default_presence_notices() -> bool {
+    true
+}
+
+// Used by serde when `reconnect_grace_secs` is missing from the config file
+fn default_reconnect_grace_secs() -> u64 {
+    10
+}
+
+// Used by serde when `event_log_max_bytes` is missing from the config file
+fn default_event_log_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+// Used by serde when `max_frame_size` is missing from the config file
+fn default_max_frame_size() -> u32 {
+    64 * 1024
+}
+
+// Used by serde when `reconnect_base_ms` is missing from the config file
+fn default_reconnect_base_ms() -> u64 {
+    500
+}
+
+// Used by serde when `reconnect_max_ms` is missing from the config file
+fn default_reconnect_max_ms() -> u64 {
+    30_000
+}
+
+// Used by serde when `verify` is missing from the TLS settings
+fn default_verify() -> bool {
+    true
 }
 
 /// The `Default` trait is used to define a default configuration.
@@ -86,8 +589,50 @@ impl Default for Config {
     /// - `host_ipv4`: Set to `Some(127.0.0.1)`, which is the default loopback address for IPv4.
     /// - `host_ipv6`: Set to `None`, indicating no IPv6 address by default.
     /// - `host_port`: Set to `7070`, representing the default port to use.
-    /// - `msg_size`: Set to `255`, defining the maximum message size.
     /// - `prefix`: Set to the character `:`. This is parsed from a string representation.
+    /// - `admin_ips`: Set to an empty list, so no connection is an admin by default.
+    /// - `bandwidth_limit_bps`: Set to `None`, so connections are not limited.
+    /// - `show_timestamps`: Set to `false`, so timestamps are hidden.
+    /// - `ignore_list_path`: Set to `None`, so the ignore list is not saved.
+    /// - `ignore_mode`: Set to `IgnoreMode::Client`, so only the client hides ignored users.
+    /// - `log_format`: Set to `LogFormat::Plain`.
+    /// - `log_chat_content`: Set to `false`, so chat content is never logged.
+    /// - `history_size`: Set to `100` messages.
+    /// - `event_log_path`: Set to `None`, so no event log is kept.
+    /// - `event_log_max_bytes`: Set to `10485760` bytes, which is 10 MiB.
+    /// - `replay_event_log`: Set to `false`, so the server starts with an empty history.
+    /// - `first_message_hint`: Set to `None`, so no hint is sent.
+    /// - `command_cooldowns_ms`: Set to an empty map, so no command has a cooldown.
+    /// - `max_frame_size`: Set to `65536` bytes.
+    /// - `disabled_commands`: Set to an empty list, so every command is allowed.
+    /// - `time_format`: Set to `None`, so times are shown as `%H:%M:%S`.
+    /// - `history_path`: Set to `None`, so no transcript is kept.
+    /// - `echo_own_messages`: Set to `false`, so our own messages aren't shown twice.
+    /// - `history_replay`: Set to `0`, so nothing is replayed.
+    /// - `max_history_request`: Set to `50` messages.
+    /// - `auto_create_channels`: Set to `true`, so anyone can create a channel by joining it.
+    /// - `heartbeat_secs`: Set to `None`, so clients are not pinged.
+    /// - `heartbeat_miss_limit`: Set to `3` pings.
+    /// - `rate_limit_per_sec`: Set to `None`, so users are not limited.
+    /// - `rate_limit_burst`: Set to `5` messages.
+    /// - `max_clients`: Set to `None`, so there is no cap.
+    /// - `presence_notices`: Set to `true`, so joins and leaves are announced.
+    /// - `reconnect_grace_secs`: Set to `10` seconds.
+    /// - `max_channels_per_user`: Set to `None`, so there is no cap.
+    /// - `tls`: Set to `None`, so connections are plain text.
+    /// - `frame_checksums`: Set to `false`, so no checksums are sent.
+    /// - `departure_reasons`: Set to `false`, so leave notices don't give a reason.
+    /// - `reconnect_base_ms`: Set to `500`.
+    /// - `reconnect_max_ms`: Set to `30000`, or 30 seconds.
+    /// - `display_fallback`: Set to `DisplayFallback::Address`, so unnamed users show as their address.
+    /// - `case_insensitive_channels`: Set to `true`, so channel names are matched ignoring case.
+    /// - `shutdown_timeout_secs`: Set to `5`.
+    /// - `nick_min_len`: Set to `1`.
+    /// - `nick_max_len`: Set to `32`.
+    /// - `prompt`: Set to `"> "`.
+    /// - `host_name`: Set to `None`, so the client connects to the literal address.
+    /// - `queue_high_water`: Set to `24`.
+    /// - `normalize_newlines`: Set to `true`.
     ///
     /// # Panics
     /// This function will panic if the `char::from_str` for `:` fails, although such a failure
@@ -106,8 +651,50 @@ impl Default for Config {
             host_ipv4: Some(Ipv4Addr::from([127, 0, 0, 1])),
             host_ipv6: None,
             host_port: 7070,
-            msg_size: 255,
             prefix: char::from_str(":").expect("':' COULD NOT CONVERT TO CHAR"),
+            admin_ips: Vec::new(),
+            bandwidth_limit_bps: None,
+            show_timestamps: false,
+            ignore_list_path: None,
+            ignore_mode: IgnoreMode::Client,
+            log_format: LogFormat::Plain,
+            log_chat_content: false,
+            history_size: default_history_size(),
+            event_log_path: None,
+            event_log_max_bytes: default_event_log_max_bytes(),
+            replay_event_log: false,
+            first_message_hint: None,
+            command_cooldowns_ms: HashMap::new(),
+            max_frame_size: default_max_frame_size(),
+            disabled_commands: Vec::new(),
+            time_format: None,
+            history_path: None,
+            echo_own_messages: false,
+            history_replay: 0,
+            max_history_request: default_max_history_request(),
+            auto_create_channels: default_auto_create_channels(),
+            heartbeat_secs: None,
+            heartbeat_miss_limit: default_heartbeat_miss_limit(),
+            rate_limit_per_sec: None,
+            rate_limit_burst: default_rate_limit_burst(),
+            max_clients: None,
+            presence_notices: default_presence_notices(),
+            reconnect_grace_secs: default_reconnect_grace_secs(),
+            max_channels_per_user: None,
+            tls: None,
+            frame_checksums: false,
+            departure_reasons: false,
+            reconnect_base_ms: default_reconnect_base_ms(),
+            reconnect_max_ms: default_reconnect_max_ms(),
+            display_fallback: DisplayFallback::Address,
+            case_insensitive_channels: default_case_insensitive_channels(),
+            shutdown_timeout_secs: default_shutdown_timeout_secs(),
+            nick_min_len: default_nick_min_len(),
+            nick_max_len: default_nick_max_len(),
+            prompt: default_prompt(),
+            host_name: None,
+            queue_high_water: default_queue_high_water(),
+            normalize_newlines: default_normalize_newlines(),
         }
     }
 }
@@ -115,10 +702,11 @@ impl Default for Config {
 /// Creates a default configuration file at the specified path and returns the default `Config` object.
 ///
 /// This function performs the following steps:
-/// 1. Attempts to create a file at the provided `path`.
+/// 1. Works out the format to write from the extension of `path`, then attempts to create a file there.
 /// 2. If file creation is successful:
 ///    - Generates a default `Config` object using `Config::default()`.
-///    - Serializes the default configuration into a pretty RON (Rusty Object Notation) string using a specified format.
+///    - Serializes the default configuration into a pretty string, as RON (Rusty Object Notation) or
+///      JSON depending on the extension of `path`.
 ///    - Writes the serialized string to the newly created file.
 ///    - Returns the default `Config` object upon success.
 /// 3. If file creation fails or any of the above operations encounter an error,
@@ -133,13 +721,14 @@ impl Default for Config {
 ///   serialization, or writing to the file.
 ///
 /// # Errors
+/// * Returns `Err(ConfigError::UnknownFormat)` if `path` ends in neither `.ron` nor `.json`.
 /// * Returns `Err(ConfigError::NoConfigOrFlag)` if:
 ///   - The function fails to create the file at the specified path.
 ///   - There is an error serializing the default `Config` object to a RON string.
 ///   - There is an error writing the serialized data to the file.
 ///
 /// # Examples
-/// ```rust
+/// ```ignore
 /// use std::path::PathBuf;
 ///
 /// let path = PathBuf::from("config.ron");
@@ -153,14 +742,12 @@ impl Default for Config {
 /// }
 /// ```
 fn create_default(path: &PathBuf) -> Result<Config, ConfigError> {
+    let format = ConfigFormat::from_path(path)?;
     let config_file = File::create(path);
     match config_file {
         Ok(mut file) => {
-            let pretty_config = PrettyConfig::new().indentor("\t").struct_names(false);
-
             let default_config = Config::default();
-            let default_string = ron::ser::to_string_pretty(&default_config, pretty_config)
-                .map_err(|_| ConfigError::NoConfigOrFlag)?;
+            let default_string = format.serialize(&default_config)?;
             file.write_all(default_string.as_bytes())
                 .map_err(|_| ConfigError::NoConfigOrFlag)?;
             Ok(default_config)
@@ -169,6 +756,31 @@ fn create_default(path: &PathBuf) -> Result<Config, ConfigError> {
     }
 }
 
+/// Reads an environment variable and parses it into the type of the setting it overrides.
+///
+/// # Arguments
+/// * `name` - The name of the environment variable, such as `CHAT_HOST_PORT`.
+///
+/// # Returns
+/// * `Ok(Some(T))` - The parsed value, if the variable is set.
+/// * `Ok(None)` - If the variable is not set.
+///
+/// # Errors
+/// * `ConfigError::InvalidEnvVar` - If the variable is set but its value doesn't parse,
+///   including when it is not valid Unicode.
+fn env_override<T: FromStr>(name: &str) -> Result<Option<T>, ConfigError> {
+    let Some(value) = env::var_os(name) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .and_then(|value| value.parse().ok())
+        .map(Some)
+        .ok_or_else(|| {
+            ConfigError::InvalidEnvVar(name.to_string(), value.to_string_lossy().into_owned())
+        })
+}
+
 impl Config {
     pub fn new() -> Self {
         Self::default()
@@ -179,7 +791,7 @@ impl Config {
     ///
     /// # Arguments
     /// * `config_path` - An optional path reference to a configuration file. If `None` is provided,
-    ///    the function attempts to dynamically locate a configuration file based on the execution directory.
+    ///   the function attempts to dynamically locate a configuration file based on the execution directory.
     ///
     /// # Returns
     /// * `Ok(Self)` - A successfully parsed and loaded `Config` object.
@@ -200,22 +812,33 @@ impl Config {
     ///
     /// If the `config_path` is provided:
     /// - It attempts to open the file at the specified path.
-    /// - Reads the file contents and parses it into a `Config` object using the `ron` format.
+    /// - Reads the file contents and parses it into a `Config` object, as RON if the path ends in
+    ///   `.ron` and as JSON if it ends in `.json`.
     ///
     /// # Errors
     /// * `ConfigError::NoConfigOrFlag` - If the configuration file path is invalid, missing, or
     ///   not located in a valid directory structure during dynamic discovery.
+    /// * `ConfigError::UnknownFormat` - If the path given ends in neither `.ron` nor `.json`.
     /// * `ConfigError::ConfigReadFailed` - If the function fails to read the file contents.
     /// * `ConfigError::ConfigParseFailed` - If the function fails to parse the configuration file.
+    /// * `ConfigError::ZeroFrameSize` - If the configuration file sets `max_frame_size` to `0`.
+    /// * `ConfigError::InvalidPort` - If the configuration file sets `host_port` outside `1` to `65535`.
+    /// * `ConfigError::InvalidEnvVar` - If an environment variable overriding a setting holds
+    ///   a value that setting can't take. See `overlay_env`.
     ///
     /// # Notes
+    /// - Once the file is parsed, any `CHAT_*` environment variables are overlaid on it and take
+    ///   precedence over what the file says. See `overlay_env`.
     /// - The function assumes a specific directory structure and configuration file naming convention.
     /// - In cases where dynamic discovery fails, ensure the `DEFAULT_CONFIG_FILE` exists and is accessible.
     /// - The `chat_shared`, `chat_server`, and `chat_client` directories are used as indicators to identify
     ///   the valid project structure.
     ///
     /// # Examples
-    /// ```rust
+    /// ```no_run
+    /// use chat_shared::Config;
+    /// use std::path::Path;
+    ///
     /// // Attempt to load configuration from a specified path
     /// let config = Config::from_path(Some(Path::new("path/to/config.ron")));
     ///
@@ -229,7 +852,9 @@ impl Config {
     /// ```
     pub fn from_path(config_path: Option<&Path>) -> Result<Self, ConfigError> {
         let mut config_file: File;
+        let format: ConfigFormat;
         if let Some(config_path) = config_path {
+            format = ConfigFormat::from_path(config_path)?;
             config_file = match File::open(config_path) {
                 Ok(file) => file,
                 Err(_) => return Err(ConfigError::NoConfigOrFlag),
@@ -260,15 +885,17 @@ impl Config {
                 {
                     let config_path = working_path.join(DEFAULT_CONFIG_FILE);
 
-                    let config = File::open(&config_path)
+                    let mut config = File::open(&config_path)
                         .ok()
                         .and_then(|mut file| {
                             let mut contents = String::new();
                             file.read_to_string(&mut contents).ok()?;
-                            ron::from_str::<Config>(&contents).ok()
+                            ConfigFormat::Ron.parse(&contents).ok()
                         })
                         .unwrap_or_else(|| create_default(&config_path).unwrap());
 
+                    config.overlay_env()?;
+                    config.validate()?;
                     return Ok(config);
                 }
 
@@ -284,11 +911,91 @@ impl Config {
 
         config_file
             .read_to_string(&mut contents)
-            .map_err(|_| ConfigError::ConfigReadFailed)?;
+            .map_err(|e| ConfigError::ConfigReadFailed(e.to_string()))?;
 
-        println!("{:?}", contents);
+        let mut config = format.parse(&contents)?;
+        config.overlay_env()?;
+        config.validate()?;
+        Ok(config)
+    }
 
-        ron::from_str::<Config>(&contents).map_err(|_| ConfigError::ConfigParseFailed)
+    /// Overlays settings from environment variables onto the config, so they can be changed
+    /// without editing the config file.
+    ///
+    /// Each variable is named after the field it sets, upper cased with a `CHAT_` prefix:
+    /// `CHAT_HOST_IPV4`, `CHAT_HOST_IPV6`, `CHAT_HOST_NAME`, `CHAT_HOST_PORT`,
+    /// `CHAT_MAX_FRAME_SIZE` and `CHAT_PREFIX`. A variable that is set takes precedence over the file, and one that
+    /// is not leaves its field as it was.
+    ///
+    /// # Errors
+    /// * `ConfigError::InvalidEnvVar` - If a variable is set to something its field can't hold,
+    ///   such as a `CHAT_HOST_PORT` that is not a port from `1` to `65535`, a
+    ///   `CHAT_MAX_FRAME_SIZE` of `0` or a `CHAT_PREFIX` longer than one character.
+    pub fn overlay_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(ip) = env_override("CHAT_HOST_IPV4")? {
+            self.host_ipv4 = Some(ip);
+        }
+        if let Some(ip) = env_override("CHAT_HOST_IPV6")? {
+            self.host_ipv6 = Some(ip);
+        }
+        if let Some(name) = env_override("CHAT_HOST_NAME")? {
+            self.host_name = Some(name);
+        }
+        if let Some(port) = env_override::<NonZeroU16>("CHAT_HOST_PORT")? {
+            self.host_port = port.get().into();
+        }
+        if let Some(size) = env_override::<NonZeroU32>("CHAT_MAX_FRAME_SIZE")? {
+            self.max_frame_size = size.get();
+        }
+        if let Some(prefix) = env_override("CHAT_PREFIX")? {
+            self.prefix = prefix;
+        }
+        Ok(())
+    }
+
+    /// Checks the settings that parse but can't be used.
+    ///
+    /// # Errors
+    /// * `ConfigError::ZeroFrameSize` - If `max_frame_size` is `0`.
+    /// * `ConfigError::InvalidPort` - If `host_port` is `0` or above `65535`.
+    ///
+    /// # Example
+    /// What a message can hold is capped by the frame size. Old config files that still set
+    /// `msg_size` parse, and it has no say in that:
+    /// ```
+    /// use chat_shared::{Config, ConfigError, Message, frame, message::MessageKind};
+    ///
+    /// let config: Config = ron::from_str(
+    ///     r#"(host_ipv4: Some("127.0.0.1"), host_port: 7171, msg_size: 16, prefix: ':', max_frame_size: 524288)"#,
+    /// )
+    /// .unwrap();
+    /// assert!(config.validate().is_ok());
+    ///
+    /// let text = "x".repeat(65536);
+    /// let client = std::sync::Arc::new(chat_shared::Client::new(String::from("me")));
+    /// let message = Message::from_string(client, text.clone(), MessageKind::Message);
+    /// let buff = frame::encode(&message, config.max_frame_size).unwrap();
+    /// assert_eq!(frame::decode(&buff[4..]).unwrap().as_string(), text);
+    ///
+    /// let zero = Config { max_frame_size: 0, ..config.clone() };
+    /// assert!(matches!(zero.validate(), Err(ConfigError::ZeroFrameSize)));
+    ///
+    /// // The port has to be one a listener can bind to
+    /// let valid = Config { host_port: 7070, ..config.clone() };
+    /// assert!(valid.validate().is_ok());
+    /// for port in [0, 70000] {
+    ///     let invalid = Config { host_port: port, ..config.clone() };
+    ///     assert!(matches!(invalid.validate(), Err(ConfigError::InvalidPort(p)) if p == port));
+    /// }
+    /// ```
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_frame_size == 0 {
+            return Err(ConfigError::ZeroFrameSize);
+        }
+        if u16::try_from(self.host_port).map_or(true, |port| port == 0) {
+            return Err(ConfigError::InvalidPort(self.host_port));
+        }
+        Ok(())
     }
 
     /// Retrieves the IP address from the configuration, prioritizing IPv6 over IPv4.
@@ -309,11 +1016,13 @@ impl Config {
     ///
     /// # Example
     /// ```rust
-    /// use some_crate::{YourStruct, ConfigError};
+    /// use chat_shared::Config;
+    /// use std::net::{Ipv4Addr, Ipv6Addr};
     ///
-    /// let config = YourStruct {
-    ///     host_ipv6: Some("::1".to_string()),
-    ///     host_ipv4: Some("127.0.0.1".to_string()),
+    /// let config = Config {
+    ///     host_ipv6: Some(Ipv6Addr::LOCALHOST),
+    ///     host_ipv4: Some(Ipv4Addr::LOCALHOST),
+    ///     ..Config::default()
     /// };
     ///
     /// let ip = config.get_ip();
@@ -338,4 +1047,156 @@ impl Config {
 
         Err(ConfigError::MissingHostIp)
     }
+
+    /// Returns every address the server should listen on.
+    ///
+    /// When both `host_ipv4` and `host_ipv6` are set the server listens on both, so
+    /// clients from either family can reach it. Otherwise it listens on whichever one
+    /// is set. Each address uses `host_port`.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<SocketAddr>)` - The IPv4 address first if there is one, then the IPv6 one.
+    ///
+    /// # Errors
+    /// Returns `ConfigError::MissingHostIp` if neither address is set, and
+    /// `ConfigError::InvalidPort` if `host_port` is not a valid TCP port.
+    ///
+    /// # Example
+    /// ```rust
+    /// use chat_shared::Config;
+    /// use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+    ///
+    /// let config = Config {
+    ///     host_ipv4: Some(Ipv4Addr::LOCALHOST),
+    ///     host_ipv6: Some(Ipv6Addr::LOCALHOST),
+    ///     host_port: 7171,
+    ///     ..Config::default()
+    /// };
+    ///
+    /// let addresses = config.listen_addresses().unwrap();
+    /// assert_eq!(
+    ///     addresses,
+    ///     [
+    ///         "127.0.0.1:7171".parse::<SocketAddr>().unwrap(),
+    ///         "[::1]:7171".parse::<SocketAddr>().unwrap(),
+    ///     ]
+    /// );
+    /// ```
+    pub fn listen_addresses(&self) -> Result<Vec<SocketAddr>, ConfigError> {
+        let port =
+            u16::try_from(self.host_port).map_err(|_| ConfigError::InvalidPort(self.host_port))?;
+        let addresses: Vec<SocketAddr> = [
+            self.host_ipv4.map(IpAddr::V4),
+            self.host_ipv6.map(IpAddr::V6),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect();
+
+        if addresses.is_empty() {
+            return Err(ConfigError::MissingHostIp);
+        }
+        Ok(addresses)
+    }
+
+    /// Works out the address the client should connect to.
+    ///
+    /// If `host_name` is set it is looked up, and the first address it resolves to is used
+    /// in place of the literal IP fields. Otherwise the address from `get_ip` is used, so
+    /// IPv6 is still preferred over IPv4. Either way the port is `host_port`.
+    ///
+    /// # Returns
+    /// * `Ok(SocketAddr)` - The address to connect to.
+    ///
+    /// # Errors
+    /// * `ConfigError::UnresolvedHost` - If `host_name` can't be resolved to any address.
+    /// * `ConfigError::MissingHostIp` - If there is no `host_name` and no IP address either.
+    /// * `ConfigError::InvalidPort` - If `host_port` is not a valid TCP port.
+    ///
+    /// # Example
+    /// ```rust
+    /// use chat_shared::Config;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let config = Config {
+    ///     host_name: Some(String::from("localhost")),
+    ///     host_port: 7171,
+    ///     ..Config::default()
+    /// };
+    ///
+    /// let address = config.server_address().await.unwrap();
+    /// assert!(address.ip().is_loopback());
+    /// assert_eq!(address.port(), 7171);
+    /// # }
+    /// ```
+    pub async fn server_address(&self) -> Result<SocketAddr, ConfigError> {
+        let port =
+            u16::try_from(self.host_port).map_err(|_| ConfigError::InvalidPort(self.host_port))?;
+        let Some(host) = &self.host_name else {
+            let ip: IpAddr = match (self.host_ipv6, self.host_ipv4) {
+                (Some(ip), _) => ip.into(),
+                (None, Some(ip)) => ip.into(),
+                (None, None) => return Err(ConfigError::MissingHostIp),
+            };
+            return Ok(SocketAddr::new(ip, port));
+        };
+
+        let unresolved = |reason: String| ConfigError::UnresolvedHost(host.clone(), reason);
+        lookup_host((host.as_str(), port))
+            .await
+            .map_err(|e| unresolved(e.to_string()))?
+            .next()
+            .ok_or_else(|| unresolved(String::from("no addresses found")))
+    }
+
+    /// Returns a copy of the config that is safe to show to someone without shell access.
+    ///
+    /// # Returns
+    /// The same settings, except that where the TLS certificates and key live is replaced
+    /// with `<redacted>`. Paths that are not set stay `None`, so it is still clear which
+    /// ones are configured.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_shared::{Config, TlsConfig};
+    /// use std::path::PathBuf;
+    ///
+    /// let mut config = Config::default();
+    /// config.tls = Some(TlsConfig {
+    ///     cert_path: Some(PathBuf::from("/etc/chat/cert.pem")),
+    ///     key_path: Some(PathBuf::from("/etc/chat/key.pem")),
+    ///     ca_path: None,
+    ///     server_name: None,
+    ///     verify: true,
+    ///     client_ca_path: None,
+    ///     client_cert_path: None,
+    ///     client_key_path: Some(PathBuf::from("/etc/chat/client.key")),
+    /// });
+    ///
+    /// let shown = ron::to_string(&config.redacted()).unwrap();
+    /// assert!(!shown.contains("/etc/chat"));
+    /// assert!(shown.contains("<redacted>"));
+    /// assert!(shown.contains(&format!("host_port:{}", config.host_port)));
+    /// ```
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        if let Some(tls) = config.tls.as_mut() {
+            let paths = [
+                &mut tls.cert_path,
+                &mut tls.key_path,
+                &mut tls.ca_path,
+                &mut tls.client_ca_path,
+                &mut tls.client_cert_path,
+                &mut tls.client_key_path,
+            ];
+            for path in paths {
+                if path.is_some() {
+                    *path = Some(PathBuf::from("<redacted>"));
+                }
+            }
+        }
+        config
+    }
 }
@@ -1,36 +1,97 @@
-use crate::Client;
+use crate::{Client, frame};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
-#[derive(Serialize, Deserialize)]
+// Clients and servers from different builds have to understand each other,
+// so every field added after the first release needs #[serde(default)].
+// Unknown fields are skipped when reading, so never deny them. A kind this
+// build doesn't know reads as MessageKind::Unknown, but builds from before
+// that fallback fail to read a message of any kind newer than they are.
+//
+// The server doesn't trust what a client puts in a message. Only content,
+// kind and channel are taken from the client, and only the Message, Command
+// and Pong kinds are accepted from it. A direct message is resolved to the
+// connected client with that id. Everything else is set by the server when
+// it relays the message, whatever the client sent
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Message {
     pub address: String,
     pub content: Vec<u8>,
     pub channel: Destination,
     pub kind: MessageKind,
+    // Assigned by the server when it accepts the message. Ids only grow while
+    // the server is running and start over when it restarts
+    #[serde(default)]
+    pub id: Option<u64>,
+    // Display name of the author, filled in by the server when relaying
+    #[serde(default)]
+    pub author: Option<String>,
+    // Id of the author's Client, filled in by the server when relaying
+    #[serde(default)]
+    pub author_id: Option<String>,
+    // Id of the message this one replies to, if it is part of a thread
+    #[serde(default)]
+    pub in_reply_to: Option<u64>,
+    // When the message was created. The server resets it when it accepts a
+    // message, so every client sees the same time. Older builds don't send
+    // one, so those messages are stamped with when they were read
+    #[serde(default = "Utc::now")]
+    pub timestamp: DateTime<Utc>,
+    // CRC32 of the content, set by senders that have frame_checksums on.
+    // Reading a message whose content doesn't match it fails, so corruption
+    // is caught rather than shown
+    #[serde(default)]
+    pub checksum: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Destination {
     Global,
     Channel(Channel),
     Direct(Client),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MessageKind {
     Message,
+    // Something the author did rather than said, as typed with :me. Shown
+    // as "* alice waves" but otherwise sent just like a Message
+    Action,
     Command,
     ServerBroadcast,
+    Ack,
+    // Sent by the server to check the client is still there. The client
+    // answers with a Pong
+    Ping,
+    Pong,
+    // Any kind added by a newer build than this one. Such messages are
+    // read rather than failing the connection, then ignored
+    #[serde(other)]
+    Unknown,
 }
 
 #[allow(dead_code)]
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Channel {
     id: String,
     display_name: String,
 }
 
+impl Channel {
+    // Channels are known by name, so the name doubles as the id
+    pub fn new(name: String) -> Self {
+        Self {
+            id: name.clone(),
+            display_name: name,
+        }
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.display_name
+    }
+}
+
 impl Message {
     pub fn new(author: Arc<Client>) -> Self {
         Self {
@@ -38,6 +99,12 @@ impl Message {
             content: Vec::new(),
             channel: Destination::Global,
             kind: MessageKind::Message,
+            id: None,
+            author: None,
+            author_id: None,
+            in_reply_to: None,
+            timestamp: Utc::now(),
+            checksum: None,
         }
     }
 
@@ -47,9 +114,36 @@ impl Message {
             content: message.into_bytes(),
             channel: Destination::Global,
             kind,
+            id: None,
+            author: None,
+            author_id: None,
+            in_reply_to: None,
+            timestamp: Utc::now(),
+            checksum: None,
+        }
+    }
+
+    // A message originating from the server itself rather than a user
+    pub fn from_server(message: String, kind: MessageKind) -> Self {
+        Self {
+            address: String::new(),
+            content: message.into_bytes(),
+            channel: Destination::Global,
+            kind,
+            id: None,
+            author: None,
+            author_id: None,
+            in_reply_to: None,
+            timestamp: Utc::now(),
+            checksum: None,
         }
     }
 
+    // Fill in the checksum of the content as it is now, just before sending
+    pub fn seal(&mut self) {
+        self.checksum = Some(frame::crc32(&self.content));
+    }
+
     pub fn as_string(&self) -> String {
         // FIX ME: This can consume, remove clone later
         String::from_utf8(self.content.clone()).unwrap_or_else(|_| String::new())
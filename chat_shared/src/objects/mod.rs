@@ -1,8 +1,9 @@
+pub mod command;
 pub mod config;
 pub mod message;
 pub mod user;
 
-pub use config::Config;
+pub use command::Command;
+pub use config::{Config, ConfigFormat, DisplayFallback, IgnoreMode, LogFormat, TlsConfig};
 pub use message::Message;
 pub use user::*;
-
@@ -1,16 +1,39 @@
+use crate::{Config, DisplayFallback, NickError, token_bucket::TokenBucket};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Arc, atomic::AtomicU64},
+    time::Instant,
+};
+use tokio::io::{self, AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify, mpsc::Sender};
 use uuid::Uuid;
 
+/// Anything a `User` can be connected over, such as a plain `TcpStream` or a TLS stream
+/// wrapping one. It is implemented for every type that qualifies.
+pub trait Connection: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Connection for T {}
+
+/// A `Connection` whose type has been erased, so plain and TLS users can be handled alike.
+pub type BoxedConnection = Box<dyn Connection>;
+
+/// The symbols allowed in a nickname, on top of letters and digits.
+pub const NICK_SYMBOLS: &str = "_-.[]";
+
 /// Represents a user in a networked system, containing information related to their connection,
 /// identifier, and activity status.
 ///
 /// # Fields
-/// - `socket`:
-///   An optional `TcpStream` that represents the user's network connection.
-///   If `None`, the user is not currently connected.
+/// - `reader`:
+///   A `Mutex`-protected optional read half of the user's `Connection`.
+///   The task reading from the user takes it out, so it is `None` while being read from.
+/// - `writer`:
+///   A `Mutex`-protected optional write half of the user's `Connection`.
+///   The lock keeps writes from different tasks from interleaving.
 /// - `nickname`:
 ///   A `Mutex`-protected optional `String` that contains the nickname/identifier of the user.
 ///   The mutex allows safe concurrent access and modification of this field across threads.
@@ -21,11 +44,76 @@ use uuid::Uuid;
 ///   A `Mutex`-protected `bool` indicating whether the user is currently active.
 ///   This field can be safely updated from multiple threads and is used to track
 ///   whether the user is still participating in the system.
+/// - `role`:
+///   A `Mutex`-protected `Role` describing what the user is allowed to do on the server.
+/// - `connected_at`:
+///   A `Mutex`-protected time the session began, used to report how long it has lasted.
+///   A session that is resumed after reconnecting keeps the time it first began.
+/// - `outbox`:
+///   A `Mutex`-protected optional `Sender` feeding the task that writes to this user's socket.
+///   If `None`, nothing is writing on behalf of the user, or the user is being disconnected.
+/// - `ignored`:
+///   A `Mutex`-protected set of `Client` ids whose messages the server should not send to this user.
+///   Only used when the server enforces ignores.
+/// - `disconnect`:
+///   A `Notify` used to wake the task reading from the user so it notices `is_active`
+///   has been set to `false`, even if the user has gone quiet.
+/// - `has_sent_message`:
+///   A `Mutex`-protected `bool` that is `true` once the user has sent their first chat message.
+/// - `last_commands`:
+///   A `Mutex`-protected map from command name to when the user last used it, for cooldowns.
+/// - `missed_pings`:
+///   A `Mutex`-protected count of the pings sent since the user was last heard from.
+/// - `message_limit`:
+///   A `Mutex`-protected optional `TokenBucket` limiting how fast the user may send messages.
+///   If `None`, the server hasn't needed to limit them yet.
+/// - `limit_warned_at`:
+///   A `Mutex`-protected optional `Instant` the user was last told they are sending too fast.
+/// - `session_token`:
+///   A random token identifying this connection's session. A client that reconnects can hand
+///   it back to pick up where it left off.
+/// - `announced`:
+///   A `Mutex`-protected `bool` that is `true` once everyone knows the user is here, either
+///   because their arrival was announced or because they resumed an earlier session.
+/// - `frames_received` and `frames_sent`:
+///   Counts of the frames read from and written to the user's connection, kept as atomics so
+///   counting costs next to nothing. They help tell where a lost message went missing.
+/// - `disconnect_reason`:
+///   A `Mutex`-protected optional `DisconnectReason`, set by whoever ends the session
+///   through `User::deactivate`. If `None`, nobody has asked for the user to go.
+/// - `display_fallback`:
+///   A `Mutex`-protected `DisplayFallback` deciding what the user is shown as until they set
+///   a nickname.
+/// - `live_from`:
+///   An `AtomicU64` holding the id of the first relayed message the user is sent as it happens.
+///   Anything older reached them in the history replay when they joined, if at all.
+/// - `certified_name`:
+///   A `Mutex`-protected optional name the user's TLS client certificate is issued for. If set,
+///   it is the only nickname the user may go by.
 pub struct User {
-    pub socket: Option<TcpStream>,
+    pub reader: Mutex<Option<ReadHalf<BoxedConnection>>>,
+    pub writer: Mutex<Option<WriteHalf<BoxedConnection>>>,
     pub is_active: Mutex<bool>,
     pub client: Arc<Client>,
     pub nick_name: Mutex<Option<String>>,
+    pub role: Mutex<Role>,
+    pub connected_at: Mutex<DateTime<Utc>>,
+    pub outbox: Mutex<Option<Sender<Vec<u8>>>>,
+    pub ignored: Mutex<HashSet<String>>,
+    pub disconnect: Notify,
+    pub has_sent_message: Mutex<bool>,
+    pub last_commands: Mutex<HashMap<String, Instant>>,
+    pub missed_pings: Mutex<u32>,
+    pub message_limit: Mutex<Option<TokenBucket>>,
+    pub limit_warned_at: Mutex<Option<Instant>>,
+    pub session_token: String,
+    pub announced: Mutex<bool>,
+    pub frames_received: AtomicU64,
+    pub frames_sent: AtomicU64,
+    pub disconnect_reason: Mutex<Option<DisconnectReason>>,
+    pub display_fallback: Mutex<DisplayFallback>,
+    pub live_from: AtomicU64,
+    pub certified_name: Mutex<Option<String>>,
 }
 
 impl User {
@@ -39,36 +127,105 @@ impl User {
     ///
     /// # Returns
     /// A new instance of the struct with the following initialized fields:
-    /// * `socket` - Set as `Some(tcp_stream)`, holding the associated TCP connection.
+    /// * `reader` and `writer` - The two halves of `tcp_stream`, each wrapped in `Some`.
     /// * `nickname` - A `Mutex`-wrapped `Option` initialized to `None`, representing the optional user nickname.
     /// * `address` - The provided address if available, or the local address from the `TcpStream` converted to a string.
     /// * `is_active` - A `Mutex`-locked boolean value initialized to `true`, indicating that the connection is active.
+    /// * `outbox` - A `Mutex`-wrapped `Option` initialized to `None`, filled in once a writer is attached.
+    /// * `ignored` - A `Mutex`-wrapped empty set, as nobody is ignored yet.
+    /// * `disconnect` - A fresh `Notify` with no waiters.
+    /// * `has_sent_message` - A `Mutex`-locked boolean initialized to `false`.
+    /// * `last_commands` - A `Mutex`-wrapped empty map, as no command has been used yet.
+    /// * `missed_pings` - A `Mutex`-locked count initialized to `0`.
+    /// * `message_limit` and `limit_warned_at` - `Mutex`-wrapped `Option`s initialized to `None`.
+    /// * `session_token` - A fresh random token.
+    /// * `announced` - A `Mutex`-locked boolean initialized to `false`.
+    /// * `frames_received` and `frames_sent` - Counters initialized to `0`.
+    /// * `disconnect_reason` - A `Mutex`-wrapped `Option` initialized to `None`.
+    /// * `display_fallback` - A `Mutex`-wrapped `DisplayFallback::Address`.
+    /// * `live_from` - `0`, so every message is relayed to the user until they join the roster.
+    /// * `certified_name` - `None`, until the server learns the name from a client certificate.
     ///
     /// # Panics
     /// This function will panic if the call to `tcp_stream.local_addr()` fails, as `.unwrap()` is used
     /// to handle the result. Ensure that the `TcpStream` is correctly initialized and valid before calling this method.
     ///
     /// # Example
-    /// ```rust
-    /// use std::net::TcpStream;
-    /// use your_module::YourStruct; // Replace with your actual module and structure name.
+    /// ```no_run
+    /// # async fn example() {
+    /// use chat_shared::User;
+    /// use tokio::net::TcpStream;
     ///
-    /// let stream = TcpStream::connect("127.0.0.1:8080").unwrap();
-    /// let instance = YourStruct::from(stream, None);
+    /// let stream = TcpStream::connect("127.0.0.1:8080").await.unwrap();
+    /// let instance = User::from(stream, None);
     ///
     /// // Use the created instance...
+    /// # }
     /// ```
     pub fn from(tcp_stream: TcpStream, address: Option<String>) -> Self {
         let address = match address {
             Some(address) => address,
             None => tcp_stream.local_addr().unwrap().to_string(),
         };
+        Self::over(Box::new(tcp_stream), address)
+    }
+
+    /// Constructs a new instance of the struct over any `Connection`, for example a TLS stream.
+    ///
+    /// # Arguments
+    /// * `connection` - The connection to read from and write to.
+    /// * `address` - The address associated with the connection.
+    ///
+    /// # Returns
+    /// A new instance initialized the same way as by `User::from`.
+    ///
+    /// # Example
+    /// Nothing here needs a network, so a user can be served over an in-memory pipe:
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// use chat_shared::{Message, User, frame, message::MessageKind};
+    /// use std::sync::Arc;
+    /// use tokio::io::duplex;
+    ///
+    /// let (mut near, far) = duplex(1024);
+    /// let user = Arc::new(User::over(Box::new(far), String::from("memory")));
+    ///
+    /// let sent = Message::from_string(Arc::clone(&user.client), "hello".into(), MessageKind::Message);
+    /// frame::write_frame(&mut near, &sent, 1024).await.unwrap();
+    ///
+    /// let mut reader = user.reader.lock().await.take().unwrap();
+    /// let received = frame::read_frame(&mut reader, 1024).await.unwrap();
+    /// assert_eq!(received.as_string(), "hello");
+    /// # }
+    /// ```
+    pub fn over(connection: BoxedConnection, address: String) -> Self {
+        let (reader, writer) = io::split(connection);
 
         Self {
-            socket: Some(tcp_stream),
+            reader: Mutex::new(Some(reader)),
+            writer: Mutex::new(Some(writer)),
             is_active: Mutex::new(true),
             client: Arc::new(Client::new(address)),
             nick_name: Mutex::new(None),
+            role: Mutex::new(Role::Member),
+            connected_at: Mutex::new(Utc::now()),
+            outbox: Mutex::new(None),
+            ignored: Mutex::new(HashSet::new()),
+            disconnect: Notify::new(),
+            has_sent_message: Mutex::new(false),
+            last_commands: Mutex::new(HashMap::new()),
+            missed_pings: Mutex::new(0),
+            message_limit: Mutex::new(None),
+            limit_warned_at: Mutex::new(None),
+            session_token: Uuid::new_v4().to_string(),
+            announced: Mutex::new(false),
+            frames_received: AtomicU64::new(0),
+            frames_sent: AtomicU64::new(0),
+            disconnect_reason: Mutex::new(None),
+            display_fallback: Mutex::new(DisplayFallback::Address),
+            live_from: AtomicU64::new(0),
+            certified_name: Mutex::new(None),
         }
     }
 
@@ -76,21 +233,24 @@ impl User {
     ///
     /// This asynchronous function checks if the user has a nickname assigned.
     /// - If a nickname is set, it returns the nickname.
-    /// - If no nickname is available, it falls back to the user's address.
+    /// - If no nickname is available, it falls back to what `display_fallback` says, which
+    ///   is the user's address unless it has been changed.
     ///
     /// # Returns
     /// A `String` representing the user's display name:
     /// - The nickname if it's set.
-    /// - Otherwise, the address.
+    /// - Otherwise, the name given by `display_fallback`.
     ///
     /// # Concurrency
     /// This function uses an asynchronous lock to safely access the user's nickname
     /// in a potentially concurrent context.
     ///
     /// # Example
-    /// ```rust
+    /// ```no_run
+    /// # async fn example(user: chat_shared::User) {
     /// let display_name = user.get_display_name().await;
     /// println!("User's display name: {}", display_name);
+    /// # }
     /// ```
     ///
     /// # Dependencies
@@ -102,19 +262,319 @@ impl User {
     /// Ensure that `self.nickname` and `self.address` are properly initialized.
     ///
     /// # Errors
-    /// This function does not return errors as it defaults to `self.address`
+    /// This function does not return errors as it falls back to `display_fallback`
     /// if the nickname is absent.
     pub async fn get_display_name(&self) -> String {
         let nickname = self.nick_name.lock().await;
 
         match &*nickname {
             Some(nick_name) => nick_name.clone(),
-            None => self.client.address.clone(),
+            None => self.display_fallback.lock().await.name_for(&self.client),
+        }
+    }
+
+    /// Checks that a nickname is one users may go by.
+    ///
+    /// # Arguments
+    /// * `name` - The nickname asked for.
+    /// * `config` - The config giving `nick_min_len` and `nick_max_len`.
+    ///
+    /// # Returns
+    /// * `Ok(())` - If the nickname can be used.
+    /// * `Err(NickError)` - The rule it breaks, worded so it can be shown to the user.
+    ///
+    /// # Behavior
+    /// - Length is counted in characters, not bytes, and must be between `nick_min_len` and
+    ///   `nick_max_len`, inclusive.
+    /// - Only letters, digits and the symbols in `NICK_SYMBOLS` are allowed.
+    /// - Control characters and surrounding whitespace are reported as such, since they are
+    ///   the easiest to type by accident.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_shared::{Config, NickError, User};
+    ///
+    /// let config = Config::default();
+    /// assert_eq!(User::validate_nickname("alice", &config), Ok(()));
+    /// assert_eq!(User::validate_nickname("[bob]_2", &config), Ok(()));
+    /// assert_eq!(User::validate_nickname("", &config), Err(NickError::TooShort { min: 1 }));
+    /// assert_eq!(
+    ///     User::validate_nickname(&"a".repeat(33), &config),
+    ///     Err(NickError::TooLong { max: 32 })
+    /// );
+    /// assert_eq!(
+    ///     User::validate_nickname("al\u{1b}ce", &config),
+    ///     Err(NickError::ControlCharacter)
+    /// );
+    /// assert_eq!(
+    ///     User::validate_nickname(" alice", &config),
+    ///     Err(NickError::SurroundingWhitespace)
+    /// );
+    /// assert_eq!(
+    ///     User::validate_nickname("al ice", &config),
+    ///     Err(NickError::InvalidCharacter(' '))
+    /// );
+    /// ```
+    pub fn validate_nickname(name: &str, config: &Config) -> Result<(), NickError> {
+        let length = name.chars().count();
+        if length < config.nick_min_len {
+            return Err(NickError::TooShort {
+                min: config.nick_min_len,
+            });
+        }
+        if length > config.nick_max_len {
+            return Err(NickError::TooLong {
+                max: config.nick_max_len,
+            });
+        }
+        if name.chars().any(char::is_control) {
+            return Err(NickError::ControlCharacter);
+        }
+        if name.trim() != name {
+            return Err(NickError::SurroundingWhitespace);
+        }
+        match name
+            .chars()
+            .find(|c| !c.is_alphanumeric() && !NICK_SYMBOLS.contains(*c))
+        {
+            Some(c) => Err(NickError::InvalidCharacter(c)),
+            None => Ok(()),
+        }
+    }
+
+    /// Ends the user's session, waking the task reading from them so it notices.
+    ///
+    /// # Arguments
+    /// * `reason` - Why the session is ending. If a reason was already given, it is kept,
+    ///   as that is what really ended the session.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use chat_shared::{DisconnectReason, User};
+    ///
+    /// let (connection, _) = tokio::io::duplex(64);
+    /// let user = User::over(Box::new(connection), String::from("127.0.0.1:50312"));
+    ///
+    /// user.deactivate(DisconnectReason::Kicked).await;
+    /// user.deactivate(DisconnectReason::Closed).await;
+    /// assert!(!*user.is_active.lock().await);
+    /// assert_eq!(*user.disconnect_reason.lock().await, Some(DisconnectReason::Kicked));
+    /// # }
+    /// ```
+    pub async fn deactivate(&self, reason: DisconnectReason) {
+        self.disconnect_reason.lock().await.get_or_insert(reason);
+        *self.is_active.lock().await = false;
+        self.disconnect.notify_one();
+    }
+
+    /// Works out how long the user's session has lasted.
+    ///
+    /// # Arguments
+    /// * `now` - The time to measure up to, normally `Utc::now()`. Passing it in keeps the
+    ///   answer the same however long the caller takes to get here.
+    ///
+    /// # Returns
+    /// The whole seconds from `connected_at` to `now`, or `0` if the clock has gone back
+    /// since the session began.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use chat_shared::User;
+    /// use chrono::{TimeDelta, Utc};
+    ///
+    /// let (connection, _) = tokio::io::duplex(64);
+    /// let user = User::over(Box::new(connection), String::from("127.0.0.1:50312"));
+    /// let began = Utc::now();
+    /// *user.connected_at.lock().await = began;
+    ///
+    /// assert_eq!(user.session_secs(began + TimeDelta::seconds(3725)).await, 3725);
+    /// assert_eq!(user.session_secs(began - TimeDelta::seconds(5)).await, 0);
+    /// # }
+    /// ```
+    pub async fn session_secs(&self, now: DateTime<Utc>) -> u64 {
+        let began = *self.connected_at.lock().await;
+        (now - began).num_seconds().try_into().unwrap_or(0)
+    }
+
+    /// Builds a public snapshot of the user that is safe to send to other users.
+    ///
+    /// # Arguments
+    /// * `reveal_address` - Whether the user's raw address should be included. This should
+    ///   only be `true` when the snapshot is going to an admin.
+    ///
+    /// # Returns
+    /// A `UserInfo` holding the display name, presence, role, and connection length of the user.
+    ///
+    /// # Example
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use chat_shared::{DisconnectReason, Presence, Role, User};
+    ///
+    /// let (connection, _) = tokio::io::duplex(64);
+    /// let user = User::over(Box::new(connection), String::from("127.0.0.1:50312"));
+    /// *user.nick_name.lock().await = Some(String::from("alice"));
+    ///
+    /// let info = user.info(false).await;
+    /// assert_eq!(info.display_name, "alice");
+    /// assert_eq!(info.presence, Presence::Online);
+    /// assert_eq!(info.role, Role::Member);
+    /// assert_eq!(info.address, None);
+    ///
+    /// user.deactivate(DisconnectReason::Quit).await;
+    /// let info = user.info(true).await;
+    /// assert_eq!(info.presence, Presence::Offline);
+    /// assert_eq!(info.address.as_deref(), Some("127.0.0.1:50312"));
+    /// # }
+    /// ```
+    pub async fn info(&self, reveal_address: bool) -> UserInfo {
+        let presence = match *self.is_active.lock().await {
+            true => Presence::Online,
+            false => Presence::Offline,
+        };
+
+        UserInfo {
+            display_name: self.get_display_name().await,
+            presence,
+            role: *self.role.lock().await,
+            connected_secs: self.session_secs(Utc::now()).await,
+            address: reveal_address.then(|| self.client.address.clone()),
+        }
+    }
+}
+
+/// The permission level of a user on the server.
+///
+/// # Variants
+/// - `Member`
+///   A regular chat participant.
+/// - `Admin`
+///   An operator that may use moderation commands and see other users' addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Role {
+    Member,
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Role::Member => write!(f, "member"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// Why a user's session came to an end.
+///
+/// # Variants
+/// - `Quit`
+///   The user asked to leave with `:quit`.
+/// - `Closed`
+///   The client closed the connection without saying anything.
+/// - `Kicked`
+///   An admin removed the user with `:kick`.
+/// - `Unresponsive`
+///   The user stopped answering the server's pings.
+/// - `ProtocolError`
+///   The client sent something that breaks the protocol, such as an oversized or unreadable frame.
+/// - `NetworkError`
+///   Reading from or writing to the connection failed.
+/// - `ServerShutdown`
+///   The server is shutting down.
+/// - `Resumed`
+///   The session was resumed on a new connection before this one was noticed to be gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisconnectReason {
+    Quit,
+    Closed,
+    Kicked,
+    Unresponsive,
+    ProtocolError,
+    NetworkError,
+    ServerShutdown,
+    Resumed,
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DisconnectReason::Quit => write!(f, "quit"),
+            DisconnectReason::Closed => write!(f, "connection closed"),
+            DisconnectReason::Kicked => write!(f, "kicked"),
+            DisconnectReason::Unresponsive => write!(f, "stopped responding"),
+            DisconnectReason::ProtocolError => write!(f, "protocol error"),
+            DisconnectReason::NetworkError => write!(f, "network error"),
+            DisconnectReason::ServerShutdown => write!(f, "server shutting down"),
+            DisconnectReason::Resumed => write!(f, "resumed elsewhere"),
+        }
+    }
+}
+
+/// Whether a user is currently participating in the chat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Presence {
+    Online,
+    Offline,
+}
+
+impl fmt::Display for Presence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Presence::Online => write!(f, "online"),
+            Presence::Offline => write!(f, "offline"),
         }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// A public view of a `User`, built by `User::info`.
+///
+/// The `address` is only populated when the requester is allowed to see it, so this
+/// struct can be handed to any user without leaking raw IP addresses.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub display_name: String,
+    pub presence: Presence,
+    pub role: Role,
+    pub connected_secs: u64,
+    pub address: Option<String>,
+}
+
+impl fmt::Display for UserInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, {}) connected for {}",
+            self.display_name,
+            self.presence,
+            self.role,
+            format_duration(self.connected_secs)
+        )?;
+
+        if let Some(address) = &self.address {
+            write!(f, " from {}", address)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats a length of time in seconds the way session lengths are shown to users.
+///
+/// # Example
+/// ```
+/// assert_eq!(chat_shared::format_duration(3725), "1h 2m 5s");
+/// assert_eq!(chat_shared::format_duration(0), "0h 0m 0s");
+/// ```
+pub fn format_duration(secs: u64) -> String {
+    format!("{}h {}m {}s", secs / 3600, (secs % 3600) / 60, secs % 60)
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Client {
     pub id: String,
     pub address: String,
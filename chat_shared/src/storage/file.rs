@@ -0,0 +1,215 @@
+use super::{MemberRecord, Storage};
+use crate::Message;
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::task;
+
+// Each record starts with the message id, the time it was accepted in
+// milliseconds since the epoch and the length of the message that follows
+const HEADER_LEN: usize = 8 + 8 + 4;
+
+/// A `Storage` that keeps messages in an append-only log on disk and member records beside it.
+///
+/// # Behavior
+/// - Each message is appended to the log as a compact binary record: its id, the time it was
+///   accepted in milliseconds since the epoch, and the length prefixed RON serialized message.
+/// - When the log would grow past `max_bytes` it is moved aside to `<path>.1`, replacing the
+///   older one, and a fresh log is started.
+/// - A record cut short by a crash ends the reading of that file rather than failing it.
+/// - Member records are kept as RON in `<path>.members`, rewritten whenever one changes.
+/// - The files are read and written on tokio's blocking threads, so the server never waits on
+///   the disk while it relays messages.
+pub struct FileStorage {
+    files: Arc<Files>,
+}
+
+// Everything the blocking threads need to get at
+struct Files {
+    path: PathBuf,
+    log: Mutex<Log>,
+    members: Mutex<HashMap<String, MemberRecord>>,
+    max_bytes: u64,
+}
+
+// The log file currently being appended to
+struct Log {
+    file: File,
+    size: u64,
+}
+
+impl FileStorage {
+    /// Opens the log at `path`, creating it if needed, and loads any member records beside it.
+    ///
+    /// # Arguments
+    /// * `path` - Where the message log is kept.
+    /// * `max_bytes` - How large the log may grow before it is rotated.
+    ///
+    /// # Errors
+    /// * Any `io::Error` from opening the log or reading the member records.
+    pub fn open(path: &Path, max_bytes: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        let members = match fs::read_to_string(members_path(path)) {
+            Ok(contents) => {
+                ron::from_str(&contents).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        let files = Files {
+            path: path.to_path_buf(),
+            log: Mutex::new(Log { file, size }),
+            members: Mutex::new(members),
+            max_bytes,
+        };
+        Ok(Self {
+            files: Arc::new(files),
+        })
+    }
+}
+
+impl Files {
+    // Move the current log aside and start an empty one
+    fn rotate(&self, log: &mut Log) -> io::Result<()> {
+        fs::rename(&self.path, rotated_path(&self.path))?;
+        log.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        log.size = 0;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for FileStorage {
+    async fn append_message(&self, message: &Message) -> io::Result<()> {
+        let id = message
+            .id
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "message has no id"))?;
+        let payload = ron::to_string(message)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?
+            .into_bytes();
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis() as u64)
+            .unwrap_or_default();
+
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+        record.extend_from_slice(&id.to_le_bytes());
+        record.extend_from_slice(&timestamp_ms.to_le_bytes());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&payload);
+
+        let files = Arc::clone(&self.files);
+        blocking(move || {
+            let mut log = lock(&files.log);
+            if log.size > 0 && log.size + record.len() as u64 > files.max_bytes {
+                files.rotate(&mut log)?;
+            }
+
+            // Write the whole record at once so a crash leaves at most one
+            // partial record at the very end
+            log.file.write_all(&record)?;
+            log.file.flush()?;
+            log.size += record.len() as u64;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn recent_messages(&self, limit: usize) -> io::Result<Vec<Message>> {
+        let files = Arc::clone(&self.files);
+        blocking(move || {
+            // Hold the log so it can't rotate out from under us
+            let _log = lock(&files.log);
+
+            let mut messages = Vec::new();
+            for path in [rotated_path(&files.path), files.path.clone()] {
+                let mut buffer = Vec::new();
+                match File::open(&path) {
+                    Ok(mut file) => file.read_to_end(&mut buffer)?,
+                    Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e),
+                };
+                read_records(&buffer, &mut messages);
+            }
+
+            let skip = messages.len().saturating_sub(limit);
+            Ok(messages.split_off(skip))
+        })
+        .await
+    }
+
+    async fn get_member(&self, nick_name: &str) -> io::Result<Option<MemberRecord>> {
+        Ok(lock(&self.files.members).get(nick_name).cloned())
+    }
+
+    async fn put_member(&self, record: MemberRecord) -> io::Result<()> {
+        let files = Arc::clone(&self.files);
+        blocking(move || {
+            // Kept locked until written, so an older set of records can't
+            // be written over a newer one
+            let mut members = lock(&files.members);
+            members.insert(record.nick_name.clone(), record);
+            let contents =
+                ron::to_string(&*members).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            fs::write(members_path(&files.path), contents)
+        })
+        .await
+    }
+}
+
+// Do some file IO on one of tokio's blocking threads and wait for it
+async fn blocking<T, F>(work: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    task::spawn_blocking(work).await.map_err(io::Error::other)?
+}
+
+// Whatever a thread was doing when it panicked, the files are still
+// worth carrying on with
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn read_records(mut buffer: &[u8], messages: &mut Vec<Message>) {
+    while buffer.len() >= HEADER_LEN {
+        let len = u32::from_le_bytes(buffer[16..20].try_into().unwrap()) as usize;
+        let Some(payload) = buffer.get(HEADER_LEN..HEADER_LEN + len) else {
+            return;
+        };
+
+        // A record we can no longer parse is skipped, not fatal
+        if let Ok(message) = ron::de::from_bytes::<Message>(payload) {
+            messages.push(message);
+        }
+        buffer = &buffer[HEADER_LEN + len..];
+    }
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".1")
+}
+
+fn members_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".members")
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
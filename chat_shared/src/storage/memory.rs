@@ -0,0 +1,51 @@
+use super::{MemberRecord, Storage};
+use crate::Message;
+use async_trait::async_trait;
+use std::{collections::HashMap, io};
+use tokio::sync::Mutex;
+
+/// A `Storage` that keeps member records in memory, so they are forgotten when the server stops.
+///
+/// Messages are not kept at all. The server already holds its recent history in memory, so a
+/// second copy here would only be written and never read, and `recent_messages` always comes
+/// back empty.
+pub struct MemoryStorage {
+    members: Mutex<HashMap<String, MemberRecord>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self {
+            members: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn append_message(&self, _message: &Message) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn recent_messages(&self, _limit: usize) -> io::Result<Vec<Message>> {
+        Ok(Vec::new())
+    }
+
+    async fn get_member(&self, nick_name: &str) -> io::Result<Option<MemberRecord>> {
+        Ok(self.members.lock().await.get(nick_name).cloned())
+    }
+
+    async fn put_member(&self, record: MemberRecord) -> io::Result<()> {
+        self.members
+            .lock()
+            .await
+            .insert(record.nick_name.clone(), record);
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+mod file;
+mod memory;
+
+pub use file::FileStorage;
+pub use memory::MemoryStorage;
+
+use crate::{Message, Role};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// What is remembered about a member once they have left, keyed by their nickname.
+///
+/// # Fields
+/// - `nick_name`: The nickname the member was using.
+/// - `role`: The role the member had on the server.
+/// - `last_seen_secs`: When the member was last connected, in seconds since the Unix epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemberRecord {
+    pub nick_name: String,
+    pub role: Role,
+    pub last_seen_secs: u64,
+}
+
+/// Somewhere the server can keep messages and member records.
+///
+/// The server only talks to this trait, so the backend can be swapped without touching it.
+/// `MemoryStorage` keeps member records in memory and forgets them on restart, while
+/// `FileStorage` keeps messages and member records on disk.
+///
+/// # Methods
+/// - `append_message`: Records a message the server has accepted and given an id. A backend
+///   that doesn't outlive the server may drop it, since the server keeps its own history.
+/// - `recent_messages`: Returns up to `limit` of the most recently recorded messages, oldest first.
+/// - `get_member`: Looks up the record for a nickname, if there is one.
+/// - `put_member`: Stores a record, replacing any earlier one for the same nickname.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn append_message(&self, message: &Message) -> io::Result<()>;
+    async fn recent_messages(&self, limit: usize) -> io::Result<Vec<Message>>;
+    async fn get_member(&self, nick_name: &str) -> io::Result<Option<MemberRecord>>;
+    async fn put_member(&self, record: MemberRecord) -> io::Result<()>;
+}
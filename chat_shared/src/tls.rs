@@ -0,0 +1,250 @@
+// Optional TLS for the connection between the client and the server. What
+// comes out of a handshake is still just a stream of bytes, so everything
+// past this module reads and writes it the same way it does a TcpStream
+use crate::{TlsConfig, TlsError};
+use std::{fmt, path::Path, sync::Arc};
+use tokio_rustls::{
+    TlsAcceptor, TlsConnector,
+    rustls::{
+        ClientConfig, DigitallySignedStruct, Error, RootCertStore, ServerConfig, SignatureScheme,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{self, CryptoProvider, ring},
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime, pem::PemObject},
+        server::WebPkiClientVerifier,
+    },
+};
+use webpki::EndEntityCert;
+
+// The DER tag of an object identifier, and the identifier of an X.509
+// common name, 2.5.4.3, as DER encodes it
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const COMMON_NAME: [u8; 3] = [0x55, 0x04, 0x03];
+
+/// Builds what the server uses to run the TLS handshake on each accepted connection.
+///
+/// # Arguments
+/// * `config` - The `tls` section of the server's config.
+///
+/// # Returns
+/// * `Ok(TlsAcceptor)` - An acceptor presenting the configured certificate, which also requires
+///   a client certificate issued by one of those in `client_ca_path` when it is set.
+/// * `Err(TlsError)` - If a certificate or key is missing, unreadable or unusable.
+///
+/// # Errors
+/// * `TlsError::MissingCertificate` - If `cert_path` or `key_path` is unset.
+/// * `TlsError::ReadFailed` - If any of the files can't be read or holds no PEM data.
+/// * `TlsError::Rejected` - If the key does not go with the certificate, or a certificate in
+///   `client_ca_path` can't be trusted.
+pub fn acceptor(config: &TlsConfig) -> Result<TlsAcceptor, TlsError> {
+    let (Some(cert_path), Some(key_path)) = (&config.cert_path, &config.key_path) else {
+        return Err(TlsError::MissingCertificate);
+    };
+    let certs = read_certs(cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| read_failed(key_path, e))?;
+
+    let builder = ServerConfig::builder();
+    let builder = match &config.client_ca_path {
+        Some(client_ca_path) => {
+            let verifier = WebPkiClientVerifier::builder(Arc::new(trusted(client_ca_path)?))
+                .build()
+                .map_err(|e| TlsError::Rejected(e.to_string()))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let server = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| TlsError::Rejected(e.to_string()))?;
+    Ok(TlsAcceptor::from(Arc::new(server)))
+}
+
+/// Builds what the client uses to run the TLS handshake when it connects.
+///
+/// # Arguments
+/// * `config` - The `tls` section of the client's config.
+///
+/// # Returns
+/// * `Ok(TlsConnector)` - A connector trusting the certificates in `ca_path`, or trusting
+///   anything when `verify` is off, and presenting `client_cert_path` when it is set.
+/// * `Err(TlsError)` - If the certificates to trust or to present are missing, unreadable or
+///   unusable.
+///
+/// # Errors
+/// * `TlsError::MissingCa` - If `verify` is on but `ca_path` is unset.
+/// * `TlsError::MissingClientKey` - If only one of `client_cert_path` and `client_key_path`
+///   is set.
+/// * `TlsError::ReadFailed` - If any of the files can't be read or holds no PEM data.
+/// * `TlsError::Rejected` - If a certificate in `ca_path` can't be trusted, or the client key
+///   does not go with the client certificate.
+pub fn connector(config: &TlsConfig) -> Result<TlsConnector, TlsError> {
+    let builder = ClientConfig::builder();
+    let builder = if config.verify {
+        let ca_path = config.ca_path.as_ref().ok_or(TlsError::MissingCa)?;
+        builder.with_root_certificates(trusted(ca_path)?)
+    } else {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification(ring::default_provider())))
+    };
+    let client = match (&config.client_cert_path, &config.client_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let certs = read_certs(cert_path)?;
+            let key =
+                PrivateKeyDer::from_pem_file(key_path).map_err(|e| read_failed(key_path, e))?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .map_err(|e| TlsError::Rejected(e.to_string()))?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => return Err(TlsError::MissingClientKey),
+    };
+    Ok(TlsConnector::from(Arc::new(client)))
+}
+
+/// Works out who a client certificate says its holder is.
+///
+/// # Arguments
+/// * `cert` - The certificate the client presented, which the handshake has already verified.
+///
+/// # Returns
+/// * `Some(String)` - The first DNS name the certificate is issued for or, if it has none, the
+///   common name in its subject.
+/// * `None` - If the certificate can't be parsed or names nobody.
+pub fn certificate_name(cert: &CertificateDer<'_>) -> Option<String> {
+    let cert = EndEntityCert::try_from(cert).ok()?;
+    if let Some(name) = cert.valid_dns_names().next() {
+        return Some(name.to_string());
+    }
+
+    // The subject is a sequence of sets of (type, value) pairs
+    der_items(cert.subject()).find_map(|(_, set)| {
+        der_items(set).find_map(|(_, pair)| {
+            let mut pair = der_items(pair);
+            let (tag, kind) = pair.next()?;
+            let (_, value) = pair.next()?;
+            if tag != OBJECT_IDENTIFIER || kind != COMMON_NAME {
+                return None;
+            }
+            String::from_utf8(value.to_vec()).ok()
+        })
+    })
+}
+
+/// Works out the name the server's certificate has to be issued for.
+///
+/// # Arguments
+/// * `config` - The `tls` section of the client's config.
+/// * `host` - The host being connected to, used when `server_name` is unset.
+///
+/// # Returns
+/// * `Ok(ServerName)` - The DNS name or IP address to check the certificate against.
+/// * `Err(TlsError::InvalidServerName)` - If it is neither.
+pub fn server_name(config: &TlsConfig, host: &str) -> Result<ServerName<'static>, TlsError> {
+    let name = config.server_name.as_deref().unwrap_or(host);
+    ServerName::try_from(name.to_string()).map_err(|_| TlsError::InvalidServerName(name.into()))
+}
+
+// Each DER tag and value in a run of them, ending early at anything that
+// doesn't parse
+fn der_items(mut bytes: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    std::iter::from_fn(move || {
+        let (&tag, rest) = bytes.split_first()?;
+        let (&first, rest) = rest.split_first()?;
+        // Short lengths fit in the first byte, longer ones follow it in as
+        // many bytes as its low bits say
+        let (len, rest) = if first < 0x80 {
+            (first as usize, rest)
+        } else {
+            let count = (first & 0x7f) as usize;
+            if count > size_of::<usize>() || rest.len() < count {
+                return None;
+            }
+            let len = rest[..count]
+                .iter()
+                .fold(0, |len, &byte| (len << 8) | byte as usize);
+            (len, &rest[count..])
+        };
+        let value = rest.get(..len)?;
+        bytes = &rest[len..];
+        Some((tag, value))
+    })
+}
+
+// Every certificate in a PEM file, as a store of certificates to trust
+fn trusted(path: &Path) -> Result<RootCertStore, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(path)? {
+        roots
+            .add(cert)
+            .map_err(|e| TlsError::Rejected(e.to_string()))?;
+    }
+    Ok(roots)
+}
+
+// Every certificate in a PEM file, which has to hold at least one
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| read_failed(path, e))?;
+    if certs.is_empty() {
+        return Err(read_failed(path, "no certificates found"));
+    }
+    Ok(certs)
+}
+
+fn read_failed(path: &Path, reason: impl fmt::Display) -> TlsError {
+    TlsError::ReadFailed {
+        path: path.to_path_buf(),
+        reason: reason.to_string(),
+    }
+}
+
+// Accepts any certificate for verify: false. Signatures are still checked,
+// so the handshake itself is sound, but nothing says who the server is
+#[derive(Debug)]
+struct NoVerification(CryptoProvider);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+/// A token bucket that limits how many units (bytes, messages, ...) pass through a connection
+/// per second.
+///
+/// # Behavior
+/// - The bucket starts full and refills continuously at `refill_per_sec`, up to `capacity`.
+/// - `take` and `pace` always let the tokens through, putting the bucket into debt if needed,
+///   and say how long the caller should wait for it to be paid off.
+/// - `try_take` only lets the tokens through if they are available, and otherwise leaves the
+///   bucket untouched.
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u64, refill_per_sec: u64) -> Self {
+        Self {
+            capacity: capacity as f64,
+            tokens: capacity as f64,
+            refill_per_sec: refill_per_sec as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    // Add the tokens that have accumulated since the last refill
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Takes the tokens, going into debt if needed.
+    ///
+    /// # Returns
+    /// How long the caller has to wait before the debt is paid off, or zero if there is none.
+    pub fn take(&mut self, amount: u64) -> Duration {
+        self.refill();
+        self.tokens -= amount as f64;
+        if self.tokens >= 0.0 || self.refill_per_sec <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-self.tokens / self.refill_per_sec)
+    }
+
+    /// Takes the tokens and waits until the bucket allows them through.
+    pub async fn pace(&mut self, amount: u64) {
+        let wait = self.take(amount);
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+
+    /// Takes the tokens only if they are all available right now.
+    ///
+    /// # Returns
+    /// `true` if the tokens were taken, or `false` if there weren't enough and none were taken.
+    pub fn try_take(&mut self, amount: u64) -> bool {
+        self.refill();
+        if self.tokens < amount as f64 {
+            return false;
+        }
+        self.tokens -= amount as f64;
+        true
+    }
+}
@@ -0,0 +1,66 @@
+// Environment variables are shared by the whole process, so everything
+// that sets them lives in this one test, in a binary of its own, where
+// nothing else can be reading them at the same time
+use chat_shared::{Config, ConfigError};
+use std::{env, fs, process};
+
+const FILE: &str = r#"(
+    host_ipv4: Some("127.0.0.1"),
+    host_port: 7171,
+    msg_size: 255,
+    prefix: ':',
+)"#;
+
+fn set(name: &str, value: &str) {
+    // SAFETY: no other thread in this test binary touches the environment
+    unsafe { env::set_var(name, value) };
+}
+
+fn unset(name: &str) {
+    // SAFETY: as for set
+    unsafe { env::remove_var(name) };
+}
+
+fn rejected_variable(result: Result<Config, ConfigError>) -> String {
+    match result {
+        Err(ConfigError::InvalidEnvVar(name, _)) => name,
+        other => panic!("expected an invalid variable, got {other:?}"),
+    }
+}
+
+#[test]
+fn environment_overrides_the_config_file() {
+    let path = env::temp_dir().join(format!("chat_config_env_{}.ron", process::id()));
+    fs::write(&path, FILE).expect("could not write the config file");
+    let load = || Config::from_path(Some(&path));
+
+    let config = load().expect("the file alone did not load");
+    assert_eq!(config.host_port, 7171);
+
+    set("CHAT_HOST_PORT", "9090");
+    set("CHAT_PREFIX", "!");
+    set("CHAT_MAX_FRAME_SIZE", "1024");
+    let config = load().expect("the overridden file did not load");
+    assert_eq!(config.host_port, 9090);
+    assert_eq!(config.prefix, '!');
+    assert_eq!(config.max_frame_size, 1024);
+    // Whatever isn't overridden still comes from the file
+    assert_eq!(config.host_ipv4, Some([127, 0, 0, 1].into()));
+
+    set("CHAT_HOST_PORT", "70000");
+    assert_eq!(rejected_variable(load()), "CHAT_HOST_PORT");
+    set("CHAT_HOST_PORT", "9090");
+
+    for size in ["lots", "0"] {
+        set("CHAT_MAX_FRAME_SIZE", size);
+        assert_eq!(rejected_variable(load()), "CHAT_MAX_FRAME_SIZE");
+    }
+    unset("CHAT_MAX_FRAME_SIZE");
+
+    set("CHAT_PREFIX", "!!");
+    assert_eq!(rejected_variable(load()), "CHAT_PREFIX");
+
+    unset("CHAT_HOST_PORT");
+    unset("CHAT_PREFIX");
+    let _ = fs::remove_file(&path);
+}
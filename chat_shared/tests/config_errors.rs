@@ -0,0 +1,55 @@
+use chat_shared::ConfigError;
+use std::{collections::HashSet, path::PathBuf};
+
+// One of every variant. The match makes this fail to build when a variant
+// is added without being listed here
+fn every_variant() -> Vec<ConfigError> {
+    let variants = vec![
+        ConfigError::NoConfigOrFlag,
+        ConfigError::NoValidSettings,
+        ConfigError::ConfigReadFailed(String::from("permission denied")),
+        ConfigError::ConfigParseFailed(String::from("1:1: Expected opening `(`")),
+        ConfigError::MissingHostIp,
+        ConfigError::ZeroFrameSize,
+        ConfigError::InvalidPort(70000),
+        ConfigError::UnresolvedHost(String::from("chat.invalid"), String::from("not found")),
+        ConfigError::InvalidEnvVar(String::from("CHAT_HOST_PORT"), String::from("lots")),
+        ConfigError::UnknownFormat(PathBuf::from("config.toml")),
+    ];
+    for variant in &variants {
+        match variant {
+            ConfigError::NoConfigOrFlag
+            | ConfigError::NoValidSettings
+            | ConfigError::ConfigReadFailed(_)
+            | ConfigError::ConfigParseFailed(_)
+            | ConfigError::MissingHostIp
+            | ConfigError::ZeroFrameSize
+            | ConfigError::InvalidPort(_)
+            | ConfigError::UnresolvedHost(..)
+            | ConfigError::InvalidEnvVar(..)
+            | ConfigError::UnknownFormat(_) => (),
+        }
+    }
+    variants
+}
+
+#[test]
+fn every_error_has_its_own_message() {
+    let mut seen = HashSet::new();
+    for error in every_variant() {
+        let message = error.to_string();
+        assert!(!message.trim().is_empty(), "{error:?} has no message");
+        assert!(
+            seen.insert(message.clone()),
+            "{error:?} shares its message: {message}"
+        );
+    }
+}
+
+#[test]
+fn errors_carry_their_detail() {
+    let read = ConfigError::ConfigReadFailed(String::from("permission denied"));
+    assert!(read.to_string().contains("permission denied"));
+    let parse = ConfigError::ConfigParseFailed(String::from("1:1: Expected opening `(`"));
+    assert!(parse.to_string().contains("1:1"));
+}
@@ -0,0 +1,83 @@
+use chat_shared::{Config, ConfigError, ConfigFormat, LogFormat, TlsConfig};
+use std::{
+    collections::HashMap,
+    env, fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+    process,
+};
+
+// A config with most of its settings moved off their defaults, so a field
+// that doesn't survive a format would show up
+fn sample() -> Config {
+    Config {
+        host_ipv4: Some(Ipv4Addr::new(10, 0, 0, 1)),
+        host_ipv6: Some(Ipv6Addr::LOCALHOST),
+        host_port: 9090,
+        prefix: '!',
+        admin_ips: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+        bandwidth_limit_bps: Some(4096),
+        show_timestamps: true,
+        log_format: LogFormat::Json,
+        command_cooldowns_ms: HashMap::from([
+            (String::from("kick"), 1000),
+            (String::from("name"), 250),
+        ]),
+        tls: Some(TlsConfig {
+            cert_path: Some(PathBuf::from("/etc/chat/cert.pem")),
+            key_path: Some(PathBuf::from("/etc/chat/key.pem")),
+            ca_path: None,
+            server_name: Some(String::from("chat.example.com")),
+            verify: false,
+            client_ca_path: Some(PathBuf::from("/etc/chat/clients.pem")),
+            client_cert_path: None,
+            client_key_path: None,
+        }),
+        host_name: Some(String::from("chat.example.com")),
+        ..Config::default()
+    }
+}
+
+fn temp_path(extension: &str) -> PathBuf {
+    env::temp_dir().join(format!("chat_config_{}.{extension}", process::id()))
+}
+
+#[test]
+fn config_round_trips_through_ron_and_json() {
+    let config = sample();
+    for (format, extension) in [(ConfigFormat::Ron, "ron"), (ConfigFormat::Json, "json")] {
+        let path = temp_path(extension);
+        let written = format
+            .serialize(&config)
+            .expect("could not write the config");
+        fs::write(&path, written).expect("could not save the config");
+
+        let loaded = Config::from_path(Some(&path));
+        let _ = fs::remove_file(&path);
+        assert_eq!(
+            loaded.expect("could not load the config"),
+            config,
+            "{extension}"
+        );
+    }
+}
+
+#[test]
+fn json_and_ron_files_agree() {
+    let config = sample();
+    let ron = ConfigFormat::Ron.serialize(&config).unwrap();
+    let json = ConfigFormat::Json.serialize(&config).unwrap();
+    assert_eq!(
+        ConfigFormat::Ron.parse(&ron).unwrap(),
+        ConfigFormat::Json.parse(&json).unwrap()
+    );
+}
+
+#[test]
+fn unknown_extensions_are_refused() {
+    let path = temp_path("toml");
+    fs::write(&path, "host_port = 9090").expect("could not save the config");
+    let loaded = Config::from_path(Some(&path));
+    let _ = fs::remove_file(&path);
+    assert!(matches!(loaded, Err(ConfigError::UnknownFormat(refused)) if refused == path));
+}
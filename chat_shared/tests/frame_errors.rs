@@ -0,0 +1,55 @@
+use chat_shared::{FrameError, Message, frame, message::MessageKind};
+
+const MAX_FRAME_SIZE: u32 = 1024;
+
+fn payload(message: &Message) -> Vec<u8> {
+    let frame = frame::encode(message, MAX_FRAME_SIZE).expect("message did not encode");
+    frame[frame::HEADER_LEN..].to_vec()
+}
+
+#[test]
+fn empty_payload_is_empty() {
+    assert!(matches!(frame::decode(&[]), Err(FrameError::Empty)));
+}
+
+#[test]
+fn payload_that_is_not_a_message_is_malformed() {
+    assert!(matches!(
+        frame::decode(b"not a message"),
+        Err(FrameError::Malformed(_))
+    ));
+    // Nothing but zeroes isn't empty, it's just not a message
+    assert!(matches!(
+        frame::decode(&[0; 16]),
+        Err(FrameError::Malformed(_))
+    ));
+}
+
+#[test]
+fn damaged_content_is_corrupt() {
+    let mut message = Message::from_server(String::from("hello"), MessageKind::ServerBroadcast);
+    message.seal();
+    message.content[0] ^= 1;
+    assert!(matches!(
+        frame::decode(&payload(&message)),
+        Err(FrameError::Corrupt { .. })
+    ));
+}
+
+#[tokio::test]
+async fn empty_frame_leaves_the_stream_in_step() {
+    let message = Message::from_server(String::from("hello"), MessageKind::ServerBroadcast);
+    let mut bytes = vec![0; frame::HEADER_LEN];
+    bytes.extend(frame::encode(&message, MAX_FRAME_SIZE).expect("message did not encode"));
+
+    let mut reader = frame::FrameReader::new(bytes.as_slice(), MAX_FRAME_SIZE);
+    assert!(matches!(
+        reader.next_message().await,
+        Err(FrameError::Empty)
+    ));
+    let next = reader
+        .next_message()
+        .await
+        .expect("the frame after the empty one was lost");
+    assert_eq!(next.as_string(), "hello");
+}
@@ -0,0 +1,196 @@
+// Feeds arbitrary bytes through the path the server reads untrusted frames
+// on: split off the stream, decoded, turned into text and, for commands,
+// parsed. Whatever arrives, every step has to end in a clean Result rather
+// than a panic. The corpus holds small inputs that once reached the
+// awkward corners of that path, and is replayed on every run
+use chat_shared::{
+    Command, FrameError, Message,
+    frame::{self, FrameReader, HEADER_LEN},
+    message::MessageKind,
+};
+use proptest::prelude::*;
+use std::{
+    fs, io,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, ReadBuf},
+    runtime::{Builder, Runtime},
+};
+
+const MAX_FRAME_SIZE: u32 = 64 * 1024;
+
+// Hands out the bytes a few at a time, so frames arrive split across reads
+// the way they can off a socket
+struct Chunked<'a> {
+    bytes: &'a [u8],
+    chunk: usize,
+}
+
+impl AsyncRead for Chunked<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let len = self.chunk.min(self.bytes.len()).min(buf.remaining());
+        let (head, tail) = self.bytes.split_at(len);
+        buf.put_slice(head);
+        self.bytes = tail;
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn runtime() -> Runtime {
+    Builder::new_current_thread()
+        .build()
+        .expect("could not start a runtime")
+}
+
+// What the server does with a message once it is decoded
+fn handle(message: &Message) {
+    let text = message.as_string();
+    if message.kind == MessageKind::Command {
+        let _ = Command::parse(&text, ':');
+    }
+}
+
+// Read every frame out of the bytes until the stream can't go on
+async fn read_all(bytes: &[u8], chunk: usize) {
+    let mut reader = FrameReader::new(
+        Chunked {
+            bytes,
+            chunk: chunk.max(1),
+        },
+        MAX_FRAME_SIZE,
+    );
+    loop {
+        match reader.next_message().await {
+            Ok(message) => handle(&message),
+            Err(FrameError::Empty | FrameError::Malformed(_) | FrameError::Corrupt { .. }) => {
+                continue;
+            }
+            Err(FrameError::Closed | FrameError::TooLarge { .. }) => break,
+            Err(e) => panic!("unexpected error reading from memory: {e}"),
+        }
+    }
+}
+
+// Read a single frame the way read_frame does, without a FrameReader
+async fn read_one(mut bytes: &[u8]) {
+    if let Ok(message) = frame::read_frame(&mut bytes, MAX_FRAME_SIZE).await {
+        handle(&message);
+    }
+}
+
+fn decode(payload: &[u8]) {
+    if let Ok(message) = frame::decode(payload) {
+        handle(&message);
+    }
+}
+
+// Put a length prefix on the payload, so it gets past the framing
+fn framed(payload: &[u8]) -> Vec<u8> {
+    let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+fn sample_frame(content: Vec<u8>, kind: MessageKind) -> Vec<u8> {
+    let mut message = Message::from_server(String::new(), kind);
+    message.content = content;
+    message.seal();
+    frame::encode(&message, MAX_FRAME_SIZE).expect("sample does not encode")
+}
+
+fn any_kind() -> impl Strategy<Value = MessageKind> {
+    prop_oneof![
+        Just(MessageKind::Message),
+        Just(MessageKind::Action),
+        Just(MessageKind::Command),
+        Just(MessageKind::ServerBroadcast),
+        Just(MessageKind::Ack),
+        Just(MessageKind::Ping),
+        Just(MessageKind::Pong),
+    ]
+}
+
+#[test]
+fn corpus_is_handled_cleanly() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/frame");
+    let entries = fs::read_dir(&corpus).expect("could not read the corpus");
+    let runtime = runtime();
+    let mut count = 0;
+    for entry in entries {
+        let path = entry.expect("could not read a corpus entry").path();
+        let bytes = fs::read(&path).expect("could not read a corpus file");
+        runtime.block_on(async {
+            read_all(&bytes, 1).await;
+            read_all(&bytes, bytes.len()).await;
+            read_one(&bytes).await;
+        });
+        decode(&bytes);
+        decode(bytes.get(HEADER_LEN..).unwrap_or_default());
+        count += 1;
+    }
+    assert!(count > 0, "the corpus at {} is empty", corpus.display());
+
+    // Make sure the corpus still speaks the current format, so the cases
+    // above are failing for the reasons they were written for
+    let valid = fs::read(corpus.join("valid.bin")).expect("the corpus has no valid frame");
+    let message = frame::decode(&valid[HEADER_LEN..]).expect("the valid frame does not decode");
+    assert_eq!(message.as_string(), "hi");
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_bytes_are_handled_cleanly(
+        bytes in prop::collection::vec(any::<u8>(), 0..512),
+        chunk in 1..64usize,
+    ) {
+        decode(&bytes);
+        runtime().block_on(async {
+            read_all(&bytes, chunk).await;
+            read_one(&bytes).await;
+        });
+    }
+
+    #[test]
+    fn arbitrary_payloads_are_handled_cleanly(
+        payload in prop::collection::vec(any::<u8>(), 0..512),
+        chunk in 1..64usize,
+    ) {
+        let bytes = framed(&payload);
+        runtime().block_on(read_all(&bytes, chunk));
+    }
+
+    #[test]
+    fn damaged_frames_are_handled_cleanly(
+        content in prop::collection::vec(any::<u8>(), 0..64),
+        kind in any_kind(),
+        damage in prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 1..8),
+        keep in any::<prop::sample::Index>(),
+    ) {
+        let mut payload = sample_frame(content, kind).split_off(HEADER_LEN);
+        for (at, byte) in damage {
+            let at = at.index(payload.len());
+            payload[at] = byte;
+        }
+        payload.truncate(keep.index(payload.len() + 1));
+        decode(&payload);
+        runtime().block_on(read_all(&framed(&payload), 7));
+    }
+
+    #[test]
+    fn sealed_messages_round_trip(
+        content in prop::collection::vec(any::<u8>(), 0..256),
+        kind in any_kind(),
+    ) {
+        let bytes = sample_frame(content.clone(), kind);
+        let message = frame::decode(&bytes[HEADER_LEN..]).expect("sealed message did not decode");
+        prop_assert_eq!(message.content, content);
+        prop_assert_eq!(message.kind, kind);
+    }
+}
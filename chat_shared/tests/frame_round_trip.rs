@@ -0,0 +1,102 @@
+use chat_shared::{
+    Client, FrameError, Message,
+    frame::{self, FrameReader},
+    message::MessageKind,
+};
+use chrono::{TimeZone, Utc};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    io::{self, AsyncWriteExt},
+    time::timeout,
+};
+
+// The fixed size messages used to be padded to, before they were framed
+const MSG_SIZE: usize = 255;
+const MAX_FRAME_SIZE: u32 = 64 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn message(text: String) -> Message {
+    let client = Arc::new(Client::new(String::from("me")));
+    Message::from_string(client, text, MessageKind::Message)
+}
+
+#[tokio::test]
+async fn messages_of_any_size_come_back_whole() {
+    let (mut writer, reader) = io::duplex(4 * MAX_FRAME_SIZE as usize);
+    let mut reader = FrameReader::new(reader, MAX_FRAME_SIZE);
+
+    for len in [1, MSG_SIZE, 3 * MSG_SIZE] {
+        let text = "x".repeat(len);
+        frame::write_frame(&mut writer, &message(text.clone()), MAX_FRAME_SIZE)
+            .await
+            .expect("the message did not send");
+        let received = timeout(TIMEOUT, reader.next_message())
+            .await
+            .expect("the message never arrived")
+            .expect("the message did not read back");
+        assert_eq!(received.as_string(), text);
+    }
+}
+
+#[tokio::test]
+async fn two_frames_in_one_read_are_both_returned() {
+    let mut both = frame::encode(&message(String::from("first")), MAX_FRAME_SIZE).unwrap();
+    both.extend(frame::encode(&message(String::from("second")), MAX_FRAME_SIZE).unwrap());
+
+    // Written all at once and left open, so the second frame can only come from
+    // what was read along with the first
+    let (mut writer, reader) = io::duplex(both.len());
+    writer.write_all(&both).await.unwrap();
+    let mut reader = FrameReader::new(reader, MAX_FRAME_SIZE);
+
+    for text in ["first", "second"] {
+        let received = timeout(TIMEOUT, reader.next_message())
+            .await
+            .expect("a frame that had arrived was not returned")
+            .expect("the frame did not read back");
+        assert_eq!(received.as_string(), text);
+    }
+}
+
+#[tokio::test]
+async fn a_message_over_the_cap_is_refused_rather_than_cut_short() {
+    let max = (2 * MSG_SIZE) as u32;
+    let big = message("x".repeat(3 * MSG_SIZE));
+
+    let mut sink = Vec::new();
+    let sent = frame::write_frame(&mut sink, &big, max).await;
+    assert!(matches!(sent, Err(FrameError::TooLarge { max: m, .. }) if m == max));
+    assert!(sink.is_empty());
+
+    // A reader with a smaller cap than the sender refuses it the same way
+    let framed = frame::encode(&big, MAX_FRAME_SIZE).unwrap();
+    let mut reader = FrameReader::new(&framed[..], max);
+    assert!(matches!(
+        reader.next_message().await,
+        Err(FrameError::TooLarge { max: m, .. }) if m == max
+    ));
+}
+
+#[test]
+fn quotes_come_back_byte_for_byte() {
+    let text = String::from(r#"she said "hi""#);
+    let encoded = frame::encode(&message(text.clone()), MAX_FRAME_SIZE).unwrap();
+    let decoded = frame::decode(&encoded[frame::HEADER_LEN..]).unwrap();
+    assert_eq!(decoded.content, text.as_bytes());
+    assert_eq!(decoded.as_string(), text);
+}
+
+#[test]
+fn timestamps_survive_serializing() {
+    let mut sent = message(String::from("hello"));
+    sent.timestamp =
+        Utc.with_ymd_and_hms(2024, 5, 1, 12, 34, 56).unwrap() + chrono::Duration::nanoseconds(789);
+
+    let ron = ron::to_string(&sent).expect("the message did not serialize");
+    let received: Message = ron::from_str(&ron).expect("the message did not deserialize");
+    assert_eq!(received.timestamp, sent.timestamp);
+
+    let frame = frame::encode(&sent, MAX_FRAME_SIZE).expect("the message did not encode");
+    let received = frame::decode(&frame[frame::HEADER_LEN..]).expect("the frame did not decode");
+    assert_eq!(received.timestamp, sent.timestamp);
+}
@@ -0,0 +1,67 @@
+// Messages as builds older and newer than this one would send them. Each
+// has to read without failing, so a client and server of different ages
+// can stay connected
+use chat_shared::{
+    Message,
+    frame::{self, FrameReader, HEADER_LEN},
+    message::MessageKind,
+};
+
+const MAX_FRAME_SIZE: u32 = 64 * 1024;
+
+fn payload(message: &Message) -> String {
+    let frame = frame::encode(message, MAX_FRAME_SIZE).expect("message did not encode");
+    String::from_utf8(frame[HEADER_LEN..].to_vec()).expect("RON is text")
+}
+
+fn framed(payload: &str) -> Vec<u8> {
+    let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(payload.as_bytes());
+    frame
+}
+
+fn hello() -> Message {
+    Message::from_server(String::from("hello"), MessageKind::ServerBroadcast)
+}
+
+#[test]
+fn a_message_from_a_newer_build_still_reads() {
+    // A kind and a field this build has never heard of
+    let newer = payload(&hello())
+        .replacen("kind:ServerBroadcast", "kind:Shout", 1)
+        .replacen('(', "(mood:Some(\"cheerful\"),", 1);
+    assert!(newer.contains("kind:Shout"), "unexpected encoding: {newer}");
+
+    let message = frame::decode(newer.as_bytes()).expect("a newer message did not read");
+    assert_eq!(message.kind, MessageKind::Unknown);
+    assert_eq!(message.as_string(), "hello");
+}
+
+#[test]
+fn a_message_from_an_older_build_still_reads() {
+    // Only what the first release sent, without any field added since
+    let older = r#"(address:"127.0.0.1:5000",content:[104,105],channel:Global,kind:Message)"#;
+
+    let message = frame::decode(older.as_bytes()).expect("an older message did not read");
+    assert_eq!(message.kind, MessageKind::Message);
+    assert_eq!(message.as_string(), "hi");
+    assert_eq!(message.id, None);
+    assert_eq!(message.author, None);
+    assert_eq!(message.checksum, None);
+}
+
+#[tokio::test]
+async fn an_unknown_kind_does_not_end_the_stream() {
+    let newer = payload(&hello()).replacen("kind:ServerBroadcast", "kind:Shout", 1);
+    let mut bytes = framed(&newer);
+    bytes.extend(frame::encode(&hello(), MAX_FRAME_SIZE).unwrap());
+
+    let mut reader = FrameReader::new(&bytes[..], MAX_FRAME_SIZE);
+    let first = reader
+        .next_message()
+        .await
+        .expect("the newer frame did not read");
+    assert_eq!(first.kind, MessageKind::Unknown);
+    let second = reader.next_message().await.expect("the stream lost step");
+    assert_eq!(second.kind, MessageKind::ServerBroadcast);
+}
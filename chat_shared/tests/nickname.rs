@@ -0,0 +1,71 @@
+use chat_shared::{Config, NickError, User};
+
+#[test]
+fn empty_names_are_too_short() {
+    let config = Config::default();
+    assert_eq!(
+        User::validate_nickname("", &config),
+        Err(NickError::TooShort { min: 1 })
+    );
+}
+
+#[test]
+fn names_past_the_maximum_are_too_long() {
+    let config = Config::default();
+    let longest = "a".repeat(config.nick_max_len);
+    assert_eq!(User::validate_nickname(&longest, &config), Ok(()));
+    assert_eq!(
+        User::validate_nickname(&format!("{longest}a"), &config),
+        Err(NickError::TooLong { max: 32 })
+    );
+    // Counted in characters, so a name of wide ones is no shorter
+    let wide = "é".repeat(config.nick_max_len);
+    assert_eq!(User::validate_nickname(&wide, &config), Ok(()));
+}
+
+#[test]
+fn lengths_come_from_the_config() {
+    let config = Config {
+        nick_min_len: 3,
+        nick_max_len: 5,
+        ..Config::default()
+    };
+    assert_eq!(
+        User::validate_nickname("al", &config),
+        Err(NickError::TooShort { min: 3 })
+    );
+    assert_eq!(User::validate_nickname("alice", &config), Ok(()));
+    assert_eq!(
+        User::validate_nickname("alice2", &config),
+        Err(NickError::TooLong { max: 5 })
+    );
+}
+
+#[test]
+fn control_characters_are_refused() {
+    let config = Config::default();
+    for name in ["al\u{7}ice", "alice\n", "\u{1b}[31mred", "tab\tbed"] {
+        assert_eq!(
+            User::validate_nickname(name, &config),
+            Err(NickError::ControlCharacter),
+            "{name:?}"
+        );
+    }
+}
+
+#[test]
+fn surrounding_whitespace_and_other_symbols_are_refused() {
+    let config = Config::default();
+    assert_eq!(
+        User::validate_nickname(" alice", &config),
+        Err(NickError::SurroundingWhitespace)
+    );
+    assert_eq!(
+        User::validate_nickname("alice ", &config),
+        Err(NickError::SurroundingWhitespace)
+    );
+    assert_eq!(
+        User::validate_nickname("al@ice", &config),
+        Err(NickError::InvalidCharacter('@'))
+    );
+}
@@ -0,0 +1,161 @@
+use chat_shared::{
+    Message, Role,
+    message::MessageKind,
+    storage::{FileStorage, MemberRecord, MemoryStorage, Storage},
+};
+use std::{env, fs, path::PathBuf, process};
+
+// A log file of its own for each test, so they can run side by side
+struct TempLog(PathBuf);
+
+impl TempLog {
+    fn new(name: &str) -> Self {
+        let log = Self(env::temp_dir().join(format!("chat_storage_{name}_{}.log", process::id())));
+        log.remove();
+        log
+    }
+
+    fn open(&self, max_bytes: u64) -> FileStorage {
+        FileStorage::open(&self.0, max_bytes).expect("could not open the log")
+    }
+
+    fn remove(&self) {
+        for suffix in ["", ".1", ".members"] {
+            let mut file = self.0.as_os_str().to_owned();
+            file.push(suffix);
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+impl Drop for TempLog {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+fn message(id: u64) -> Message {
+    let mut message = Message::from_server(format!("message {id}"), MessageKind::Message);
+    message.id = Some(id);
+    message
+}
+
+fn member(nick_name: &str, role: Role, last_seen_secs: u64) -> MemberRecord {
+    MemberRecord {
+        nick_name: nick_name.to_string(),
+        role,
+        last_seen_secs,
+    }
+}
+
+fn ids(messages: &[Message]) -> Vec<u64> {
+    messages.iter().filter_map(|message| message.id).collect()
+}
+
+// What every backend has to do, whatever it keeps
+
+async fn stores_and_replaces_members(storage: &dyn Storage) {
+    assert_eq!(storage.get_member("alice").await.unwrap(), None);
+
+    storage
+        .put_member(member("alice", Role::Member, 1))
+        .await
+        .unwrap();
+    storage
+        .put_member(member("bob", Role::Member, 2))
+        .await
+        .unwrap();
+    storage
+        .put_member(member("alice", Role::Admin, 3))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        storage.get_member("alice").await.unwrap(),
+        Some(member("alice", Role::Admin, 3))
+    );
+    assert_eq!(
+        storage.get_member("bob").await.unwrap(),
+        Some(member("bob", Role::Member, 2))
+    );
+    assert_eq!(storage.get_member("carol").await.unwrap(), None);
+}
+
+async fn recent_messages_are_the_latest_oldest_first(storage: &dyn Storage) {
+    assert!(storage.recent_messages(10).await.unwrap().is_empty());
+    for id in 1..=5 {
+        storage.append_message(&message(id)).await.unwrap();
+    }
+
+    for limit in [0, 3, 10] {
+        let recent = ids(&storage.recent_messages(limit).await.unwrap());
+        assert!(recent.len() <= limit);
+        // Whatever is returned is the tail of what was appended, in order
+        let tail: Vec<u64> = (1..=5).skip(5 - recent.len()).collect();
+        assert_eq!(recent, tail);
+    }
+}
+
+#[tokio::test]
+async fn memory_storage_stores_and_replaces_members() {
+    stores_and_replaces_members(&MemoryStorage::new()).await;
+}
+
+#[tokio::test]
+async fn memory_storage_recent_messages_are_the_latest_oldest_first() {
+    recent_messages_are_the_latest_oldest_first(&MemoryStorage::new()).await;
+}
+
+#[tokio::test]
+async fn file_storage_stores_and_replaces_members() {
+    let log = TempLog::new("members");
+    stores_and_replaces_members(&log.open(u64::MAX)).await;
+}
+
+#[tokio::test]
+async fn file_storage_recent_messages_are_the_latest_oldest_first() {
+    let log = TempLog::new("recent");
+    recent_messages_are_the_latest_oldest_first(&log.open(u64::MAX)).await;
+}
+
+// What only a backend that outlives the server has to do
+
+#[tokio::test]
+async fn file_storage_keeps_everything_across_a_reopen() {
+    let log = TempLog::new("reopen");
+    let storage = log.open(u64::MAX);
+    for id in 1..=5 {
+        storage.append_message(&message(id)).await.unwrap();
+    }
+    storage
+        .put_member(member("alice", Role::Admin, 7))
+        .await
+        .unwrap();
+    drop(storage);
+
+    let storage = log.open(u64::MAX);
+    assert_eq!(ids(&storage.recent_messages(3).await.unwrap()), [3, 4, 5]);
+    assert_eq!(
+        ids(&storage.recent_messages(10).await.unwrap()),
+        [1, 2, 3, 4, 5]
+    );
+    assert_eq!(
+        storage.get_member("alice").await.unwrap(),
+        Some(member("alice", Role::Admin, 7))
+    );
+}
+
+#[tokio::test]
+async fn file_storage_reads_back_across_a_rotation() {
+    let log = TempLog::new("rotate");
+    // Small enough that a couple of messages fill the log
+    let storage = log.open(256);
+    for id in 1..=5 {
+        storage.append_message(&message(id)).await.unwrap();
+    }
+
+    let recent = ids(&storage.recent_messages(10).await.unwrap());
+    assert!(!recent.is_empty());
+    assert_eq!(recent.last(), Some(&5));
+    assert!(recent.windows(2).all(|pair| pair[1] == pair[0] + 1));
+}